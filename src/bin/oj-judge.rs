@@ -0,0 +1,125 @@
+//! Judge daemon claiming jobs from the queue in the database shared with `oj`
+//!
+//! Any number of judges may run next to a server started with `--threads 0`. Judges
+//! and servers judging on the same machine need their own sandboxes, given with
+//! `--sandbox-offset`, as sandbox ids are shared by the whole machine. A judge
+//! finishes the jobs it runs before shutting down, and jobs of a judge that dies are
+//! re-queued once their lease expires.
+//!
+//! With `--remote`, the judge runs on another machine and claims jobs from the server
+//! over HTTP instead, authenticating with `judge_secret`.
+
+use std::sync::Arc;
+
+use clap::Parser;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use oj::config::{Config, JudgeCliArgs, JudgeCliCommand};
+use oj::database as db;
+use oj::judge::{JudgeEnvironment, build_checker_cache, spawn_workers};
+use oj::queue::JobQueue;
+use oj::remote::RemoteJobs;
+use oj::sandbox::{ArtifactStore, CheckerCache, CompileCache};
+use oj::worker::LocalJobs;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = JudgeCliArgs::parse();
+    let log_level = if cli.verbose { "debug" } else { "info" };
+
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or(log_level));
+
+    if cli.judge.threads == 0 {
+        log::error!("The number of worker threads must not be 0");
+        std::process::exit(1);
+    }
+
+    let Config {
+        server: server_config,
+        problems: problem_config,
+        languages: language_config,
+        database: database_config,
+    } = cli.read_config()?;
+
+    let environment = match JudgeEnvironment::prepare(&cli.judge, &server_config, &language_config)
+    {
+        Ok(environment) => environment,
+        Err(e) => {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let doctor = cli.command == Some(JudgeCliCommand::Doctor);
+    if doctor || !cli.judge.skip_self_test {
+        environment.self_test(&language_config, doctor);
+    }
+
+    let compile_cache_size = server_config.compile_cache_size_mb.unwrap_or(512) * 1024 * 1024;
+    let compile_cache = Arc::new(CompileCache::build(compile_cache_size)?);
+    let artifact_store = Arc::new(ArtifactStore::build(server_config.artifacts.clone())?);
+    let shutdown_token = CancellationToken::new();
+
+    let mut workers = JoinSet::new();
+    match &cli.remote {
+        Some(server) => {
+            let Some(secret) = &server_config.judge_secret else {
+                log::error!("Remote judging requires judge_secret in the server config");
+                std::process::exit(1);
+            };
+            log::info!("Claiming jobs from {server}");
+            // Checkers come with the jobs, so they are compiled on first use
+            let source = Arc::new(RemoteJobs::build(
+                server,
+                secret,
+                server_config.queue.lease(),
+            )?);
+            spawn_workers(
+                &mut workers,
+                environment,
+                source,
+                Arc::new(CheckerCache::build()?),
+                compile_cache,
+                artifact_store,
+                shutdown_token.clone(),
+            )?;
+        }
+        None => {
+            let db_pool = db::init_db(&database_config).await?;
+            let checker_cache = build_checker_cache(&problem_config, &language_config)?;
+            let source = Arc::new(LocalJobs {
+                queue: Arc::new(JobQueue::new(db_pool, &server_config.queue)),
+                problems: Arc::new(problem_config),
+                languages: Arc::new(language_config),
+            });
+            spawn_workers(
+                &mut workers,
+                environment,
+                source,
+                Arc::new(checker_cache),
+                compile_cache,
+                artifact_store,
+                shutdown_token.clone(),
+            )?;
+        }
+    }
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {
+            log::info!("Ctrl-c received, finishing running jobs...");
+        }
+        Some(res_worker) = workers.join_next() => {
+            log::error!("A worker terminated unexpectedly: {res_worker:?}");
+        }
+    }
+
+    shutdown_token.cancel();
+    while let Some(res) = workers.join_next().await {
+        if let Err(e) = res {
+            log::error!("Worker handle finished with error: {e:?}");
+        }
+    }
+
+    log::info!("Shutdown complete");
+    Ok(())
+}
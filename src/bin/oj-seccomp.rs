@@ -0,0 +1,6 @@
+//! Wrapper running a contestant program under a seccomp policy, see `oj::sandbox::seccomp`
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(oj::sandbox::seccomp::wrapper_main(&args));
+}
@@ -1,32 +1,145 @@
-use clap::Parser;
-use serde::Deserialize;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::database::ImportMode;
 
 #[derive(Parser)]
-#[command(name = "oj", version = "0.1.0", about, long_about = None)]
+#[command(name = "oj", version = "0.1.0", long_about = None)]
+#[command(about = "Online judge server, judging jobs with its own workers")]
 pub struct CliArgs {
     /// Path to the configuration file
     #[arg(long = "config", short = 'c')]
     pub config_path: String,
 
+    /// Path of the SQLite database file, or a `sqlite:` or `postgres:` URL, overriding
+    /// `database.path` in the config
+    #[arg(long = "database", value_name = "PATH|URL")]
+    pub database: Option<String>,
+
     /// Whether to remove the existing database
     #[arg(long = "flush-data", short = 'f')]
     pub flush_data: bool,
 
-    /// Number of threads to judge concurrently
-    #[arg(short, long, default_value_t = 2)]
-    pub threads: u8,
+    /// Verbose logging
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    // Judging options, with `--threads 0` leaving all judging to `oj-judge`
+    #[command(flatten)]
+    pub judge: JudgeArgs,
+
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+/// Command line arguments of the `oj-judge` daemon
+#[derive(Parser)]
+#[command(name = "oj-judge", version = "0.1.0", long_about = None)]
+#[command(about = "Judge daemon claiming jobs from the queue shared with the oj server")]
+pub struct JudgeCliArgs {
+    /// Path to the configuration file, the same as the server's
+    #[arg(long = "config", short = 'c')]
+    pub config_path: String,
+
+    /// Path of the SQLite database file, or a `sqlite:` or `postgres:` URL, overriding
+    /// `database.path` in the config
+    #[arg(long = "database", value_name = "PATH|URL")]
+    pub database: Option<String>,
 
     /// Verbose logging
     #[arg(short, long)]
     pub verbose: bool,
+
+    #[command(flatten)]
+    pub judge: JudgeArgs,
+
+    /// Base URL of the oj server to claim jobs from over HTTP, instead of from the
+    /// shared database, e.g. `http://10.0.0.1:12345`
+    #[arg(long, value_name = "URL")]
+    pub remote: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<JudgeCliCommand>,
+}
+
+/// Options of the workers judging jobs from the queue
+#[derive(clap::Args)]
+pub struct JudgeArgs {
+    /// Number of threads to judge concurrently
+    #[arg(short, long, default_value_t = 2)]
+    pub threads: u8,
+
+    /// Number of sandbox ids to skip, so that the workers use sandboxes `offset + 1` to
+    /// `offset + threads`, for running several judging processes on one machine
+    #[arg(long, default_value_t = 0)]
+    pub sandbox_offset: u8,
+
+    /// Sandbox backend, overriding `server.sandbox_backend` in the config
+    #[arg(long, value_enum)]
+    pub sandbox_backend: Option<SandboxBackendKind>,
+
+    /// Skip the self-test of the judging environment at startup
+    #[arg(long)]
+    pub skip_self_test: bool,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum CliCommand {
+    /// Run the self-test of the judging environment, print its results and exit
+    Doctor,
+    /// Write a snapshot of the database to a file, safe while the server runs
+    Backup {
+        /// File to write the snapshot to, which must not exist
+        file: PathBuf,
+    },
+    /// Replace the database with a snapshot, with the server and judges stopped
+    Restore {
+        /// Snapshot written by `backup`
+        file: PathBuf,
+    },
+    /// Write users, jobs and problem metadata to a JSON archive
+    Export {
+        /// File to write the archive to, which must not exist
+        file: PathBuf,
+        /// Only export the jobs of this contest
+        #[arg(long)]
+        contest: Option<u32>,
+        /// Only export jobs created at or after this time, in RFC 3339
+        #[arg(long)]
+        from: Option<String>,
+        /// Only export jobs created at or before this time, in RFC 3339
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Load the users and jobs of an archive written by `export`
+    Import {
+        /// Archive written by `export`
+        file: PathBuf,
+        /// Whether to keep the existing users and jobs
+        #[arg(long, value_enum, default_value_t)]
+        mode: ImportMode,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum JudgeCliCommand {
+    /// Run the self-test of the judging environment, print its results and exit
+    Doctor,
 }
 
 impl CliArgs {
     /// Load the configuration from the specified file
     pub fn read_config(&self) -> std::io::Result<Config> {
-        let file = std::fs::File::open(&self.config_path)?;
-        let reader = std::io::BufReader::new(file);
-        serde_json::from_reader(reader).map_err(|e| e.into())
+        Config::read(&self.config_path, self.database.as_deref())
+    }
+}
+
+impl JudgeCliArgs {
+    /// Load the configuration from the specified file
+    pub fn read_config(&self) -> std::io::Result<Config> {
+        Config::read(&self.config_path, self.database.as_deref())
     }
 }
 
@@ -35,6 +148,62 @@ pub struct Config {
     pub server: ServerConfig,
     pub problems: ProblemConfig,
     pub languages: LanguageConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+}
+
+impl Config {
+    /// Reads the config file, with `database` overriding the path of the database
+    fn read(path: &str, database: Option<&str>) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let reader = std::io::BufReader::new(file);
+        let mut config: Self = serde_json::from_reader(reader)?;
+        if let Some(database) = database {
+            config.database.path = Some(database.to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// Location of the database and settings of its connection pools
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DatabaseConfig {
+    /// Path of the SQLite database file, or a `sqlite:` or `postgres:` URL, `oj.sqlite3`
+    /// in the user data directory if unset
+    pub path: Option<String>,
+    /// Maximum number of connections writing to the database, 1 if unset
+    pub max_connections: Option<u32>,
+    /// Maximum number of read-only connections serving queries, 4 if unset
+    pub max_read_connections: Option<u32>,
+    /// Milliseconds a connection waits for a lock held by another one before failing,
+    /// 2000 if unset
+    pub busy_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub journal_mode: JournalMode,
+}
+
+impl DatabaseConfig {
+    /// Whether the database is on a PostgreSQL server rather than in an SQLite file
+    pub fn is_postgres(&self) -> bool {
+        self.path.as_deref().is_some_and(|path| {
+            path.starts_with("postgres://") || path.starts_with("postgresql://")
+        })
+    }
+}
+
+/// Journal mode of the SQLite database, see the SQLite documentation of `journal_mode`
+///
+/// Readers only run alongside the writer in WAL mode, the other modes make them wait.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    #[default]
+    Wal,
+    Off,
 }
 
 #[derive(Deserialize, Debug)]
@@ -42,22 +211,202 @@ pub struct ServerConfig {
     pub bind_address: Option<String>,
     pub bind_port: Option<u16>,
     pub blocking: Option<bool>,
+    /// Bearer token required by admin endpoints, which are disabled without it
+    pub admin_token: Option<String>,
+    /// Maximum size of the compile cache in MiB, 0 disables it
+    pub compile_cache_size_mb: Option<u64>,
+    #[serde(default)]
+    pub artifacts: ArtifactRetention,
+    #[serde(default)]
+    pub sandbox_backend: SandboxBackendKind,
+    /// Pins each sandbox to CPU cores when set
+    pub cpu_pinning: Option<CpuPinning>,
+    /// Shared secret of remote judge nodes, which the judge endpoints are disabled without
+    pub judge_secret: Option<String>,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Takes snapshots of the database periodically when set
+    pub backup: Option<BackupConfig>,
+}
+
+/// Settings of the durable job queue shared by the server and the judges
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct QueueConfig {
+    /// Seconds a judge may hold a job without a heartbeat before it is re-queued,
+    /// 30 if unset
+    pub lease_secs: Option<u64>,
+    /// Number of jobs waiting to be judged above which new ones are rejected,
+    /// unlimited if unset
+    pub max_length: Option<u32>,
+    /// Seconds blocking submissions wait for their result before getting the job as
+    /// it is, unlimited if unset
+    pub blocking_timeout_secs: Option<u64>,
+}
+
+impl QueueConfig {
+    pub fn lease(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lease_secs.unwrap_or(30).max(1))
+    }
+}
+
+/// Periodic snapshots of the database, taken while the server runs
+#[derive(Deserialize, Debug, Clone)]
+pub struct BackupConfig {
+    /// Directory the snapshots are written to, named by the time they are taken
+    pub dir: String,
+    /// Minutes between two snapshots, 1440 (a day) if unset
+    pub interval_mins: Option<u64>,
+    /// Number of the latest snapshots kept, 7 if unset
+    pub keep: Option<usize>,
+}
+
+/// Limits on how fast submissions are accepted, those left unset don't apply
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    pub per_user: Option<TokenBucketConfig>,
+    pub per_ip: Option<TokenBucketConfig>,
+    /// Header in which a trusted reverse proxy passes the client IP, e.g.
+    /// `X-Forwarded-For`, the peer address being used if unset
+    pub client_ip_header: Option<String>,
+}
+
+/// A token bucket allowing `submissions` per `window_secs` on average
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct TokenBucketConfig {
+    pub submissions: u32,
+    pub window_secs: u64,
+    /// Extra submissions allowed at once on top of `submissions`, 0 if unset
+    #[serde(default)]
+    pub burst: u32,
+}
+
+/// Mechanism used to isolate contestant programs
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxBackendKind {
+    /// Run programs in isolate boxes with cgroups
+    #[default]
+    Isolate,
+    /// Run programs as plain processes with rlimits only
+    ///
+    /// INSECURE: meant for development and tests on machines without isolate.
+    Process,
+}
+
+/// Retention policy of the judging artifacts kept for each job
+///
+/// The most recent jobs are always kept first. A limit set to 0 doesn't apply.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ArtifactRetention {
+    /// Maximum number of jobs whose artifacts are kept, 1000 if unset
+    pub keep_last: Option<usize>,
+    /// Maximum age of artifacts in hours, 720 (30 days) if unset
+    pub max_age_hours: Option<u64>,
+    /// Maximum total size of all artifacts in MiB, 1024 if unset
+    pub max_size_mb: Option<u64>,
+    /// Whether to keep artifacts of failed cases only
+    #[serde(default)]
+    pub failed_only: bool,
+}
+
+impl ArtifactRetention {
+    pub fn keep_last(&self) -> Option<usize> {
+        Some(self.keep_last.unwrap_or(1000)).filter(|&n| n > 0)
+    }
+
+    pub fn max_age(&self) -> Option<std::time::Duration> {
+        Some(self.max_age_hours.unwrap_or(720))
+            .filter(|&hours| hours > 0)
+            .map(|hours| std::time::Duration::from_secs(hours * 3600))
+    }
+
+    /// Maximum total size of all artifacts in bytes
+    pub fn max_size(&self) -> Option<u64> {
+        Some(self.max_size_mb.unwrap_or(1024))
+            .filter(|&mb| mb > 0)
+            .map(|mb| mb * 1024 * 1024)
+    }
+}
+
+/// Assignment of sandboxes to CPU cores, for less noisy timing
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CpuPinning {
+    /// CPU set of each worker, starting from the first, detected from the available
+    /// cores if unset
+    pub cpus: Option<Vec<Vec<usize>>>,
+    /// Whether to refuse starting more workers than there are dedicated cores
+    #[serde(default)]
+    pub strict: bool,
 }
 
 pub type ProblemConfig = Vec<OneProblemConfig>;
 pub type LanguageConfig = Vec<OneLanguageConfig>;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OneProblemConfig {
     pub id: u32,
     pub name: String,
     #[serde(flatten)]
     pub judge_type: JudgeType,
-    // pub misc: Option<serde_json::Value>,
+    #[serde(default)]
+    pub misc: ProblemMisc,
     pub cases: Vec<OneCaseConfig>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Extra problem settings that only apply to some judge types
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ProblemMisc {
+    /// Absolute and relative tolerance used by the `float` judge type
+    pub epsilon: Option<f64>,
+    /// Whether the `token` judge type ignores letter case
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// How much detail to put in case info on Wrong Answer for line-based judge types
+    #[serde(default)]
+    pub wrong_answer_info: WrongAnswerInfo,
+    /// Command of the trusted interactor for the `interactive` judge type
+    ///
+    /// `%INPUT%` and `%ANSWER%` are replaced with the case input and answer files.
+    pub interactor: Option<Vec<String>>,
+    /// Command of the special judge for the `spj` judge type
+    ///
+    /// `%OUTPUT%`, `%ANSWER%` and `%INPUT%` are replaced with the program output,
+    /// the case answer file and the case input file.
+    pub special_judge: Option<Vec<String>>,
+    /// Checker compiled by the server, used as the special judge or interactor
+    pub checker: Option<CheckerSource>,
+}
+
+/// Source of a checker program that the server compiles and caches
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CheckerSource {
+    /// Path to the checker source file
+    pub source: String,
+    /// Name of the language config used to compile it
+    pub language: String,
+    /// Arguments for the compiled checker, with the same placeholders as the
+    /// command it replaces. Defaults to `["%OUTPUT%", "%ANSWER%"]` for `spj`
+    /// and `["%INPUT%", "%ANSWER%"]` for `interactive`.
+    pub args: Option<Vec<String>>,
+}
+
+/// Detail level of Wrong Answer diagnostics for `standard` and `strict` problems
+///
+/// Defaults to `None`, so that nothing about hidden test data leaks to contestants.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WrongAnswerInfo {
+    #[default]
+    None,
+    /// Report the first mismatching line number, or whether output is too short/long
+    Line,
+    /// Additionally report truncated expected and actual snippets of that line
+    Snippet,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OneCaseConfig {
     pub score: f64,
     pub input_file: String,
@@ -66,7 +415,7 @@ pub struct OneCaseConfig {
     pub memory_limit: KiloByte,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct MicroSecond(pub u32);
 
 #[derive(Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
@@ -84,21 +433,37 @@ impl From<Second> for MicroSecond {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct KiloByte(pub u32);
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OneLanguageConfig {
     pub name: String,
     pub file_name: String,
     pub command: Vec<String>,
+    /// Syscall filter applied to programs of this language, none by default
+    #[serde(default)]
+    pub seccomp: Option<SeccompPolicy>,
+}
+
+/// Syscall allowlist enforced on contestant programs with seccomp
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompPolicy {
+    /// Strict allowlist for natively compiled programs, such as C, C++ and Rust
+    Compiled,
+    /// Looser allowlist for interpreters and VMs, which also spawn threads and processes
+    Interpreter,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum JudgeType {
     Standard,
     Strict,
+    Float,
+    Token,
+    Interactive,
     Spj,
     DynamicRanking,
 }
@@ -1,760 +1,487 @@
+mod archive;
+mod backup;
+mod migrations;
+mod postgres;
+mod sqlite;
+
 use std::collections::HashMap;
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use actix_web::web;
-use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
-use sqlx::{QueryBuilder, Sqlite};
+use async_trait::async_trait;
 
-use crate::create_timestamp;
+pub use archive::{
+    ARCHIVE_VERSION, Archive, ContestSummary, ExportFilter, ImportMode, ImportOutcome,
+    ImportSummary, JobImport, export_archive, export_database, import_archive, import_database,
+};
+pub use backup::{backup_database, restore_database, run_scheduled_backups};
+pub use migrations::SCHEMA_VERSION;
+pub use postgres::PgStorage;
+pub use sqlite::{SqliteStorage, get_db_path};
+
+use crate::config::DatabaseConfig;
+use crate::queue::{JobPriority, QueuedJob, WorkerStatus};
+use crate::rate_limit::TokenBucket;
 use crate::routes::{
     CaseResult, JobRecord, JobSubmission, JobsQueryParams, RanklistEntry, User, UserScore,
 };
 
-const DATABASE_NAME: &str = "oj.sqlite3";
-
-pub fn get_db_path() -> PathBuf {
-    use directories::ProjectDirs;
-
-    let proj_dirs = ProjectDirs::from("", "", "oj").expect("Unable to find user directory");
-    let data_dir = proj_dirs.data_local_dir();
-
-    fs::create_dir_all(data_dir).expect("Failed to create local data dir");
-
-    data_dir.join(DATABASE_NAME)
-}
-
-pub async fn init_db(db_path: impl AsRef<Path>) -> sqlx::Result<SqlitePool> {
-    let db_url = format!("sqlite://{}?mode=rwc", db_path.as_ref().display()); // rwc = read/write/create
-    let db_pool = SqlitePoolOptions::new()
-        .max_connections(1)
-        .min_connections(0) // Allow pool to shrink when idle
-        .connect(&db_url)
-        .await?;
-
-    // Execute PRAGMA statements first (these cannot be run inside a transaction)
-    for pragma_sql in &[
-        "PRAGMA foreign_keys = ON;",
-        "PRAGMA busy_timeout = 2000;", // 2 seconds timeout for lock contention
-        "PRAGMA journal_mode = WAL;",  // Write-Ahead Logging for better concurrency
-        "PRAGMA synchronous = NORMAL;", // Balance between safety and performance
-    ] {
-        sqlx::query(pragma_sql).execute(&db_pool).await?;
-    }
-
-    // Use a transaction for table creation and data initialization
-    let mut tx = db_pool.begin().await?;
-
-    for sql in &[
-        r"
-        CREATE TABLE IF NOT EXISTS users (
-            id            INTEGER PRIMARY KEY,
-            name          TEXT    NOT NULL UNIQUE
-        );",
-        r"
-        CREATE TABLE IF NOT EXISTS jobs (
-            pk            INTEGER  PRIMARY KEY,
-            id            INTEGER  GENERATED ALWAYS AS (pk - 1) STORED UNIQUE,
-            created_time  TEXT     NOT NULL,
-            updated_time  TEXT     NOT NULL,
-            user_id       INTEGER  NOT NULL,
-            contest_id    INTEGER  NOT NULL,
-            problem_id    INTEGER  NOT NULL,
-            source_code   TEXT     NOT NULL,
-            language      TEXT     NOT NULL,
-            state         TEXT     NOT NULL,
-            result        TEXT     NOT NULL,
-            score         REAL     NOT NULL,
-            FOREIGN KEY (user_id)  REFERENCES users (id)
-        );",
-        r"
-        CREATE TABLE IF NOT EXISTS job_case (
-            job_id         INTEGER      NOT NULL,
-            case_index     INTEGER      NOT NULL,
-            result         TEXT         NOT NULL,
-            time_us        INTEGER      NOT NULL,
-            memory_kb      INTEGER      NOT NULL,
-            info           TEXT         DEFAULT '',
-            PRIMARY KEY (job_id, case_index),
-            FOREIGN KEY (job_id)  REFERENCES jobs (id)
-        );",
-        "INSERT OR IGNORE INTO users (id, name) VALUES (0, 'root');",
-    ] {
-        sqlx::query(sql).execute(tx.as_mut()).await?;
-    }
-
-    tx.commit().await?;
+/// Read-only connections used if `max_read_connections` is unset
+const DEFAULT_READ_CONNECTIONS: u32 = 4;
 
-    log::info!("Initialized database at {}", db_path.as_ref().display());
-
-    Ok(db_pool)
-}
-
-pub fn remove_db(db_path: impl AsRef<Path>) {
-    // Remove WAL and SHM files (ignore errors as they might not exist)
-    let wal_path = format!("{}-wal", db_path.as_ref().display());
-    let shm_path = format!("{}-shm", db_path.as_ref().display());
-    let _ = fs::remove_file(wal_path);
-    let _ = fs::remove_file(shm_path);
-
-    // Remove main database file
-    if let Err(e) = std::fs::remove_file(&db_path) {
-        log::warn!(
-            "Unable to remove database at {}: {e}",
-            db_path.as_ref().display()
-        );
-    } else {
-        log::info!("Removed database at {}", db_path.as_ref().display());
-    }
-}
-
-/// Creates a new job entry in the database along with its associated test cases.
-///
-/// * `len` - The number of cases, including compilation, to create for this job.
+/// Persistence of users, jobs and the judging queue
 ///
-/// # Errors
-///
-/// This function will return an `Err` in the following cases:
-///
-/// - If the database connection pool cannot begin a transaction.
-/// - If the insertion of the job record into the `jobs` table fails (e.g., due to constraint violations).
-/// - If the insertion of any of the associated test cases into the `job_case` table fails.
-/// - If committing the transaction fails.
-pub async fn create_job(
-    body: &web::Json<JobSubmission>,
-    pool: Arc<SqlitePool>,
-    len: u32,
-) -> sqlx::Result<u32> {
-    let now = create_timestamp();
-
-    // Use a transaction for better error handling and potential future batch operations
-    let mut tx = pool.begin().await?;
-
-    let result = sqlx::query!(
-        r#"
-        INSERT INTO jobs (user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time)
-        VALUES (?, ?, ?, ?, ?, 'Queueing', 'Waiting', 0.0, ?, ?)
-        "#,
-        body.user_id,
-        body.contest_id,
-        body.problem_id,
-        body.source_code,
-        body.language,
-        now,
-        now
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    let pk = result.last_insert_rowid() as u32;
-    let job_id = pk - 1; // Since id is generated as pk - 1
-
-    for i in 0..len {
-        sqlx::query!(
-            r#"
-            INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb)
-            VALUES (?, ?, 'Waiting', 0, 0)
-            "#,
-            job_id,
-            i
-        )
-        .execute(tx.as_mut())
-        .await?;
-    }
-
-    tx.commit().await?;
-    Ok(job_id)
-}
-
-pub async fn find_job(id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<bool> {
-    let result = sqlx::query!(
-        r#"
-        SELECT 1 as "exists_flag: i32" FROM jobs WHERE id = ?
-        "#,
-        id
-    )
-    .fetch_optional(pool.as_ref())
-    .await?;
-
-    Ok(result.is_some())
-}
+/// Implemented for SQLite, the default, and PostgreSQL, with the same schema and
+/// semantics. Which one is used depends on the scheme of `database.path`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Creates a new job entry in the database along with its associated test cases.
+    ///
+    /// * `len` - The number of cases, including compilation, to create for this job.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `Err` in the following cases:
+    ///
+    /// - If the database connection pool cannot begin a transaction.
+    /// - If the insertion of the job record into the `jobs` table fails (e.g., due to constraint violations).
+    /// - If the insertion of any of the associated test cases into the `job_case` table fails.
+    /// - If committing the transaction fails.
+    async fn create_job(&self, body: &JobSubmission, len: u32) -> sqlx::Result<u32>;
+
+    /// Creates a job like `create_job` and queues it like `enqueue_job`, in one
+    /// transaction
+    ///
+    /// Nothing is created if `max_queued` jobs are already waiting to be claimed, then
+    /// `None` is returned instead of the job id.
+    async fn create_queued_job(
+        &self,
+        body: &JobSubmission,
+        len: u32,
+        priority: u32,
+        max_queued: Option<u32>,
+    ) -> sqlx::Result<Option<u32>>;
+
+    async fn find_job(&self, id: u32) -> sqlx::Result<bool>;
+
+    async fn find_user(&self, id: u32) -> sqlx::Result<bool>;
+
+    async fn fetch_job(&self, id: u32) -> sqlx::Result<JobRecord>;
+
+    async fn update_job_to_running(&self, id: u32) -> sqlx::Result<()>;
+
+    async fn update_job_to_canceled(&self, id: u32) -> sqlx::Result<()>;
+
+    /// Returns the number of cases reverted
+    async fn revert_job_to_queueing(&self, id: u32) -> sqlx::Result<usize>;
+
+    /// Reverts a job like `revert_job_to_queueing` and queues it like `enqueue_job`, in
+    /// one transaction
+    ///
+    /// Nothing changes if `max_queued` jobs are already waiting to be claimed, then
+    /// `None` is returned instead of the number of cases reverted.
+    async fn requeue_job(
+        &self,
+        id: u32,
+        priority: u32,
+        user_id: u32,
+        max_queued: Option<u32>,
+    ) -> sqlx::Result<Option<usize>>;
+
+    async fn save_result(&self, id: u32, result: &JobRecord) -> sqlx::Result<()>;
+
+    /// Appends a job to the judging queue at a priority level, lower levels being claimed
+    /// first
+    ///
+    /// Within a level, jobs are served in rounds: a user's job joins the round after the
+    /// user's previous job, but never one that has already passed. So every user with
+    /// queued jobs gets one judged per round, however many they submitted.
+    async fn enqueue_job(&self, id: u32, priority: u32, user_id: u32) -> sqlx::Result<()>;
+
+    /// Leases the next unleased job in the queue to `owner`, returning its id
+    ///
+    /// Nothing is leased while the queue is paused. Concurrent judges never claim the
+    /// same job.
+    async fn claim_job(&self, owner: &str, lease: Duration) -> sqlx::Result<Option<u32>>;
+
+    /// 1-based position of a job among the unleased jobs in claiming order, if it is one
+    async fn queue_position(&self, id: u32) -> sqlx::Result<Option<u32>>;
+
+    /// Extends the lease of a job, returning whether `owner` still holds it
+    async fn renew_lease(&self, id: u32, owner: &str, lease: Duration) -> sqlx::Result<bool>;
+
+    /// Removes a job leased by `owner` from the queue and saves its result at once,
+    /// returning whether it still held the lease
+    ///
+    /// Without a result, the job is marked as a System Error, so that it isn't left
+    /// Queueing or Running while no longer queued. Nothing changes if the lease is lost.
+    async fn finish_job(
+        &self,
+        id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> sqlx::Result<bool>;
+
+    /// Saves the result of one case of a job leased by `owner`, returning whether it
+    /// still held the lease
+    async fn save_case_result(&self, id: u32, owner: &str, case: &CaseResult)
+    -> sqlx::Result<bool>;
+
+    /// Removes a job from the queue unless a judge has claimed it, returning whether it did
+    async fn dequeue_unclaimed_job(&self, id: u32) -> sqlx::Result<bool>;
+
+    /// Removes all jobs that no judge has claimed from the queue, returning their ids
+    async fn dequeue_unclaimed_jobs(&self) -> sqlx::Result<Vec<u32>>;
+
+    async fn count_unclaimed_jobs(&self) -> sqlx::Result<u32>;
+
+    /// Unleased jobs in the queue, in the order they will be claimed
+    async fn fetch_queued_jobs(&self) -> sqlx::Result<Vec<QueuedJob>>;
+
+    /// Releases the jobs whose lease has expired back to the queue and reverts them to
+    /// Queueing, returning their ids
+    async fn requeue_expired_jobs(&self) -> sqlx::Result<Vec<u32>>;
+
+    /// Records that a worker is judging a job, or is idle with `job_id` unset
+    ///
+    /// Workers start each job at case 0, the compilation.
+    async fn update_worker(&self, name: &str, job_id: Option<u32>) -> sqlx::Result<()>;
+
+    /// Records that a worker is idle, unless it already was and has been seen since
+    /// `seen_after` (Unix time in milliseconds)
+    ///
+    /// Idle workers poll the queue often, this keeps them from writing on each poll.
+    async fn mark_worker_idle(&self, name: &str, seen_after: i64) -> sqlx::Result<()>;
+
+    /// Records that a worker has judged case `case_index` of its job and moved on to the
+    /// next one, if any
+    async fn advance_worker(&self, name: &str, job_id: u32, case_index: u32) -> sqlx::Result<()>;
+
+    /// Records that a worker is alive
+    async fn touch_worker(&self, name: &str) -> sqlx::Result<()>;
+
+    /// All workers that ever claimed from the queue, those not seen since `seen_after`
+    /// (Unix time in milliseconds) being reported Offline
+    async fn fetch_workers(&self, seen_after: i64) -> sqlx::Result<Vec<WorkerStatus>>;
+
+    /// Pauses or resumes the claiming of jobs from the queue
+    async fn set_queue_paused(&self, paused: bool) -> sqlx::Result<()>;
+
+    async fn queue_paused(&self) -> sqlx::Result<bool>;
+
+    /// Refills token buckets of the rate limiter up to `now` (Unix time in milliseconds)
+    /// and takes a token from each of them if all hold a whole one
+    ///
+    /// Returns the tokens each bucket held before any was taken. Buckets are created
+    /// full, and forgotten once full again.
+    async fn take_rate_tokens(&self, buckets: &[TokenBucket], now: i64) -> sqlx::Result<Vec<f64>>;
+
+    async fn fetch_jobs_by_query(&self, query: &JobsQueryParams) -> sqlx::Result<Vec<JobRecord>>;
+
+    /// Get all users from the database
+    async fn get_users(&self) -> sqlx::Result<Vec<User>>;
+
+    /// Check if a user name already exists for a different user ID
+    async fn user_name_exists(&self, name: &str, exclude_id: Option<u32>) -> sqlx::Result<bool>;
+
+    /// Create a new user with auto-generated ID
+    async fn create_user(&self, name: &str) -> sqlx::Result<User>;
+
+    /// Update an existing user
+    async fn update_user(&self, id: u32, name: &str) -> sqlx::Result<User>;
+
+    /// Get all jobs for a specific user
+    async fn fetch_user_jobs(&self, user_id: u32) -> sqlx::Result<Vec<JobRecord>>;
+
+    /// Inserts users and jobs with their ids, times and cases as they are, in one
+    /// transaction
+    ///
+    /// With `clear`, all users, jobs and queued jobs are removed first, so that job ids
+    /// start over. Users whose id or name is taken are skipped, and so are jobs whose id
+    /// is taken or whose user is not present with the id and name given in `users`.
+    /// The jobs are not queued. New jobs get ids after the largest one.
+    async fn import_data(
+        &self,
+        users: &[User],
+        jobs: &[JobRecord],
+        clear: bool,
+    ) -> sqlx::Result<ImportOutcome>;
+
+    /// Writes a consistent snapshot of the database to `file`, which must not exist
+    async fn backup(&self, file: &Path) -> anyhow::Result<()>;
+
+    /// Closes all connections, waiting for running queries
+    async fn close(&self);
+
+    /// Get global ranklist (contest_id = 0)
+    async fn get_global_ranklist(
+        &self,
+        scoring_rule: Option<String>,
+        tie_breaker: Option<String>,
+        problems: Arc<crate::config::ProblemConfig>,
+    ) -> sqlx::Result<Vec<RanklistEntry>> {
+        let scoring_rule = scoring_rule.unwrap_or_else(|| "latest".to_string());
+        let tie_breaker = tie_breaker.unwrap_or_default();
+
+        // Get all users
+        let users = self.get_users().await?;
+
+        // Get all problem IDs from configuration (sorted)
+        let mut problem_ids: Vec<u32> = problems.iter().map(|p| p.id).collect();
+        problem_ids.sort();
+
+        // Calculate scores for each user
+        let mut user_scores = Vec::new();
+
+        for user in users {
+            let mut user_score = UserScore {
+                user_id: user.id,
+                user_name: user.name.clone(),
+                problem_scores: HashMap::new(),
+                total_score: 0.0,
+                latest_submission_time: None,
+                submission_count: 0,
+            };
+
+            // Initialize all problem scores to 0
+            for problem_id in &problem_ids {
+                user_score.problem_scores.insert(*problem_id, 0.0);
+            }
 
-pub async fn find_user(id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<bool> {
-    let result = sqlx::query!(
-        r#"
-        SELECT 1 as "exists_flag: i32" FROM users WHERE id = ?
-        "#,
-        id
-    )
-    .fetch_optional(pool.as_ref())
-    .await?;
-
-    Ok(result.is_some())
-}
+            // Get user's job submission data
+            let jobs = self.fetch_user_jobs(user.id).await?;
+            user_score.submission_count = jobs.len() as u32;
+
+            // Calculate score and the scoring-used submission time for each problem based on scoring rule
+            for problem_id in &problem_ids {
+                let problem_jobs: Vec<_> = jobs
+                    .iter()
+                    .filter(|job| job.submission.problem_id == *problem_id)
+                    .collect();
+
+                if !problem_jobs.is_empty() {
+                    // Choose the job used for scoring for this problem, and remember its time
+                    let chosen_job = match scoring_rule.as_str() {
+                        "latest" => {
+                            // Use the latest submission
+                            problem_jobs
+                                .iter()
+                                .max_by_key(|job| &job.created_time)
+                                .copied()
+                        }
+                        "highest" => {
+                            // Use the highest score, with earliest submission time as tie breaker
+                            problem_jobs
+                                .iter()
+                                .max_by(|a, b| {
+                                    a.score
+                                        .partial_cmp(&b.score)
+                                        .unwrap_or(std::cmp::Ordering::Equal)
+                                        // If scores equal, earlier created_time is better
+                                        .then_with(|| b.created_time.cmp(&a.created_time))
+                                })
+                                .copied()
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(job) = chosen_job {
+                        user_score.problem_scores.insert(*problem_id, job.score);
+
+                        // Update the user's latest scoring-used submission time
+                        if user_score
+                            .latest_submission_time
+                            .as_ref()
+                            .is_none_or(|t| t < &job.created_time)
+                        {
+                            user_score.latest_submission_time = Some(job.created_time.clone());
+                        }
+                    }
+                }
+            }
 
-pub async fn fetch_job(id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<JobRecord> {
-    log::debug!("Trying to fetch job {id} full record from database");
-    let job_data = sqlx::query!(
-        r#"
-        SELECT user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time
-        FROM jobs
-        WHERE id = ?
-        "#,
-        id
-    )
-    .fetch_one(pool.as_ref())
-    .await?;
-
-    let submission = JobSubmission {
-        user_id: job_data.user_id as u32,
-        contest_id: job_data.contest_id as u32,
-        problem_id: job_data.problem_id as u32,
-        source_code: job_data.source_code,
-        language: job_data.language,
-    };
+            // Calculate total score
+            user_score.total_score = user_score.problem_scores.values().sum();
+            user_scores.push(user_score);
+        }
 
-    // Fetch case results
-    let case_data = sqlx::query!(
-        r#"
-        SELECT case_index, result, time_us, memory_kb, info
-        FROM job_case
-        WHERE job_id = ?
-        ORDER BY case_index
-        "#,
-        id
-    )
-    .fetch_all(pool.as_ref())
-    .await?;
-
-    // Pre-allocate the cases vector with the exact size needed
-    let mut cases = Vec::with_capacity(case_data.len());
-    for case in case_data {
-        cases.push(CaseResult {
-            id: case.case_index as u32,
-            result: case.result,
-            time: case.time_us as u32,
-            memory: case.memory_kb as u32, // memory in KB
-            info: case.info.unwrap_or_default(),
+        // Sort users based on scores and tie breaker
+        user_scores.sort_unstable_by(|a, b| {
+            // First sort by total score (descending)
+            match b
+                .total_score
+                .partial_cmp(&a.total_score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+            {
+                std::cmp::Ordering::Equal => {
+                    // If scores are equal, apply tie breaker
+                    match tie_breaker.as_str() {
+                        "submission_time" => {
+                            // Earlier latest submission time is better
+                            match (&a.latest_submission_time, &b.latest_submission_time) {
+                                (Some(a_time), Some(b_time)) => a_time.cmp(b_time),
+                                (Some(_), None) => std::cmp::Ordering::Less, // Has submission is better than no submission
+                                (None, Some(_)) => std::cmp::Ordering::Greater,
+                                _ => std::cmp::Ordering::Equal, // Both have no submissions
+                            }
+                        }
+                        "submission_count" => {
+                            // Fewer submissions is better
+                            a.submission_count.cmp(&b.submission_count)
+                        }
+                        "user_id" => {
+                            // Smaller user_id is better
+                            a.user_id.cmp(&b.user_id)
+                        }
+                        _ => std::cmp::Ordering::Equal, // Default to none
+                    }
+                }
+                other => other,
+            }
         });
-    }
-
-    log::debug!("Fetched job {id} full record from database");
-    Ok(JobRecord {
-        id,
-        created_time: job_data.created_time,
-        updated_time: job_data.updated_time,
-        submission,
-        state: job_data.state,
-        result: job_data.result,
-        score: job_data.score,
-        cases,
-    })
-}
-
-pub async fn update_job_to_running(id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<()> {
-    let now = create_timestamp();
-    let mut tx = pool.begin().await?;
-
-    // Update job state and result to Running
-    sqlx::query!(
-        r#"
-        UPDATE jobs 
-        SET state = 'Running', result = 'Running', updated_time = ?
-        WHERE id = ?
-        "#,
-        now,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    // Update case 0 result to Running
-    sqlx::query!(
-        r#"
-        UPDATE job_case 
-        SET result = 'Running'
-        WHERE job_id = ? AND case_index = 0
-        "#,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    tx.commit().await?;
-    Ok(())
-}
-
-pub async fn update_job_to_canceled(id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<()> {
-    let now = create_timestamp();
-    let mut tx = pool.begin().await?;
-
-    // Update job state and result to Canceled
-    sqlx::query!(
-        r#"
-        UPDATE jobs 
-        SET state = 'Canceled', result = 'Skipped', updated_time = ?
-        WHERE id = ?
-        "#,
-        now,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    // Update all case results to Canceled
-    sqlx::query!(
-        r#"
-        UPDATE job_case 
-        SET result = 'Skipped'
-        WHERE job_id = ?
-        "#,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    tx.commit().await?;
-    Ok(())
-}
-
-/// Returns the number of cases reverted
-pub async fn revert_job_to_queueing(id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<usize> {
-    let now = create_timestamp();
-    let mut tx = pool.begin().await?;
-
-    // Revert job state, result and score
-    sqlx::query!(
-        r#"
-        UPDATE jobs 
-        SET state = 'Queueing', result = 'Waiting', score = 0.0, updated_time = ?
-        WHERE id = ?
-        "#,
-        now,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    // Revert each case
-    let reverted_cases = sqlx::query!(
-        r#"
-        UPDATE job_case 
-        SET result = 'Waiting', time_us = 0, memory_kb = 0, info = ''
-        WHERE job_id = ?
-        "#,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?
-    .rows_affected();
-
-    tx.commit().await?;
-    Ok(reverted_cases as usize)
-}
 
-pub async fn save_result(id: u32, pool: Arc<SqlitePool>, result: &JobRecord) -> sqlx::Result<()> {
-    let now = create_timestamp();
-    let mut tx = pool.begin().await?;
-
-    // Update job record
-    sqlx::query!(
-        r#"
-        UPDATE jobs 
-        SET state = ?, result = ?, score = ?, updated_time = ?
-        WHERE id = ?
-        "#,
-        result.state,
-        result.result,
-        result.score,
-        now,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    // Delete existing case results
-    sqlx::query!(
-        r#"
-        DELETE FROM job_case WHERE job_id = ?
-        "#,
-        id
-    )
-    .execute(tx.as_mut())
-    .await?;
-
-    // Insert new case results
-    for case in &result.cases {
-        sqlx::query!(
-            r#"
-            INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb, info)
-            VALUES (?, ?, ?, ?, ?, ?)
-            "#,
-            id,
-            case.id,
-            case.result,
-            case.time,
-            case.memory, // memory already in KB
-            case.info
-        )
-        .execute(tx.as_mut())
-        .await?;
-    }
+        // Calculate ranks and build result
+        let mut result = Vec::new();
+        let mut current_rank = 1;
 
-    tx.commit().await?;
-    Ok(())
-}
+        for (index, user_score) in user_scores.iter().enumerate() {
+            // Update rank if this user has different score from previous
+            if index > 0 {
+                let prev_user = &user_scores[index - 1];
+                if user_score.total_score < prev_user.total_score {
+                    current_rank = index as u32 + 1;
+                }
+                // If scores are equal, check tie breaker
+                else {
+                    let tie_broken = match tie_breaker.as_str() {
+                        "submission_time" => {
+                            match (
+                                &user_score.latest_submission_time,
+                                &prev_user.latest_submission_time,
+                            ) {
+                                (Some(curr_time), Some(prev_time)) => curr_time != prev_time,
+                                (None, None) => false,
+                                _ => true, // One has submission, the other doesn't
+                            }
+                        }
+                        "submission_count" => {
+                            user_score.submission_count != prev_user.submission_count
+                        }
+                        "user_id" => user_score.user_id != prev_user.user_id,
+                        _ => false,
+                    };
 
-pub async fn fetch_jobs_by_query(
-    query: web::Query<JobsQueryParams>,
-    pool: Arc<SqlitePool>,
-) -> sqlx::Result<Vec<JobRecord>> {
-    let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT id FROM jobs WHERE 1=1");
+                    if tie_broken {
+                        current_rank = index as u32 + 1;
+                    }
+                }
+            }
 
-    if let Some(user_id) = query.user_id {
-        qb.push(" AND user_id = ").push_bind(user_id);
-    }
-    if let Some(ref user_name) = query.user_name {
-        qb.push(" AND user_id IN (SELECT id FROM users WHERE name = ")
-            .push_bind(user_name)
-            .push(")");
-    }
-    if let Some(contest_id) = query.contest_id {
-        qb.push(" AND contest_id = ").push_bind(contest_id);
-    }
-    if let Some(problem_id) = query.problem_id {
-        qb.push(" AND problem_id = ").push_bind(problem_id);
-    }
-    if let Some(ref language) = query.language {
-        qb.push(" AND language = ").push_bind(language);
-    }
-    if let Some(ref from) = query.from {
-        qb.push(" AND created_time >= ").push_bind(from);
-    }
-    if let Some(ref to) = query.to {
-        qb.push(" AND created_time <= ").push_bind(to);
-    }
-    if let Some(ref state) = query.state {
-        qb.push(" AND state = ").push_bind(state);
-    }
-    if let Some(ref result) = query.result {
-        qb.push(" AND result = ").push_bind(result);
-    }
-    qb.push(" ORDER BY created_time");
+            // Build scores array in problem_id order
+            let scores: Vec<f64> = problem_ids
+                .iter()
+                .map(|problem_id| {
+                    user_score
+                        .problem_scores
+                        .get(problem_id)
+                        .copied()
+                        .unwrap_or(0.0)
+                })
+                .collect();
 
-    let job_ids = qb
-        .build_query_as::<(u32,)>()
-        .fetch_all(pool.as_ref())
-        .await?;
-    let mut jobs = Vec::new();
+            result.push(RanklistEntry {
+                user: User {
+                    id: user_score.user_id,
+                    name: user_score.user_name.clone(),
+                },
+                rank: current_rank,
+                scores,
+            });
+        }
 
-    for (id,) in job_ids {
-        jobs.push(fetch_job(id, pool.clone()).await.unwrap());
+        Ok(result)
     }
-    Ok(jobs)
-}
-
-/// Get all users from the database
-pub async fn get_users(pool: Arc<SqlitePool>) -> sqlx::Result<Vec<User>> {
-    let users = sqlx::query_as!(
-        User,
-        r#"
-        SELECT id as "id: u32", name
-        FROM users
-        ORDER BY id
-        "#
-    )
-    .fetch_all(pool.as_ref())
-    .await?;
-
-    Ok(users)
 }
 
-/// Check if a user name already exists for a different user ID
-pub async fn user_name_exists(
-    name: &str,
-    exclude_id: Option<u32>,
-    pool: Arc<SqlitePool>,
-) -> sqlx::Result<bool> {
-    if let Some(id) = exclude_id {
-        let result = sqlx::query!(
-            r#"
-            SELECT 1 as "exists_flag: i32" FROM users WHERE name = ? AND id != ?
-            "#,
-            name,
-            id
-        )
-        .fetch_optional(pool.as_ref())
-        .await?;
-        Ok(result.is_some())
+/// Opens the storage writing to the configured database, creating it if needed, and
+/// upgrades its schema
+pub async fn init_db(config: &DatabaseConfig) -> anyhow::Result<Arc<dyn Storage>> {
+    Ok(if config.is_postgres() {
+        Arc::new(PgStorage::connect(config).await?)
     } else {
-        let result = sqlx::query!(
-            r#"
-            SELECT 1 as "exists_flag: i32" FROM users WHERE name = ?
-            "#,
-            name
-        )
-        .fetch_optional(pool.as_ref())
-        .await?;
-        Ok(result.is_some())
-    }
-}
-
-/// Get the next available user ID
-pub async fn get_next_user_id(pool: Arc<SqlitePool>) -> sqlx::Result<u32> {
-    let max_id = sqlx::query!(
-        r#"
-        SELECT MAX(id) as max_id FROM users
-        "#
-    )
-    .fetch_one(pool.as_ref())
-    .await?;
-
-    Ok(max_id.max_id.map(|id| id + 1).unwrap_or(0) as u32)
-}
-
-/// Create a new user with auto-generated ID
-pub async fn create_user(name: &str, pool: Arc<SqlitePool>) -> sqlx::Result<User> {
-    let new_id = get_next_user_id(pool.clone()).await?;
-
-    sqlx::query!(
-        r#"
-        INSERT INTO users (id, name) VALUES (?, ?)
-        "#,
-        new_id,
-        name
-    )
-    .execute(pool.as_ref())
-    .await?;
-
-    Ok(User {
-        id: new_id,
-        name: name.to_string(),
+        Arc::new(SqliteStorage::connect(config).await?)
     })
 }
 
-/// Update an existing user
-pub async fn update_user(id: u32, name: &str, pool: Arc<SqlitePool>) -> sqlx::Result<User> {
-    sqlx::query!(
-        r#"
-        UPDATE users SET name = ? WHERE id = ?
-        "#,
-        name,
-        id
-    )
-    .execute(pool.as_ref())
-    .await?;
-
-    Ok(User {
-        id,
-        name: name.to_string(),
+/// Opens a storage of read-only connections to the database `init_db` set up
+///
+/// Queries on these don't wait for the writes of the other storage (in WAL mode with
+/// SQLite).
+pub async fn init_read_pool(config: &DatabaseConfig) -> anyhow::Result<Arc<dyn Storage>> {
+    Ok(if config.is_postgres() {
+        Arc::new(PgStorage::connect_read_only(config).await?)
+    } else {
+        Arc::new(SqliteStorage::connect_read_only(config).await?)
     })
 }
 
-/// Get global ranklist (contest_id = 0)
-pub async fn get_global_ranklist(
-    scoring_rule: Option<String>,
-    tie_breaker: Option<String>,
-    problems: Arc<crate::config::ProblemConfig>,
-    pool: Arc<SqlitePool>,
-) -> sqlx::Result<Vec<RanklistEntry>> {
-    let scoring_rule = scoring_rule.unwrap_or_else(|| "latest".to_string());
-    let tie_breaker = tie_breaker.unwrap_or_default();
-
-    // Get all users
-    let users = get_users(pool.clone()).await?;
-
-    // Get all problem IDs from configuration (sorted)
-    let mut problem_ids: Vec<u32> = problems.iter().map(|p| p.id).collect();
-    problem_ids.sort();
-
-    // Calculate scores for each user
-    let mut user_scores = Vec::new();
-
-    for user in users {
-        let mut user_score = UserScore {
-            user_id: user.id,
-            user_name: user.name.clone(),
-            problem_scores: HashMap::new(),
-            total_score: 0.0,
-            latest_submission_time: None,
-            submission_count: 0,
-        };
-
-        // Initialize all problem scores to 0
-        for problem_id in &problem_ids {
-            user_score.problem_scores.insert(*problem_id, 0.0);
-        }
-
-        // Get user's job submission data
-        let jobs = get_user_jobs(user.id, pool.clone()).await?;
-        user_score.submission_count = jobs.len() as u32;
-
-        // Calculate score and the scoring-used submission time for each problem based on scoring rule
-        for problem_id in &problem_ids {
-            let problem_jobs: Vec<_> = jobs
-                .iter()
-                .filter(|job| job.submission.problem_id == *problem_id)
-                .collect();
-
-            if !problem_jobs.is_empty() {
-                // Choose the job used for scoring for this problem, and remember its time
-                let chosen_job = match scoring_rule.as_str() {
-                    "latest" => {
-                        // Use the latest submission
-                        problem_jobs
-                            .iter()
-                            .max_by_key(|job| &job.created_time)
-                            .copied()
-                    }
-                    "highest" => {
-                        // Use the highest score, with earliest submission time as tie breaker
-                        problem_jobs
-                            .iter()
-                            .max_by(|a, b| {
-                                a.score
-                                    .partial_cmp(&b.score)
-                                    .unwrap_or(std::cmp::Ordering::Equal)
-                                    // If scores equal, earlier created_time is better
-                                    .then_with(|| b.created_time.cmp(&a.created_time))
-                            })
-                            .copied()
-                    }
-                    _ => None,
-                };
-
-                if let Some(job) = chosen_job {
-                    user_score.problem_scores.insert(*problem_id, job.score);
-
-                    // Update the user's latest scoring-used submission time
-                    if user_score
-                        .latest_submission_time
-                        .as_ref()
-                        .is_none_or(|t| t < &job.created_time)
-                    {
-                        user_score.latest_submission_time = Some(job.created_time.clone());
-                    }
-                }
-            }
-        }
-
-        // Calculate total score
-        user_score.total_score = user_score.problem_scores.values().sum();
-        user_scores.push(user_score);
+/// Removes all data of the configured database
+pub async fn remove_db(config: &DatabaseConfig) -> anyhow::Result<()> {
+    if config.is_postgres() {
+        postgres::remove_db(config).await
+    } else {
+        sqlite::remove_db(config)
     }
+}
 
-    // Sort users based on scores and tie breaker
-    user_scores.sort_unstable_by(|a, b| {
-        // First sort by total score (descending)
-        match b
-            .total_score
-            .partial_cmp(&a.total_score)
-            .unwrap_or(std::cmp::Ordering::Equal)
-        {
-            std::cmp::Ordering::Equal => {
-                // If scores are equal, apply tie breaker
-                match tie_breaker.as_str() {
-                    "submission_time" => {
-                        // Earlier latest submission time is better
-                        match (&a.latest_submission_time, &b.latest_submission_time) {
-                            (Some(a_time), Some(b_time)) => a_time.cmp(b_time),
-                            (Some(_), None) => std::cmp::Ordering::Less, // Has submission is better than no submission
-                            (None, Some(_)) => std::cmp::Ordering::Greater,
-                            _ => std::cmp::Ordering::Equal, // Both have no submissions
-                        }
-                    }
-                    "submission_count" => {
-                        // Fewer submissions is better
-                        a.submission_count.cmp(&b.submission_count)
-                    }
-                    "user_id" => {
-                        // Smaller user_id is better
-                        a.user_id.cmp(&b.user_id)
-                    }
-                    _ => std::cmp::Ordering::Equal, // Default to none
-                }
-            }
-            other => other,
-        }
-    });
-
-    // Calculate ranks and build result
-    let mut result = Vec::new();
-    let mut current_rank = 1;
-
-    for (index, user_score) in user_scores.iter().enumerate() {
-        // Update rank if this user has different score from previous
-        if index > 0 {
-            let prev_user = &user_scores[index - 1];
-            if user_score.total_score < prev_user.total_score {
-                current_rank = index as u32 + 1;
-            }
-            // If scores are equal, check tie breaker
-            else {
-                let tie_broken = match tie_breaker.as_str() {
-                    "submission_time" => {
-                        match (
-                            &user_score.latest_submission_time,
-                            &prev_user.latest_submission_time,
-                        ) {
-                            (Some(curr_time), Some(prev_time)) => curr_time != prev_time,
-                            (None, None) => false,
-                            _ => true, // One has submission, the other doesn't
-                        }
-                    }
-                    "submission_count" => user_score.submission_count != prev_user.submission_count,
-                    "user_id" => user_score.user_id != prev_user.user_id,
-                    _ => false,
-                };
-
-                if tie_broken {
-                    current_rank = index as u32 + 1;
-                }
-            }
-        }
+/// Unix time in milliseconds at which a lease taken now expires
+fn lease_deadline(lease: Duration) -> i64 {
+    chrono::Utc::now().timestamp_millis() + lease.as_millis() as i64
+}
 
-        // Build scores array in problem_id order
-        let scores: Vec<f64> = problem_ids
-            .iter()
-            .map(|problem_id| {
-                user_score
-                    .problem_scores
-                    .get(problem_id)
-                    .copied()
-                    .unwrap_or(0.0)
-            })
-            .collect();
-
-        result.push(RanklistEntry {
-            user: User {
-                id: user_score.user_id,
-                name: user_score.user_name.clone(),
-            },
-            rank: current_rank,
-            scores,
-        });
+/// Builds a queued job from its row in the `queue` table
+fn queued_job(
+    job_id: i64,
+    priority: i64,
+    user_id: i64,
+    enqueued_time: String,
+    now: chrono::DateTime<chrono::Utc>,
+) -> QueuedJob {
+    let age = chrono::DateTime::parse_from_rfc3339(&enqueued_time)
+        .map(|time| (now - time.to_utc()).num_seconds().max(0) as u64)
+        .unwrap_or_default();
+    QueuedJob {
+        id: job_id as u32,
+        user_id: user_id as u32,
+        priority: JobPriority::from_level(priority as u32),
+        enqueued_time,
+        age_secs: age,
     }
-
-    Ok(result)
 }
 
-/// Get all jobs for a specific user
-async fn get_user_jobs(user_id: u32, pool: Arc<SqlitePool>) -> sqlx::Result<Vec<JobRecord>> {
-    let job_ids = sqlx::query!(
-        r#"
-        SELECT id FROM jobs WHERE user_id = ? ORDER BY created_time
-        "#,
-        user_id
-    )
-    .fetch_all(pool.as_ref())
-    .await?;
-
-    let mut jobs = Vec::new();
-    for row in job_ids {
-        if let Ok(job) = fetch_job(row.id as u32, pool.clone()).await {
-            jobs.push(job);
-        }
+/// Builds the status of a worker from its row in the `workers` table
+fn worker_status(
+    name: String,
+    job_id: Option<i64>,
+    case_index: Option<i64>,
+    last_seen: i64,
+    seen_after: i64,
+) -> WorkerStatus {
+    let state = match (job_id, case_index) {
+        _ if last_seen < seen_after => "Offline",
+        (None, _) => "Idle",
+        (Some(_), Some(0) | None) => "Compiling",
+        (Some(_), Some(_)) => "Running",
+    };
+    let last_seen = chrono::DateTime::from_timestamp_millis(last_seen)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    WorkerStatus {
+        name,
+        state,
+        job_id: job_id.map(|id| id as u32),
+        case: case_index.filter(|&case| case > 0).map(|case| case as u32),
+        last_seen,
     }
-
-    Ok(jobs)
 }
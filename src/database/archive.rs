@@ -0,0 +1,259 @@
+//! Archives of users, jobs and problem metadata in JSON, for moving data between
+//! servers and keeping it after a semester
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{DatabaseConfig, Storage, init_db, init_read_pool};
+use crate::config::{OneProblemConfig, ProblemConfig};
+use crate::create_timestamp;
+use crate::routes::{JobRecord, JobsQueryParams, User};
+
+/// Version of the archive format, bumped on incompatible changes
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Archive {
+    pub version: u32,
+    pub exported_time: String,
+    /// Problems of the exporting server, for reference, as they are not imported
+    pub problems: Vec<OneProblemConfig>,
+    pub contests: Vec<ContestSummary>,
+    pub users: Vec<User>,
+    pub jobs: Vec<JobRecord>,
+}
+
+/// Problems and users of the exported jobs of a contest
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContestSummary {
+    pub id: u32,
+    pub problem_ids: Vec<u32>,
+    pub user_ids: Vec<u32>,
+}
+
+/// Which jobs to export, all users are exported regardless
+#[derive(Deserialize, Debug, Default)]
+pub struct ExportFilter {
+    pub contest_id: Option<u32>,
+    /// Earliest creation time of the jobs, in RFC 3339
+    pub from: Option<String>,
+    /// Latest creation time of the jobs, in RFC 3339
+    pub to: Option<String>,
+}
+
+/// How an archive is imported
+#[derive(Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Keep existing data, skipping users and jobs whose ids are taken, and jobs of
+    /// users present under another name
+    #[default]
+    Merge,
+    /// Remove all users and jobs first
+    Fresh,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportSummary {
+    pub users_imported: u32,
+    pub users_skipped: u32,
+    pub jobs_imported: u32,
+    pub jobs_skipped: u32,
+}
+
+/// What `Storage::import_data` did with each user and job, in the order given
+#[derive(Debug, Default)]
+pub struct ImportOutcome {
+    /// Whether each user was inserted
+    pub users: Vec<bool>,
+    pub jobs: Vec<JobImport>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobImport {
+    Imported,
+    /// A job with the same id is present
+    IdTaken,
+    /// The user of the job is not present as it is in the archive, e.g. its id belongs
+    /// to another name
+    UserDiffers,
+}
+
+impl ExportFilter {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for time in [&self.from, &self.to].into_iter().flatten() {
+            if DateTime::parse_from_rfc3339(time).is_err() {
+                bail!("Invalid time {time}, expected RFC 3339");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Archive {
+    /// Checks that this binary can import the archive
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.version > ARCHIVE_VERSION {
+            bail!(
+                "Archive version {} is newer than {ARCHIVE_VERSION} supported by this binary, \
+                 please upgrade oj",
+                self.version
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Collects the jobs matching `filter`, all users and all problems into an archive
+pub async fn export_archive(
+    storage: &dyn Storage,
+    problems: &ProblemConfig,
+    filter: &ExportFilter,
+) -> sqlx::Result<Archive> {
+    let query = JobsQueryParams {
+        contest_id: filter.contest_id,
+        from: filter.from.clone(),
+        to: filter.to.clone(),
+        ..Default::default()
+    };
+    let jobs = storage.fetch_jobs_by_query(&query).await?;
+    let users = storage.get_users().await?;
+
+    let mut contests = BTreeMap::<u32, (BTreeSet<u32>, BTreeSet<u32>)>::new();
+    for job in &jobs {
+        let (problem_ids, user_ids) = contests.entry(job.submission.contest_id).or_default();
+        problem_ids.insert(job.submission.problem_id);
+        user_ids.insert(job.submission.user_id);
+    }
+    let contests = contests
+        .into_iter()
+        .map(|(id, (problem_ids, user_ids))| ContestSummary {
+            id,
+            problem_ids: problem_ids.into_iter().collect(),
+            user_ids: user_ids.into_iter().collect(),
+        })
+        .collect();
+
+    Ok(Archive {
+        version: ARCHIVE_VERSION,
+        exported_time: create_timestamp(),
+        problems: problems.clone(),
+        contests,
+        users,
+        jobs,
+    })
+}
+
+/// Inserts the users and jobs of a validated archive, keeping their ids
+///
+/// The whole import is one transaction, so that a failed import leaves the database as
+/// it was. Jobs are only imported if their user is present as it is in the archive,
+/// same id and same name, so that they never end up under somebody else. Unfinished
+/// jobs are imported as canceled, so that they can be rejudged.
+pub async fn import_archive(
+    storage: &dyn Storage,
+    archive: Archive,
+    mode: ImportMode,
+) -> sqlx::Result<ImportSummary> {
+    let mut jobs = archive.jobs;
+    for job in &mut jobs {
+        if job.state == "Queueing" || job.state == "Running" {
+            job.state = "Canceled".to_string();
+            job.result = "Skipped".to_string();
+            for case in &mut job.cases {
+                case.result = "Skipped".to_string();
+            }
+        }
+    }
+
+    let outcome = storage
+        .import_data(&archive.users, &jobs, mode == ImportMode::Fresh)
+        .await?;
+
+    let mut summary = ImportSummary::default();
+    for (user, imported) in archive.users.iter().zip(outcome.users) {
+        if imported {
+            summary.users_imported += 1;
+        } else {
+            log::info!("Skipped user {} ({}), already present", user.id, user.name);
+            summary.users_skipped += 1;
+        }
+    }
+    for (job, imported) in jobs.iter().zip(outcome.jobs) {
+        match imported {
+            JobImport::Imported => {
+                summary.jobs_imported += 1;
+                continue;
+            }
+            JobImport::IdTaken => log::info!("Skipped job {}, already present", job.id),
+            JobImport::UserDiffers => log::warn!(
+                "Skipped job {} of user {}, which is missing or has another name",
+                job.id,
+                job.submission.user_id
+            ),
+        }
+        summary.jobs_skipped += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Writes an archive of the configured database to `file`, for `oj export`
+pub async fn export_database(
+    config: &DatabaseConfig,
+    problems: &ProblemConfig,
+    filter: &ExportFilter,
+    file: &Path,
+) -> anyhow::Result<()> {
+    filter.validate()?;
+    let storage = init_read_pool(config).await?;
+    let archive = export_archive(storage.as_ref(), problems, filter).await?;
+    storage.close().await;
+
+    let out =
+        File::create_new(file).with_context(|| format!("Unable to create {}", file.display()))?;
+    let mut out = BufWriter::new(out);
+    serde_json::to_writer_pretty(&mut out, &archive)?;
+    out.flush()?;
+
+    log::info!(
+        "Exported {} users and {} jobs to {}",
+        archive.users.len(),
+        archive.jobs.len(),
+        file.display()
+    );
+    Ok(())
+}
+
+/// Imports the archive in `file` into the configured database, for `oj import`
+pub async fn import_database(
+    config: &DatabaseConfig,
+    file: &Path,
+    mode: ImportMode,
+) -> anyhow::Result<()> {
+    let archive: Archive = File::open(file)
+        .map_err(anyhow::Error::from)
+        .and_then(|f| Ok(serde_json::from_reader(BufReader::new(f))?))
+        .with_context(|| format!("Unable to read archive {}", file.display()))?;
+    archive.validate()?;
+
+    let storage = init_db(config).await?;
+    let summary = import_archive(storage.as_ref(), archive, mode).await?;
+    storage.close().await;
+
+    log::info!(
+        "Imported {} users and {} jobs from {}, skipped {} users and {} jobs",
+        summary.users_imported,
+        summary.jobs_imported,
+        file.display(),
+        summary.users_skipped,
+        summary.jobs_skipped
+    );
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Snapshots of the database, taken while the server runs, and restoring from them
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use sqlx::Connection;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use tokio_util::sync::CancellationToken;
+
+use super::sqlite::db_path;
+use super::{DatabaseConfig, SCHEMA_VERSION, Storage, init_read_pool, migrations};
+use crate::config::BackupConfig;
+
+const SNAPSHOT_PREFIX: &str = "oj-";
+const SNAPSHOT_SUFFIX: &str = ".sqlite3";
+
+/// Backs up the configured database to `file`, for `oj backup`
+pub async fn backup_database(config: &DatabaseConfig, file: &Path) -> anyhow::Result<()> {
+    let storage = init_read_pool(config).await?;
+    storage.backup(file).await?;
+    storage.close().await;
+    log::info!("Backed up database to {}", file.display());
+    Ok(())
+}
+
+/// Replaces the configured database with the backup in `file`, for `oj restore`
+///
+/// The backup must be an intact OJ database no newer than this binary, it is upgraded
+/// to the current schema on the next start. The server and judges must be stopped, as
+/// they would keep writing to the replaced database.
+pub async fn restore_database(config: &DatabaseConfig, file: &Path) -> anyhow::Result<()> {
+    if config.is_postgres() {
+        bail!("Backups of PostgreSQL databases are restored with pg_restore");
+    }
+    let version = check_backup(file)
+        .await
+        .with_context(|| format!("Unable to restore from {}", file.display()))?;
+
+    let db_path = db_path(config)?;
+    let partial = PathBuf::from(format!("{}.restoring", db_path.display()));
+    fs::copy(file, &partial)?;
+    // The log of the replaced database must not be applied to the restored one
+    let _ = fs::remove_file(format!("{}-wal", db_path.display()));
+    let _ = fs::remove_file(format!("{}-shm", db_path.display()));
+    fs::rename(&partial, &db_path)?;
+
+    log::info!(
+        "Restored database at {} from {} (schema version {version})",
+        db_path.display(),
+        file.display()
+    );
+    Ok(())
+}
+
+/// Checks that `file` is an intact OJ database this binary can open, returning its
+/// schema version
+async fn check_backup(file: &Path) -> anyhow::Result<u32> {
+    let options = SqliteConnectOptions::new().filename(file).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&options).await?;
+
+    let integrity: String = sqlx::query_scalar("PRAGMA quick_check")
+        .fetch_one(&mut conn)
+        .await?;
+    if integrity != "ok" {
+        bail!("Backup is corrupted: {integrity}");
+    }
+    let has_jobs: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'jobs')",
+    )
+    .fetch_one(&mut conn)
+    .await?;
+    if !has_jobs {
+        bail!("Not an OJ database");
+    }
+    let version = migrations::read_version(&mut conn).await?;
+    if version > SCHEMA_VERSION {
+        bail!(
+            "Backup schema version {version} is newer than {SCHEMA_VERSION} supported by this \
+             binary, please upgrade oj"
+        );
+    }
+
+    conn.close().await?;
+    Ok(version)
+}
+
+/// Takes a snapshot into `config.dir` every interval until `token` is cancelled,
+/// keeping the latest `config.keep` ones
+pub async fn run_scheduled_backups(
+    storage: &dyn Storage,
+    config: BackupConfig,
+    token: CancellationToken,
+) {
+    let dir = PathBuf::from(&config.dir);
+    let interval = Duration::from_secs(config.interval_mins.unwrap_or(24 * 60).max(1) * 60);
+    let keep = config.keep.unwrap_or(7).max(1);
+    log::info!(
+        "Backing up database to {} every {} minutes",
+        dir.display(),
+        interval.as_secs() / 60
+    );
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(interval) => {}
+        }
+
+        let name = format!(
+            "{SNAPSHOT_PREFIX}{}{SNAPSHOT_SUFFIX}",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        );
+        let file = dir.join(name);
+        let snapshot = async {
+            fs::create_dir_all(&dir)?;
+            storage.backup(&file).await
+        };
+        match snapshot.await {
+            Ok(()) => log::info!("Backed up database to {}", file.display()),
+            Err(e) => {
+                log::error!("Failed to back up database to {}: {e}", file.display());
+                continue;
+            }
+        }
+        if let Err(e) = prune_snapshots(&dir, keep) {
+            log::warn!("Failed to remove old backups in {}: {e}", dir.display());
+        }
+    }
+}
+
+/// Removes all but the latest `keep` snapshots in `dir`, whose names sort by time
+fn prune_snapshots(dir: &Path, keep: usize) -> std::io::Result<()> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(SNAPSHOT_SUFFIX) {
+            snapshots.push(name);
+        }
+    }
+    snapshots.sort_unstable();
+
+    let excess = snapshots.len().saturating_sub(keep);
+    for name in &snapshots[..excess] {
+        fs::remove_file(dir.join(name))?;
+        log::info!("Removed old backup {name}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_snapshots() {
+        let dir = std::env::temp_dir().join(format!("oj-prune-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "oj-20260101-000000.sqlite3",
+            "oj-20260102-000000.sqlite3",
+            "oj-20260103-000000.sqlite3",
+            "oj-20260104-000000.sqlite3.partial",
+            "other.sqlite3",
+        ];
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        prune_snapshots(&dir, 2).unwrap();
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, names[1..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
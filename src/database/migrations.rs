@@ -0,0 +1,309 @@
+//! Versioned schema of the database
+//!
+//! Each migration upgrades the schema by one version, and the version of a database
+//! is kept in the `schema_version` table. Databases created before versioning have
+//! no such table and are upgraded from version 0. As they may already have any part
+//! of the schema up to version 5, the steps of those versions are idempotent; later
+//! steps need not be.
+
+use anyhow::bail;
+use sqlx::SqliteConnection;
+use sqlx::sqlite::SqlitePool;
+
+/// A change to the schema
+enum Step {
+    /// A statement run as is
+    Sql(&'static str),
+    /// Adds a column to a table unless it already has one of that name
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+}
+
+/// Migrations in order, the one at index `i` upgrading to version `i + 1`
+const MIGRATIONS: &[&[Step]] = &[
+    // 1: Users and jobs
+    &[
+        Step::Sql(
+            r"
+            CREATE TABLE IF NOT EXISTS users (
+                id            INTEGER PRIMARY KEY,
+                name          TEXT    NOT NULL UNIQUE
+            );",
+        ),
+        Step::Sql(
+            r"
+            CREATE TABLE IF NOT EXISTS jobs (
+                pk            INTEGER  PRIMARY KEY,
+                id            INTEGER  GENERATED ALWAYS AS (pk - 1) STORED UNIQUE,
+                created_time  TEXT     NOT NULL,
+                updated_time  TEXT     NOT NULL,
+                user_id       INTEGER  NOT NULL,
+                contest_id    INTEGER  NOT NULL,
+                problem_id    INTEGER  NOT NULL,
+                source_code   TEXT     NOT NULL,
+                language      TEXT     NOT NULL,
+                state         TEXT     NOT NULL,
+                result        TEXT     NOT NULL,
+                score         REAL     NOT NULL,
+                FOREIGN KEY (user_id)  REFERENCES users (id)
+            );",
+        ),
+        Step::Sql(
+            r"
+            CREATE TABLE IF NOT EXISTS job_case (
+                job_id         INTEGER      NOT NULL,
+                case_index     INTEGER      NOT NULL,
+                result         TEXT         NOT NULL,
+                time_us        INTEGER      NOT NULL,
+                memory_kb      INTEGER      NOT NULL,
+                info           TEXT         DEFAULT '',
+                PRIMARY KEY (job_id, case_index),
+                FOREIGN KEY (job_id)  REFERENCES jobs (id)
+            );",
+        ),
+        Step::Sql("INSERT OR IGNORE INTO users (id, name) VALUES (0, 'root');"),
+    ],
+    // 2: Partial scores of cases
+    &[Step::AddColumn {
+        table: "job_case",
+        column: "score",
+        definition: "REAL NOT NULL DEFAULT 0.0",
+    }],
+    // 3: Durable job queue
+    &[Step::Sql(
+        r"
+        CREATE TABLE IF NOT EXISTS queue (
+            job_id         INTEGER      PRIMARY KEY,
+            enqueued_time  TEXT         NOT NULL,
+            lease_owner    TEXT,
+            lease_expires  INTEGER,
+            FOREIGN KEY (job_id)  REFERENCES jobs (id)
+        );",
+    )],
+    // 4: Priority levels and rounds of queued jobs
+    &[
+        Step::AddColumn {
+            table: "queue",
+            column: "priority",
+            definition: "INTEGER NOT NULL DEFAULT 1",
+        },
+        Step::AddColumn {
+            table: "queue",
+            column: "user_id",
+            definition: "INTEGER NOT NULL DEFAULT 0",
+        },
+        Step::AddColumn {
+            table: "queue",
+            column: "round",
+            definition: "INTEGER NOT NULL DEFAULT 0",
+        },
+    ],
+    // 5: Worker states and the pause flag
+    &[
+        Step::Sql(
+            r"
+            CREATE TABLE IF NOT EXISTS workers (
+                name           TEXT         PRIMARY KEY,
+                job_id         INTEGER,
+                case_index     INTEGER,
+                last_seen      INTEGER      NOT NULL
+            );",
+        ),
+        Step::Sql(
+            r"
+            CREATE TABLE IF NOT EXISTS settings (
+                key            TEXT         PRIMARY KEY,
+                value          TEXT         NOT NULL
+            );",
+        ),
+    ],
+    // 6: Indexes for the filters of job lists
+    &[
+        Step::Sql("CREATE INDEX jobs_user_id ON jobs (user_id);"),
+        Step::Sql("CREATE INDEX jobs_problem_id ON jobs (problem_id);"),
+        Step::Sql("CREATE INDEX jobs_contest_id ON jobs (contest_id);"),
+        Step::Sql("CREATE INDEX jobs_created_time ON jobs (created_time);"),
+    ],
+    // 7: Token buckets of the rate limiter
+    &[Step::Sql(
+        r"
+        CREATE TABLE rate_limits (
+            key            TEXT         PRIMARY KEY,
+            tokens         REAL         NOT NULL,
+            capacity       REAL         NOT NULL,
+            rate           REAL         NOT NULL,
+            updated        INTEGER      NOT NULL
+        );",
+    )],
+];
+
+/// Version of the schema this binary works with
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Upgrades the database to `SCHEMA_VERSION`, all in one transaction
+///
+/// Fails without changing anything if the database is newer than this binary.
+pub async fn migrate(pool: &SqlitePool) -> anyhow::Result<()> {
+    // Take the write lock up front, so that processes starting at the same time
+    // migrate one after another
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);")
+        .execute(tx.as_mut())
+        .await?;
+    let version: Option<i64> = sqlx::query_scalar("SELECT version FROM schema_version")
+        .fetch_optional(tx.as_mut())
+        .await?;
+    let version = version.unwrap_or(0) as u32;
+
+    if version > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {version} is newer than {SCHEMA_VERSION} supported by \
+             this binary, please upgrade oj"
+        );
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    log::info!("Migrating database from schema version {version} to {SCHEMA_VERSION}");
+    for steps in &MIGRATIONS[version as usize..] {
+        for step in *steps {
+            run_step(step, tx.as_mut()).await?;
+        }
+    }
+    sqlx::query("DELETE FROM schema_version")
+        .execute(tx.as_mut())
+        .await?;
+    sqlx::query("INSERT INTO schema_version (version) VALUES (?)")
+        .bind(SCHEMA_VERSION)
+        .execute(tx.as_mut())
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Reads the version of a database without changing it, 0 if it is unversioned
+pub async fn read_version(conn: &mut SqliteConnection) -> sqlx::Result<u32> {
+    let versioned: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !versioned {
+        return Ok(0);
+    }
+    let version: Option<i64> = sqlx::query_scalar("SELECT version FROM schema_version")
+        .fetch_optional(conn)
+        .await?;
+    Ok(version.unwrap_or(0) as u32)
+}
+
+async fn run_step(step: &Step, conn: &mut SqliteConnection) -> sqlx::Result<()> {
+    match step {
+        Step::Sql(sql) => {
+            sqlx::query(sql).execute(conn).await?;
+        }
+        Step::AddColumn {
+            table,
+            column,
+            definition,
+        } => {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = ?)",
+            )
+            .bind(table)
+            .bind(column)
+            .fetch_one(&mut *conn)
+            .await?;
+            if !exists {
+                let sql = format!("ALTER TABLE {table} ADD COLUMN {column} {definition}");
+                sqlx::query(&sql).execute(conn).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_pool() -> SqlitePool {
+        // Each connection to an in-memory database has its own
+        sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn schema_version(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT version FROM schema_version")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_unversioned_database() {
+        let pool = memory_pool().await;
+        // A database from before partial scores, with a judged job
+        for step in MIGRATIONS[0] {
+            run_step(step, &mut pool.acquire().await.unwrap())
+                .await
+                .unwrap();
+        }
+        sqlx::query(
+            "INSERT INTO jobs (created_time, updated_time, user_id, contest_id, problem_id,
+                source_code, language, state, result, score)
+            VALUES ('', '', 0, 0, 0, '', 'Rust', 'Finished', 'Accepted', 100.0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb)
+            VALUES (0, 1, 'Accepted', 0, 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        migrate(&pool).await.unwrap();
+        assert_eq!(schema_version(&pool).await, SCHEMA_VERSION as i64);
+        let score: f64 = sqlx::query_scalar("SELECT score FROM job_case WHERE job_id = 0")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(score, 0.0);
+        let indexes: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name LIKE 'jobs_%'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(indexes, 4);
+
+        // Migrating again changes nothing
+        migrate(&pool).await.unwrap();
+        assert_eq!(schema_version(&pool).await, SCHEMA_VERSION as i64);
+    }
+
+    #[tokio::test]
+    async fn test_refuse_newer_database() {
+        let pool = memory_pool().await;
+        migrate(&pool).await.unwrap();
+        sqlx::query("UPDATE schema_version SET version = version + 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(migrate(&pool).await.is_err());
+        assert_eq!(schema_version(&pool).await, SCHEMA_VERSION as i64 + 1);
+    }
+}
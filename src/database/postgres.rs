@@ -0,0 +1,1189 @@
+//! Storage on a PostgreSQL server, selected by a `postgres://` URL in `database.path`
+//!
+//! The schema mirrors the SQLite one column for column, with 64-bit integers and
+//! floats, and times kept as RFC 3339 text compared bytewise. Queries are checked at
+//! runtime, as the compile-time checked ones are prepared against SQLite.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::bail;
+use async_trait::async_trait;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use sqlx::{Connection, PgConnection, Postgres, QueryBuilder};
+
+use super::{
+    DEFAULT_READ_CONNECTIONS, ImportOutcome, JobImport, SCHEMA_VERSION, Storage, lease_deadline,
+    queued_job, worker_status,
+};
+use crate::config::DatabaseConfig;
+use crate::create_timestamp;
+use crate::queue::{QueuedJob, WorkerStatus};
+use crate::rate_limit::TokenBucket;
+use crate::routes::{CaseResult, JobRecord, JobSubmission, JobsQueryParams, User};
+
+/// Migrations in order, upgrading to the same versions as `migrations::MIGRATIONS`
+const MIGRATIONS: &[&[&str]] = &[
+    // 1: Users and jobs
+    &[
+        r#"
+        CREATE TABLE users (
+            id            BIGINT   PRIMARY KEY,
+            name          TEXT     NOT NULL UNIQUE
+        )"#,
+        r#"
+        CREATE TABLE jobs (
+            pk            BIGSERIAL         PRIMARY KEY,
+            id            BIGINT            GENERATED ALWAYS AS (pk - 1) STORED UNIQUE,
+            created_time  TEXT COLLATE "C"  NOT NULL,
+            updated_time  TEXT COLLATE "C"  NOT NULL,
+            user_id       BIGINT            NOT NULL,
+            contest_id    BIGINT            NOT NULL,
+            problem_id    BIGINT            NOT NULL,
+            source_code   TEXT              NOT NULL,
+            language      TEXT              NOT NULL,
+            state         TEXT              NOT NULL,
+            result        TEXT              NOT NULL,
+            score         DOUBLE PRECISION  NOT NULL,
+            FOREIGN KEY (user_id)  REFERENCES users (id)
+        )"#,
+        r#"
+        CREATE TABLE job_case (
+            job_id         BIGINT       NOT NULL,
+            case_index     BIGINT       NOT NULL,
+            result         TEXT         NOT NULL,
+            time_us        BIGINT       NOT NULL,
+            memory_kb      BIGINT       NOT NULL,
+            info           TEXT         DEFAULT '',
+            PRIMARY KEY (job_id, case_index),
+            FOREIGN KEY (job_id)  REFERENCES jobs (id)
+        )"#,
+        "INSERT INTO users (id, name) VALUES (0, 'root')",
+    ],
+    // 2: Partial scores of cases
+    &["ALTER TABLE job_case ADD COLUMN score DOUBLE PRECISION NOT NULL DEFAULT 0.0"],
+    // 3: Durable job queue
+    &[r#"
+        CREATE TABLE queue (
+            job_id         BIGINT            PRIMARY KEY,
+            enqueued_time  TEXT COLLATE "C"  NOT NULL,
+            lease_owner    TEXT,
+            lease_expires  BIGINT,
+            FOREIGN KEY (job_id)  REFERENCES jobs (id)
+        )"#],
+    // 4: Priority levels and rounds of queued jobs
+    &[r"
+        ALTER TABLE queue
+            ADD COLUMN priority  BIGINT  NOT NULL DEFAULT 1,
+            ADD COLUMN user_id   BIGINT  NOT NULL DEFAULT 0,
+            ADD COLUMN round     BIGINT  NOT NULL DEFAULT 0
+        "],
+    // 5: Worker states and the pause flag
+    &[
+        r#"
+        CREATE TABLE workers (
+            name           TEXT COLLATE "C"  PRIMARY KEY,
+            job_id         BIGINT,
+            case_index     BIGINT,
+            last_seen      BIGINT            NOT NULL
+        )"#,
+        r"
+        CREATE TABLE settings (
+            key            TEXT         PRIMARY KEY,
+            value          TEXT         NOT NULL
+        )",
+    ],
+    // 6: Indexes for the filters of job lists
+    &[
+        "CREATE INDEX jobs_user_id ON jobs (user_id)",
+        "CREATE INDEX jobs_problem_id ON jobs (problem_id)",
+        "CREATE INDEX jobs_contest_id ON jobs (contest_id)",
+        "CREATE INDEX jobs_created_time ON jobs (created_time)",
+    ],
+    // 7: Token buckets of the rate limiter
+    &[r"
+        CREATE TABLE rate_limits (
+            key            TEXT              PRIMARY KEY,
+            tokens         DOUBLE PRECISION  NOT NULL,
+            capacity       DOUBLE PRECISION  NOT NULL,
+            rate           DOUBLE PRECISION  NOT NULL,
+            updated        BIGINT            NOT NULL
+        )"],
+];
+
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSION as usize);
+
+/// Key of the advisory lock held while migrating
+const MIGRATION_LOCK: i64 = 0x6f6a_6d69_6772;
+
+/// Tables of the schema, dropped by `--flush-data`
+const TABLES: &str = "queue, job_case, jobs, users, workers, settings, rate_limits, schema_version";
+
+/// Connection options shared by both pools of the configured database
+fn connect_options(config: &DatabaseConfig) -> anyhow::Result<PgConnectOptions> {
+    let url = config.path.as_deref().unwrap_or_default();
+    let lock_timeout = config.busy_timeout_ms.unwrap_or(2000);
+    Ok(PgConnectOptions::from_str(url)?.options([("lock_timeout", lock_timeout)]))
+}
+
+pub async fn remove_db(config: &DatabaseConfig) -> anyhow::Result<()> {
+    let options = connect_options(config)?;
+    let mut conn = PgConnection::connect_with(&options).await?;
+    sqlx::query(&format!("DROP TABLE IF EXISTS {TABLES} CASCADE"))
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+    log::info!(
+        "Removed database {} on {}",
+        options.get_database().unwrap_or_default(),
+        options.get_host()
+    );
+    Ok(())
+}
+
+/// Upgrades the database to `SCHEMA_VERSION`, all in one transaction
+///
+/// Fails without changing anything if the database is newer than this binary.
+async fn migrate(pool: &PgPool) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    // Processes starting at the same time migrate one after another
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATION_LOCK)
+        .execute(tx.as_mut())
+        .await?;
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS schema_version (version BIGINT NOT NULL)")
+        .execute(tx.as_mut())
+        .await?;
+    let version: Option<i64> = sqlx::query_scalar("SELECT version FROM schema_version")
+        .fetch_optional(tx.as_mut())
+        .await?;
+    let version = version.unwrap_or(0) as u32;
+
+    if version > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {version} is newer than {SCHEMA_VERSION} supported by \
+             this binary, please upgrade oj"
+        );
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    log::info!("Migrating database from schema version {version} to {SCHEMA_VERSION}");
+    for steps in &MIGRATIONS[version as usize..] {
+        for sql in *steps {
+            sqlx::query(sql).execute(tx.as_mut()).await?;
+        }
+    }
+    sqlx::query("DELETE FROM schema_version")
+        .execute(tx.as_mut())
+        .await?;
+    sqlx::query("INSERT INTO schema_version (version) VALUES ($1)")
+        .bind(SCHEMA_VERSION as i64)
+        .execute(tx.as_mut())
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub struct PgStorage {
+    pool: PgPool,
+}
+
+impl PgStorage {
+    /// Opens the pool of connections writing to the database and upgrades its schema
+    ///
+    /// The database itself must exist.
+    pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        let options = connect_options(config)?;
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections.unwrap_or(1).max(1))
+            .min_connections(0)
+            .connect_with(options.clone())
+            .await?;
+
+        migrate(&pool).await?;
+
+        log::info!(
+            "Initialized database {} on {}",
+            options.get_database().unwrap_or_default(),
+            options.get_host()
+        );
+
+        Ok(Self { pool })
+    }
+
+    /// Opens a pool of connections whose transactions are read-only by default
+    pub async fn connect_read_only(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        let max_connections = config
+            .max_read_connections
+            .unwrap_or(DEFAULT_READ_CONNECTIONS)
+            .max(1);
+        let options = connect_options(config)?.options([("default_transaction_read_only", "on")]);
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .min_connections(0)
+            .connect_with(options)
+            .await?;
+        Ok(Self { pool })
+    }
+}
+
+/// Inserts a Queueing job with `len` Waiting cases on `conn`, returning its id
+async fn insert_job(conn: &mut PgConnection, body: &JobSubmission, len: u32) -> sqlx::Result<u32> {
+    let now = create_timestamp();
+    let job_id: i64 = sqlx::query_scalar(
+        r"
+        INSERT INTO jobs (user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time)
+        VALUES ($1, $2, $3, $4, $5, 'Queueing', 'Waiting', 0.0, $6, $6)
+        RETURNING id
+        ",
+    )
+    .bind(i64::from(body.user_id))
+    .bind(i64::from(body.contest_id))
+    .bind(i64::from(body.problem_id))
+    .bind(&body.source_code)
+    .bind(&body.language)
+    .bind(&now)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    for i in 0..len {
+        sqlx::query(
+            r"
+            INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb)
+            VALUES ($1, $2, 'Waiting', 0, 0)
+            ",
+        )
+        .bind(job_id)
+        .bind(i64::from(i))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(job_id as u32)
+}
+
+/// Reverts a job and its cases to Queueing on `conn`, returning the number of cases
+async fn revert_job(conn: &mut PgConnection, id: u32) -> sqlx::Result<usize> {
+    let now = create_timestamp();
+    sqlx::query(
+        r"
+        UPDATE jobs
+        SET state = 'Queueing', result = 'Waiting', score = 0.0, updated_time = $1
+        WHERE id = $2
+        ",
+    )
+    .bind(&now)
+    .bind(i64::from(id))
+    .execute(&mut *conn)
+    .await?;
+    let reverted_cases = sqlx::query(
+        r"
+        UPDATE job_case
+        SET result = 'Waiting', time_us = 0, memory_kb = 0, score = 0.0, info = ''
+        WHERE job_id = $1
+        ",
+    )
+    .bind(i64::from(id))
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    Ok(reverted_cases as usize)
+}
+
+/// Queues a job on `conn`, see `Storage::enqueue_job`
+async fn insert_queued(
+    conn: &mut PgConnection,
+    id: u32,
+    priority: u32,
+    user_id: u32,
+) -> sqlx::Result<()> {
+    let now = create_timestamp();
+    sqlx::query(
+        r"
+        INSERT INTO queue (job_id, enqueued_time, priority, user_id, round)
+        VALUES ($1, $2, $3, $4, GREATEST(
+            COALESCE((SELECT MIN(round) FROM queue WHERE priority = $3 AND lease_owner IS NULL), 0),
+            COALESCE((SELECT MAX(round) + 1 FROM queue WHERE priority = $3 AND user_id = $4), 0)
+        ))
+        ",
+    )
+    .bind(i64::from(id))
+    .bind(&now)
+    .bind(i64::from(priority))
+    .bind(i64::from(user_id))
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether `max_queued` jobs are waiting to be claimed, false without a limit
+///
+/// With a limit, the queue stays locked against other inserts until the transaction
+/// on `conn` ends, so that no job is queued after counting.
+async fn queue_full(conn: &mut PgConnection, max_queued: Option<u32>) -> sqlx::Result<bool> {
+    let Some(max_queued) = max_queued else {
+        return Ok(false);
+    };
+    sqlx::query("LOCK TABLE queue IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await?;
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM queue WHERE lease_owner IS NULL")
+        .fetch_one(conn)
+        .await?;
+
+    Ok(count >= i64::from(max_queued))
+}
+
+/// Writes the result of a job and its cases on `conn`, typically in a transaction
+async fn write_result(conn: &mut PgConnection, id: u32, result: &JobRecord) -> sqlx::Result<()> {
+    let now = create_timestamp();
+
+    sqlx::query(
+        "UPDATE jobs SET state = $1, result = $2, score = $3, updated_time = $4 WHERE id = $5",
+    )
+    .bind(&result.state)
+    .bind(&result.result)
+    .bind(result.score)
+    .bind(&now)
+    .bind(i64::from(id))
+    .execute(&mut *conn)
+    .await?;
+
+    // Upsert all case results at once, so that no row is ever missing
+    if !result.cases.is_empty() {
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb, score, info) ",
+        );
+        qb.push_values(&result.cases, |mut row, case| {
+            row.push_bind(i64::from(id))
+                .push_bind(i64::from(case.id))
+                .push_bind(&case.result)
+                .push_bind(i64::from(case.time))
+                .push_bind(i64::from(case.memory))
+                .push_bind(case.score)
+                .push_bind(&case.info);
+        });
+        qb.push(
+            r"
+        ON CONFLICT (job_id, case_index) DO UPDATE
+        SET result = excluded.result, time_us = excluded.time_us, memory_kb = excluded.memory_kb,
+            score = excluded.score, info = excluded.info
+        ",
+        );
+        qb.build().execute(&mut *conn).await?;
+    }
+
+    // Remove cases beyond the result, if it has fewer
+    sqlx::query("DELETE FROM job_case WHERE job_id = $1 AND case_index >= $2")
+        .bind(i64::from(id))
+        .bind(result.cases.len() as i64)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Inserts a user with its id, returning false if the id or name is taken
+async fn import_user(conn: &mut PgConnection, user: &User) -> sqlx::Result<bool> {
+    let imported =
+        sqlx::query("INSERT INTO users (id, name) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(i64::from(user.id))
+            .bind(&user.name)
+            .execute(conn)
+            .await?
+            .rows_affected();
+
+    Ok(imported > 0)
+}
+
+/// Whether a user with the id and name of `user` exists
+async fn user_present(conn: &mut PgConnection, user: &User) -> sqlx::Result<bool> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND name = $2)")
+        .bind(i64::from(user.id))
+        .bind(&user.name)
+        .fetch_one(conn)
+        .await
+}
+
+/// Inserts a job with its id, times and cases as they are, returning false if the id
+/// is taken
+async fn import_job(conn: &mut PgConnection, job: &JobRecord) -> sqlx::Result<bool> {
+    let submission = &job.submission;
+
+    let imported: Option<i64> = sqlx::query_scalar(
+        r"
+        INSERT INTO jobs (pk, user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        ON CONFLICT DO NOTHING
+        RETURNING id
+        ",
+    )
+    .bind(i64::from(job.id) + 1) // Since id is generated as pk - 1
+    .bind(i64::from(submission.user_id))
+    .bind(i64::from(submission.contest_id))
+    .bind(i64::from(submission.problem_id))
+    .bind(&submission.source_code)
+    .bind(&submission.language)
+    .bind(&job.state)
+    .bind(&job.result)
+    .bind(job.score)
+    .bind(&job.created_time)
+    .bind(&job.updated_time)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if imported.is_none() {
+        return Ok(false);
+    }
+
+    for case in &job.cases {
+        sqlx::query(
+            r"
+            INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb, score, info)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+        )
+        .bind(i64::from(job.id))
+        .bind(i64::from(case.id))
+        .bind(&case.result)
+        .bind(i64::from(case.time))
+        .bind(i64::from(case.memory))
+        .bind(case.score)
+        .bind(&case.info)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(true)
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn create_job(&self, body: &JobSubmission, len: u32) -> sqlx::Result<u32> {
+        let mut tx = self.pool.begin().await?;
+        let job_id = insert_job(&mut tx, body, len).await?;
+        tx.commit().await?;
+        Ok(job_id)
+    }
+
+    async fn create_queued_job(
+        &self,
+        body: &JobSubmission,
+        len: u32,
+        priority: u32,
+        max_queued: Option<u32>,
+    ) -> sqlx::Result<Option<u32>> {
+        let mut tx = self.pool.begin().await?;
+        if queue_full(&mut tx, max_queued).await? {
+            return Ok(None);
+        }
+        let job_id = insert_job(&mut tx, body, len).await?;
+        insert_queued(&mut tx, job_id, priority, body.user_id).await?;
+        tx.commit().await?;
+        Ok(Some(job_id))
+    }
+
+    async fn find_job(&self, id: u32) -> sqlx::Result<bool> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM jobs WHERE id = $1)")
+            .bind(i64::from(id))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn find_user(&self, id: u32) -> sqlx::Result<bool> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+            .bind(i64::from(id))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn fetch_job(&self, id: u32) -> sqlx::Result<JobRecord> {
+        log::debug!("Trying to fetch job {id} full record from database");
+        #[allow(clippy::type_complexity)]
+        let (
+            user_id,
+            contest_id,
+            problem_id,
+            source_code,
+            language,
+            state,
+            result,
+            score,
+            created_time,
+            updated_time,
+        ): (i64, i64, i64, String, String, String, String, f64, String, String) =
+            sqlx::query_as(
+                r"
+                SELECT user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time
+                FROM jobs
+                WHERE id = $1
+                ",
+            )
+            .bind(i64::from(id))
+            .fetch_one(&self.pool)
+            .await?;
+
+        let case_data: Vec<(i64, String, i64, i64, f64, Option<String>)> = sqlx::query_as(
+            r"
+            SELECT case_index, result, time_us, memory_kb, score, info
+            FROM job_case
+            WHERE job_id = $1
+            ORDER BY case_index
+            ",
+        )
+        .bind(i64::from(id))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let cases = case_data
+            .into_iter()
+            .map(
+                |(case_index, result, time_us, memory_kb, score, info)| CaseResult {
+                    id: case_index as u32,
+                    result,
+                    time: time_us as u32,
+                    memory: memory_kb as u32,
+                    score,
+                    info: info.unwrap_or_default(),
+                },
+            )
+            .collect();
+
+        log::debug!("Fetched job {id} full record from database");
+        Ok(JobRecord {
+            id,
+            created_time,
+            updated_time,
+            submission: JobSubmission {
+                user_id: user_id as u32,
+                contest_id: contest_id as u32,
+                problem_id: problem_id as u32,
+                source_code,
+                language,
+            },
+            state,
+            result,
+            score,
+            cases,
+        })
+    }
+
+    async fn update_job_to_running(&self, id: u32) -> sqlx::Result<()> {
+        let now = create_timestamp();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "UPDATE jobs SET state = 'Running', result = 'Running', updated_time = $1 WHERE id = $2",
+        )
+        .bind(&now)
+        .bind(i64::from(id))
+        .execute(tx.as_mut())
+        .await?;
+        sqlx::query("UPDATE job_case SET result = 'Running' WHERE job_id = $1 AND case_index = 0")
+            .bind(i64::from(id))
+            .execute(tx.as_mut())
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_job_to_canceled(&self, id: u32) -> sqlx::Result<()> {
+        let now = create_timestamp();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "UPDATE jobs SET state = 'Canceled', result = 'Skipped', updated_time = $1 WHERE id = $2",
+        )
+        .bind(&now)
+        .bind(i64::from(id))
+        .execute(tx.as_mut())
+        .await?;
+        sqlx::query("UPDATE job_case SET result = 'Skipped' WHERE job_id = $1")
+            .bind(i64::from(id))
+            .execute(tx.as_mut())
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn revert_job_to_queueing(&self, id: u32) -> sqlx::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        let reverted_cases = revert_job(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(reverted_cases)
+    }
+
+    async fn requeue_job(
+        &self,
+        id: u32,
+        priority: u32,
+        user_id: u32,
+        max_queued: Option<u32>,
+    ) -> sqlx::Result<Option<usize>> {
+        let mut tx = self.pool.begin().await?;
+        if queue_full(&mut tx, max_queued).await? {
+            return Ok(None);
+        }
+        let reverted_cases = revert_job(&mut tx, id).await?;
+        insert_queued(&mut tx, id, priority, user_id).await?;
+        tx.commit().await?;
+        Ok(Some(reverted_cases))
+    }
+
+    async fn save_result(&self, id: u32, result: &JobRecord) -> sqlx::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        write_result(&mut tx, id, result).await?;
+        tx.commit().await
+    }
+
+    async fn enqueue_job(&self, id: u32, priority: u32, user_id: u32) -> sqlx::Result<()> {
+        insert_queued(&mut *self.pool.acquire().await?, id, priority, user_id).await
+    }
+
+    async fn claim_job(&self, owner: &str, lease: Duration) -> sqlx::Result<Option<u32>> {
+        let expires = lease_deadline(lease);
+        // Concurrent claims skip the rows locked by each other instead of claiming the
+        // same job after waiting for it
+        let claimed: Option<i64> = sqlx::query_scalar(
+            r"
+            UPDATE queue
+            SET lease_owner = $1, lease_expires = $2
+            WHERE lease_owner IS NULL AND job_id = (
+                SELECT job_id FROM queue
+                WHERE lease_owner IS NULL
+                    AND NOT EXISTS (SELECT 1 FROM settings WHERE key = 'queue_paused' AND value = 'true')
+                ORDER BY priority, round, enqueued_time, job_id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING job_id
+            ",
+        )
+        .bind(owner)
+        .bind(expires)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(claimed.map(|id| id as u32))
+    }
+
+    async fn queue_position(&self, id: u32) -> sqlx::Result<Option<u32>> {
+        let position: i64 = sqlx::query_scalar(
+            r"
+            SELECT COUNT(*)
+            FROM queue AS q, queue AS t
+            WHERE t.job_id = $1 AND t.lease_owner IS NULL AND q.lease_owner IS NULL
+                AND (q.priority, q.round, q.enqueued_time, q.job_id)
+                    <= (t.priority, t.round, t.enqueued_time, t.job_id)
+            ",
+        )
+        .bind(i64::from(id))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((position > 0).then_some(position as u32))
+    }
+
+    async fn renew_lease(&self, id: u32, owner: &str, lease: Duration) -> sqlx::Result<bool> {
+        let renewed = sqlx::query(
+            "UPDATE queue SET lease_expires = $1 WHERE job_id = $2 AND lease_owner = $3",
+        )
+        .bind(lease_deadline(lease))
+        .bind(i64::from(id))
+        .bind(owner)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(renewed > 0)
+    }
+
+    async fn finish_job(
+        &self,
+        id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> sqlx::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let released = sqlx::query("DELETE FROM queue WHERE job_id = $1 AND lease_owner = $2")
+            .bind(i64::from(id))
+            .bind(owner)
+            .execute(tx.as_mut())
+            .await?
+            .rows_affected();
+        if released == 0 {
+            return Ok(false);
+        }
+
+        match result {
+            Some(result) => write_result(&mut tx, id, result).await?,
+            None => {
+                sqlx::query(
+                    r"
+                    UPDATE jobs SET state = 'Finished', result = 'System Error', updated_time = $1
+                    WHERE id = $2
+                    ",
+                )
+                .bind(create_timestamp())
+                .bind(i64::from(id))
+                .execute(tx.as_mut())
+                .await?;
+                sqlx::query(
+                    r"
+                    UPDATE job_case SET result = 'Skipped'
+                    WHERE job_id = $1 AND result IN ('Waiting', 'Running')
+                    ",
+                )
+                .bind(i64::from(id))
+                .execute(tx.as_mut())
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn save_case_result(
+        &self,
+        id: u32,
+        owner: &str,
+        case: &CaseResult,
+    ) -> sqlx::Result<bool> {
+        let saved = sqlx::query(
+            r"
+            UPDATE job_case
+            SET result = $1, time_us = $2, memory_kb = $3, score = $4, info = $5
+            WHERE job_id = $6 AND case_index = $7
+                AND EXISTS (SELECT 1 FROM queue WHERE job_id = $6 AND lease_owner = $8)
+            ",
+        )
+        .bind(&case.result)
+        .bind(i64::from(case.time))
+        .bind(i64::from(case.memory))
+        .bind(case.score)
+        .bind(&case.info)
+        .bind(i64::from(id))
+        .bind(i64::from(case.id))
+        .bind(owner)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(saved > 0)
+    }
+
+    async fn dequeue_unclaimed_job(&self, id: u32) -> sqlx::Result<bool> {
+        let dequeued = sqlx::query("DELETE FROM queue WHERE job_id = $1 AND lease_owner IS NULL")
+            .bind(i64::from(id))
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        Ok(dequeued > 0)
+    }
+
+    async fn dequeue_unclaimed_jobs(&self) -> sqlx::Result<Vec<u32>> {
+        let dequeued: Vec<i64> =
+            sqlx::query_scalar("DELETE FROM queue WHERE lease_owner IS NULL RETURNING job_id")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(dequeued.into_iter().map(|id| id as u32).collect())
+    }
+
+    async fn count_unclaimed_jobs(&self) -> sqlx::Result<u32> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM queue WHERE lease_owner IS NULL")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count as u32)
+    }
+
+    async fn fetch_queued_jobs(&self) -> sqlx::Result<Vec<QueuedJob>> {
+        let rows: Vec<(i64, i64, i64, String)> = sqlx::query_as(
+            r"
+            SELECT job_id, priority, user_id, enqueued_time FROM queue
+            WHERE lease_owner IS NULL
+            ORDER BY priority, round, enqueued_time, job_id
+            ",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = chrono::Utc::now();
+        let jobs = rows
+            .into_iter()
+            .map(|(job_id, priority, user_id, enqueued_time)| {
+                queued_job(job_id, priority, user_id, enqueued_time, now)
+            })
+            .collect();
+        Ok(jobs)
+    }
+
+    async fn requeue_expired_jobs(&self) -> sqlx::Result<Vec<u32>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let expired: Vec<i64> = sqlx::query_scalar(
+            r"
+            UPDATE queue
+            SET lease_owner = NULL, lease_expires = NULL
+            WHERE lease_expires < $1
+            RETURNING job_id
+            ",
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut ids = Vec::with_capacity(expired.len());
+        for id in expired {
+            let id = id as u32;
+            self.revert_job_to_queueing(id).await?;
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    async fn update_worker(&self, name: &str, job_id: Option<u32>) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let case_index = job_id.map(|_| 0i64);
+        sqlx::query(
+            r"
+            INSERT INTO workers (name, job_id, case_index, last_seen) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (name) DO UPDATE
+            SET job_id = excluded.job_id, case_index = excluded.case_index, last_seen = excluded.last_seen
+            ",
+        )
+        .bind(name)
+        .bind(job_id.map(i64::from))
+        .bind(case_index)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_worker_idle(&self, name: &str, seen_after: i64) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query(
+            r"
+            INSERT INTO workers (name, job_id, case_index, last_seen) VALUES ($1, NULL, NULL, $2)
+            ON CONFLICT (name) DO UPDATE
+            SET job_id = NULL, case_index = NULL, last_seen = excluded.last_seen
+            WHERE workers.job_id IS NOT NULL OR workers.last_seen < $3
+            ",
+        )
+        .bind(name)
+        .bind(now)
+        .bind(seen_after)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn advance_worker(&self, name: &str, job_id: u32, case_index: u32) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query(
+            r"
+            UPDATE workers
+            SET case_index = LEAST($1 + 1, (SELECT MAX(case_index) FROM job_case WHERE job_id = $2)),
+                last_seen = $3
+            WHERE name = $4 AND job_id = $2
+            ",
+        )
+        .bind(i64::from(case_index))
+        .bind(i64::from(job_id))
+        .bind(now)
+        .bind(name)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn touch_worker(&self, name: &str) -> sqlx::Result<()> {
+        sqlx::query("UPDATE workers SET last_seen = $1 WHERE name = $2")
+            .bind(chrono::Utc::now().timestamp_millis())
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn fetch_workers(&self, seen_after: i64) -> sqlx::Result<Vec<WorkerStatus>> {
+        let rows: Vec<(String, Option<i64>, Option<i64>, i64)> =
+            sqlx::query_as("SELECT name, job_id, case_index, last_seen FROM workers ORDER BY name")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let workers = rows
+            .into_iter()
+            .map(|(name, job_id, case_index, last_seen)| {
+                worker_status(name, job_id, case_index, last_seen, seen_after)
+            })
+            .collect();
+        Ok(workers)
+    }
+
+    async fn set_queue_paused(&self, paused: bool) -> sqlx::Result<()> {
+        sqlx::query(
+            r"
+            INSERT INTO settings (key, value) VALUES ('queue_paused', $1)
+            ON CONFLICT (key) DO UPDATE SET value = excluded.value
+            ",
+        )
+        .bind(paused.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn queue_paused(&self) -> sqlx::Result<bool> {
+        let value: Option<String> =
+            sqlx::query_scalar("SELECT value FROM settings WHERE key = 'queue_paused'")
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(value.is_some_and(|value| value == "true"))
+    }
+
+    async fn take_rate_tokens(&self, buckets: &[TokenBucket], now: i64) -> sqlx::Result<Vec<f64>> {
+        // The buckets stay locked by their upserts until the transaction ends
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r"
+            DELETE FROM rate_limits
+            WHERE tokens + rate * GREATEST($1 - updated, 0) / 1000.0 >= capacity
+            ",
+        )
+        .bind(now)
+        .execute(tx.as_mut())
+        .await?;
+
+        let mut tokens = Vec::with_capacity(buckets.len());
+        for bucket in buckets {
+            let refilled: f64 = sqlx::query_scalar(
+                r"
+                INSERT INTO rate_limits (key, tokens, capacity, rate, updated)
+                VALUES ($1, $2, $2, $3, $4)
+                ON CONFLICT (key) DO UPDATE SET
+                    tokens = LEAST(
+                        excluded.capacity,
+                        rate_limits.tokens
+                            + excluded.rate * GREATEST(excluded.updated - rate_limits.updated, 0)
+                                / 1000.0
+                    ),
+                    capacity = excluded.capacity,
+                    rate = excluded.rate,
+                    updated = GREATEST(excluded.updated, rate_limits.updated)
+                RETURNING tokens
+                ",
+            )
+            .bind(&bucket.key)
+            .bind(bucket.capacity)
+            .bind(bucket.rate)
+            .bind(now)
+            .fetch_one(tx.as_mut())
+            .await?;
+            tokens.push(refilled);
+        }
+
+        if tokens.iter().all(|&tokens| tokens >= 1.0) {
+            for bucket in buckets {
+                sqlx::query("UPDATE rate_limits SET tokens = tokens - 1 WHERE key = $1")
+                    .bind(&bucket.key)
+                    .execute(tx.as_mut())
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(tokens)
+    }
+
+    async fn fetch_jobs_by_query(&self, query: &JobsQueryParams) -> sqlx::Result<Vec<JobRecord>> {
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new("SELECT id FROM jobs WHERE 1=1");
+
+        if let Some(user_id) = query.user_id {
+            qb.push(" AND user_id = ").push_bind(i64::from(user_id));
+        }
+        if let Some(ref user_name) = query.user_name {
+            qb.push(" AND user_id IN (SELECT id FROM users WHERE name = ")
+                .push_bind(user_name)
+                .push(")");
+        }
+        if let Some(contest_id) = query.contest_id {
+            qb.push(" AND contest_id = ")
+                .push_bind(i64::from(contest_id));
+        }
+        if let Some(problem_id) = query.problem_id {
+            qb.push(" AND problem_id = ")
+                .push_bind(i64::from(problem_id));
+        }
+        if let Some(ref language) = query.language {
+            qb.push(" AND language = ").push_bind(language);
+        }
+        if let Some(ref from) = query.from {
+            qb.push(" AND created_time >= ").push_bind(from);
+        }
+        if let Some(ref to) = query.to {
+            qb.push(" AND created_time <= ").push_bind(to);
+        }
+        if let Some(ref state) = query.state {
+            qb.push(" AND state = ").push_bind(state);
+        }
+        if let Some(ref result) = query.result {
+            qb.push(" AND result = ").push_bind(result);
+        }
+        qb.push(" ORDER BY created_time");
+
+        let job_ids = qb.build_query_scalar::<i64>().fetch_all(&self.pool).await?;
+        let mut jobs = Vec::with_capacity(job_ids.len());
+        for id in job_ids {
+            jobs.push(self.fetch_job(id as u32).await?);
+        }
+        Ok(jobs)
+    }
+
+    async fn get_users(&self) -> sqlx::Result<Vec<User>> {
+        let users: Vec<(i64, String)> = sqlx::query_as("SELECT id, name FROM users ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(users
+            .into_iter()
+            .map(|(id, name)| User {
+                id: id as u32,
+                name,
+            })
+            .collect())
+    }
+
+    async fn user_name_exists(&self, name: &str, exclude_id: Option<u32>) -> sqlx::Result<bool> {
+        // No user has id -1
+        let exclude_id = exclude_id.map_or(-1, i64::from);
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE name = $1 AND id != $2)")
+            .bind(name)
+            .bind(exclude_id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn create_user(&self, name: &str) -> sqlx::Result<User> {
+        let max_id: Option<i64> = sqlx::query_scalar("SELECT MAX(id) FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+        let new_id = max_id.map_or(0, |id| id + 1);
+
+        sqlx::query("INSERT INTO users (id, name) VALUES ($1, $2)")
+            .bind(new_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(User {
+            id: new_id as u32,
+            name: name.to_string(),
+        })
+    }
+
+    async fn update_user(&self, id: u32, name: &str) -> sqlx::Result<User> {
+        sqlx::query("UPDATE users SET name = $1 WHERE id = $2")
+            .bind(name)
+            .bind(i64::from(id))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(User {
+            id,
+            name: name.to_string(),
+        })
+    }
+
+    async fn fetch_user_jobs(&self, user_id: u32) -> sqlx::Result<Vec<JobRecord>> {
+        let job_ids: Vec<i64> =
+            sqlx::query_scalar("SELECT id FROM jobs WHERE user_id = $1 ORDER BY created_time")
+                .bind(i64::from(user_id))
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut jobs = Vec::new();
+        for id in job_ids {
+            if let Ok(job) = self.fetch_job(id as u32).await {
+                jobs.push(job);
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    async fn import_data(
+        &self,
+        users: &[User],
+        jobs: &[JobRecord],
+        clear: bool,
+    ) -> sqlx::Result<ImportOutcome> {
+        let mut tx = self.pool.begin().await?;
+        if clear {
+            sqlx::query("TRUNCATE queue, job_case, jobs, users RESTART IDENTITY")
+                .execute(tx.as_mut())
+                .await?;
+        }
+
+        let mut outcome = ImportOutcome::default();
+        for user in users {
+            outcome.users.push(import_user(&mut tx, user).await?);
+        }
+        // Imported or already there, either way the jobs keep their user
+        let mut present = HashSet::new();
+        for user in users {
+            if user_present(&mut tx, user).await? {
+                present.insert(user.id);
+            }
+        }
+        for job in jobs {
+            outcome
+                .jobs
+                .push(if !present.contains(&job.submission.user_id) {
+                    JobImport::UserDiffers
+                } else if import_job(&mut tx, job).await? {
+                    JobImport::Imported
+                } else {
+                    JobImport::IdTaken
+                });
+        }
+
+        // Unlike SQLite, the sequence of new ids does not follow inserted ones
+        if outcome.jobs.contains(&JobImport::Imported) {
+            sqlx::query(
+                "SELECT setval(pg_get_serial_sequence('jobs', 'pk'), (SELECT MAX(pk) FROM jobs))",
+            )
+            .execute(tx.as_mut())
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn backup(&self, _file: &Path) -> anyhow::Result<()> {
+        bail!("Backups of PostgreSQL databases are taken with pg_dump")
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
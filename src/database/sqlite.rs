@@ -0,0 +1,1249 @@
+//! Storage in a single SQLite file, the default
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::bail;
+use async_trait::async_trait;
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous,
+};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+
+use super::{
+    DEFAULT_READ_CONNECTIONS, ImportOutcome, JobImport, Storage, lease_deadline, migrations,
+    queued_job, worker_status,
+};
+use crate::config::{DatabaseConfig, JournalMode};
+use crate::create_timestamp;
+use crate::queue::{QueuedJob, WorkerStatus};
+use crate::rate_limit::TokenBucket;
+use crate::routes::{CaseResult, JobRecord, JobSubmission, JobsQueryParams, User};
+
+const DATABASE_NAME: &str = "oj.sqlite3";
+
+pub fn get_db_path() -> PathBuf {
+    use directories::ProjectDirs;
+
+    let proj_dirs = ProjectDirs::from("", "", "oj").expect("Unable to find user directory");
+    let data_dir = proj_dirs.data_local_dir();
+
+    fs::create_dir_all(data_dir).expect("Failed to create local data dir");
+
+    data_dir.join(DATABASE_NAME)
+}
+
+/// Connection options shared by both pools of the configured database
+fn connect_options(config: &DatabaseConfig) -> anyhow::Result<SqliteConnectOptions> {
+    let options = match &config.path {
+        Some(url) if url.starts_with("sqlite:") => SqliteConnectOptions::from_str(url)?,
+        Some(path) => SqliteConnectOptions::new().filename(path),
+        None => SqliteConnectOptions::new().filename(get_db_path()),
+    };
+    let busy_timeout = Duration::from_millis(config.busy_timeout_ms.unwrap_or(2000));
+    Ok(options
+        .foreign_keys(true)
+        .busy_timeout(busy_timeout) // Timeout for lock contention
+        .synchronous(SqliteSynchronous::Normal)) // Balance between safety and performance
+}
+
+/// Path of the file of the configured database
+pub fn db_path(config: &DatabaseConfig) -> anyhow::Result<PathBuf> {
+    Ok(connect_options(config)?.get_filename().to_path_buf())
+}
+
+pub fn remove_db(config: &DatabaseConfig) -> anyhow::Result<()> {
+    let db_path = db_path(config)?;
+
+    // Remove WAL and SHM files (ignore errors as they might not exist)
+    let wal_path = format!("{}-wal", db_path.display());
+    let shm_path = format!("{}-shm", db_path.display());
+    let _ = fs::remove_file(wal_path);
+    let _ = fs::remove_file(shm_path);
+
+    // Remove main database file
+    if let Err(e) = std::fs::remove_file(&db_path) {
+        log::warn!("Unable to remove database at {}: {e}", db_path.display());
+    } else {
+        log::info!("Removed database at {}", db_path.display());
+    }
+    Ok(())
+}
+
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Opens the pool of connections writing to the database, creating it if needed,
+    /// and upgrades its schema
+    pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        let journal_mode = match config.journal_mode {
+            JournalMode::Delete => SqliteJournalMode::Delete,
+            JournalMode::Truncate => SqliteJournalMode::Truncate,
+            JournalMode::Persist => SqliteJournalMode::Persist,
+            JournalMode::Memory => SqliteJournalMode::Memory,
+            JournalMode::Wal => SqliteJournalMode::Wal,
+            JournalMode::Off => SqliteJournalMode::Off,
+        };
+        let options = connect_options(config)?
+            .create_if_missing(true)
+            .journal_mode(journal_mode);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections.unwrap_or(1).max(1))
+            .min_connections(0) // Allow pool to shrink when idle
+            .connect_with(options.clone())
+            .await?;
+
+        migrations::migrate(&pool).await?;
+
+        log::info!(
+            "Initialized database at {}",
+            options.get_filename().display()
+        );
+
+        Ok(Self { pool })
+    }
+
+    /// Opens a pool of read-only connections to the database `connect` set up
+    ///
+    /// In WAL mode, queries on these don't wait for the writes of the other pool.
+    pub async fn connect_read_only(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        let max_connections = config
+            .max_read_connections
+            .unwrap_or(DEFAULT_READ_CONNECTIONS)
+            .max(1);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .min_connections(0)
+            .connect_with(connect_options(config)?.read_only(true))
+            .await?;
+        Ok(Self { pool })
+    }
+
+    /// Get the next available user ID
+    async fn get_next_user_id(&self) -> sqlx::Result<u32> {
+        let max_id = sqlx::query!(
+            r#"
+            SELECT MAX(id) as max_id FROM users
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(max_id.max_id.map(|id| id + 1).unwrap_or(0) as u32)
+    }
+}
+
+/// Inserts a Queueing job with `len` Waiting cases on `conn`, returning its id
+async fn insert_job(
+    conn: &mut SqliteConnection,
+    body: &JobSubmission,
+    len: u32,
+) -> sqlx::Result<u32> {
+    let now = create_timestamp();
+
+    let result = sqlx::query!(
+        r#"
+    INSERT INTO jobs (user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time)
+    VALUES (?, ?, ?, ?, ?, 'Queueing', 'Waiting', 0.0, ?, ?)
+    "#,
+        body.user_id,
+        body.contest_id,
+        body.problem_id,
+        body.source_code,
+        body.language,
+        now,
+        now
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let pk = result.last_insert_rowid() as u32;
+    let job_id = pk - 1; // Since id is generated as pk - 1
+
+    for i in 0..len {
+        sqlx::query!(
+            r#"
+        INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb)
+        VALUES (?, ?, 'Waiting', 0, 0)
+        "#,
+            job_id,
+            i
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(job_id)
+}
+
+/// Reverts a job and its cases to Queueing on `conn`, returning the number of cases
+async fn revert_job(conn: &mut SqliteConnection, id: u32) -> sqlx::Result<usize> {
+    let now = create_timestamp();
+
+    // Revert job state, result and score
+    sqlx::query!(
+        r#"
+    UPDATE jobs
+    SET state = 'Queueing', result = 'Waiting', score = 0.0, updated_time = ?
+    WHERE id = ?
+    "#,
+        now,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // Revert each case
+    let reverted_cases = sqlx::query!(
+        r#"
+    UPDATE job_case
+    SET result = 'Waiting', time_us = 0, memory_kb = 0, score = 0.0, info = ''
+    WHERE job_id = ?
+    "#,
+        id
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    Ok(reverted_cases as usize)
+}
+
+/// Queues a job on `conn`, see `Storage::enqueue_job`
+async fn insert_queued(
+    conn: &mut SqliteConnection,
+    id: u32,
+    priority: u32,
+    user_id: u32,
+) -> sqlx::Result<()> {
+    let now = create_timestamp();
+    sqlx::query!(
+        r#"
+    INSERT INTO queue (job_id, enqueued_time, priority, user_id, round)
+    VALUES (?, ?, ?, ?, MAX(
+        COALESCE((SELECT MIN(round) FROM queue WHERE priority = ? AND lease_owner IS NULL), 0),
+        COALESCE((SELECT MAX(round) + 1 FROM queue WHERE priority = ? AND user_id = ?), 0)
+    ))
+    "#,
+        id,
+        now,
+        priority,
+        user_id,
+        priority,
+        priority,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether `max_queued` jobs are waiting to be claimed, false without a limit
+async fn queue_full(conn: &mut SqliteConnection, max_queued: Option<u32>) -> sqlx::Result<bool> {
+    let Some(max_queued) = max_queued else {
+        return Ok(false);
+    };
+    let count = sqlx::query_scalar!(
+        r#"
+    SELECT COUNT(*) AS "count!: i64" FROM queue WHERE lease_owner IS NULL
+    "#
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(count >= i64::from(max_queued))
+}
+
+/// Writes the result of a job and its cases on `conn`, typically in a transaction
+async fn write_result(
+    conn: &mut SqliteConnection,
+    id: u32,
+    result: &JobRecord,
+) -> sqlx::Result<()> {
+    let now = create_timestamp();
+
+    // Update job record
+    sqlx::query!(
+        r#"
+    UPDATE jobs
+    SET state = ?, result = ?, score = ?, updated_time = ?
+    WHERE id = ?
+    "#,
+        result.state,
+        result.result,
+        result.score,
+        now,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // Upsert all case results at once, so that no row is ever missing
+    if !result.cases.is_empty() {
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb, score, info) ",
+        );
+        qb.push_values(&result.cases, |mut row, case| {
+            row.push_bind(id)
+                .push_bind(case.id)
+                .push_bind(&case.result)
+                .push_bind(case.time)
+                .push_bind(case.memory) // memory already in KB
+                .push_bind(case.score)
+                .push_bind(&case.info);
+        });
+        qb.push(
+            r#"
+    ON CONFLICT (job_id, case_index) DO UPDATE
+    SET result = excluded.result, time_us = excluded.time_us, memory_kb = excluded.memory_kb,
+        score = excluded.score, info = excluded.info
+    "#,
+        );
+        qb.build().execute(&mut *conn).await?;
+    }
+
+    // Remove cases beyond the result, if it has fewer
+    let len = result.cases.len() as u32;
+    sqlx::query!(
+        r#"
+    DELETE FROM job_case WHERE job_id = ? AND case_index >= ?
+    "#,
+        id,
+        len
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Inserts a user with its id, returning false if the id or name is taken
+async fn import_user(conn: &mut SqliteConnection, user: &User) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+    INSERT OR IGNORE INTO users (id, name) VALUES (?, ?)
+    "#,
+        user.id,
+        user.name
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Whether a user with the id and name of `user` exists
+async fn user_present(conn: &mut SqliteConnection, user: &User) -> sqlx::Result<bool> {
+    let present = sqlx::query_scalar!(
+        r#"
+    SELECT EXISTS(SELECT 1 FROM users WHERE id = ? AND name = ?) AS "present!: bool"
+    "#,
+        user.id,
+        user.name
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(present)
+}
+
+/// Inserts a job with its id, times and cases as they are, returning false if the id
+/// is taken
+async fn import_job(conn: &mut SqliteConnection, job: &JobRecord) -> sqlx::Result<bool> {
+    let pk = job.id + 1; // Since id is generated as pk - 1
+    let submission = &job.submission;
+
+    let result = sqlx::query!(
+        r#"
+    INSERT OR IGNORE INTO jobs (pk, user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+    "#,
+        pk,
+        submission.user_id,
+        submission.contest_id,
+        submission.problem_id,
+        submission.source_code,
+        submission.language,
+        job.state,
+        job.result,
+        job.score,
+        job.created_time,
+        job.updated_time
+    )
+    .execute(&mut *conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    for case in &job.cases {
+        sqlx::query!(
+            r#"
+        INSERT INTO job_case (job_id, case_index, result, time_us, memory_kb, score, info)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+            job.id,
+            case.id,
+            case.result,
+            case.time,
+            case.memory,
+            case.score,
+            case.info
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(true)
+}
+
+/// Removes all users, jobs and queued jobs, so that the next job gets id 0
+async fn clear_data(conn: &mut SqliteConnection) -> sqlx::Result<()> {
+    // Referencing tables first, for the foreign keys
+    sqlx::query!("DELETE FROM queue")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM job_case")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM jobs").execute(&mut *conn).await?;
+    sqlx::query!("DELETE FROM users")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn create_job(&self, body: &JobSubmission, len: u32) -> sqlx::Result<u32> {
+        let mut tx = self.pool.begin().await?;
+        let job_id = insert_job(&mut tx, body, len).await?;
+        tx.commit().await?;
+        Ok(job_id)
+    }
+
+    async fn create_queued_job(
+        &self,
+        body: &JobSubmission,
+        len: u32,
+        priority: u32,
+        max_queued: Option<u32>,
+    ) -> sqlx::Result<Option<u32>> {
+        // Take the write lock up front, so that no job is queued after counting
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        if queue_full(&mut tx, max_queued).await? {
+            return Ok(None);
+        }
+        let job_id = insert_job(&mut tx, body, len).await?;
+        insert_queued(&mut tx, job_id, priority, body.user_id).await?;
+        tx.commit().await?;
+        Ok(Some(job_id))
+    }
+
+    async fn find_job(&self, id: u32) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"
+        SELECT 1 as "exists_flag: i32" FROM jobs WHERE id = ?
+        "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(result.is_some())
+    }
+
+    async fn find_user(&self, id: u32) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"
+        SELECT 1 as "exists_flag: i32" FROM users WHERE id = ?
+        "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(result.is_some())
+    }
+
+    async fn fetch_job(&self, id: u32) -> sqlx::Result<JobRecord> {
+        log::debug!("Trying to fetch job {id} full record from database");
+        let job_data = sqlx::query!(
+            r#"
+        SELECT user_id, contest_id, problem_id, source_code, language, state, result, score, created_time, updated_time
+        FROM jobs
+        WHERE id = ?
+        "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let submission = JobSubmission {
+            user_id: job_data.user_id as u32,
+            contest_id: job_data.contest_id as u32,
+            problem_id: job_data.problem_id as u32,
+            source_code: job_data.source_code,
+            language: job_data.language,
+        };
+
+        // Fetch case results
+        let case_data = sqlx::query!(
+            r#"
+        SELECT case_index, result, time_us, memory_kb, score, info
+        FROM job_case
+        WHERE job_id = ?
+        ORDER BY case_index
+        "#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Pre-allocate the cases vector with the exact size needed
+        let mut cases = Vec::with_capacity(case_data.len());
+        for case in case_data {
+            cases.push(CaseResult {
+                id: case.case_index as u32,
+                result: case.result,
+                time: case.time_us as u32,
+                memory: case.memory_kb as u32, // memory in KB
+                score: case.score,
+                info: case.info.unwrap_or_default(),
+            });
+        }
+
+        log::debug!("Fetched job {id} full record from database");
+        Ok(JobRecord {
+            id,
+            created_time: job_data.created_time,
+            updated_time: job_data.updated_time,
+            submission,
+            state: job_data.state,
+            result: job_data.result,
+            score: job_data.score,
+            cases,
+        })
+    }
+
+    async fn update_job_to_running(&self, id: u32) -> sqlx::Result<()> {
+        let now = create_timestamp();
+        let mut tx = self.pool.begin().await?;
+
+        // Update job state and result to Running
+        sqlx::query!(
+            r#"
+        UPDATE jobs
+        SET state = 'Running', result = 'Running', updated_time = ?
+        WHERE id = ?
+        "#,
+            now,
+            id
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        // Update case 0 result to Running
+        sqlx::query!(
+            r#"
+        UPDATE job_case
+        SET result = 'Running'
+        WHERE job_id = ? AND case_index = 0
+        "#,
+            id
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_job_to_canceled(&self, id: u32) -> sqlx::Result<()> {
+        let now = create_timestamp();
+        let mut tx = self.pool.begin().await?;
+
+        // Update job state and result to Canceled
+        sqlx::query!(
+            r#"
+        UPDATE jobs
+        SET state = 'Canceled', result = 'Skipped', updated_time = ?
+        WHERE id = ?
+        "#,
+            now,
+            id
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        // Update all case results to Canceled
+        sqlx::query!(
+            r#"
+        UPDATE job_case
+        SET result = 'Skipped'
+        WHERE job_id = ?
+        "#,
+            id
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn revert_job_to_queueing(&self, id: u32) -> sqlx::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        let reverted_cases = revert_job(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(reverted_cases)
+    }
+
+    async fn requeue_job(
+        &self,
+        id: u32,
+        priority: u32,
+        user_id: u32,
+        max_queued: Option<u32>,
+    ) -> sqlx::Result<Option<usize>> {
+        // Take the write lock up front, so that no job is queued after counting
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        if queue_full(&mut tx, max_queued).await? {
+            return Ok(None);
+        }
+        let reverted_cases = revert_job(&mut tx, id).await?;
+        insert_queued(&mut tx, id, priority, user_id).await?;
+        tx.commit().await?;
+        Ok(Some(reverted_cases))
+    }
+
+    async fn save_result(&self, id: u32, result: &JobRecord) -> sqlx::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        write_result(&mut tx, id, result).await?;
+        tx.commit().await
+    }
+
+    async fn enqueue_job(&self, id: u32, priority: u32, user_id: u32) -> sqlx::Result<()> {
+        insert_queued(&mut *self.pool.acquire().await?, id, priority, user_id).await
+    }
+
+    async fn claim_job(&self, owner: &str, lease: Duration) -> sqlx::Result<Option<u32>> {
+        let expires = lease_deadline(lease);
+        // A single statement, which SQLite runs with the write lock held
+        let claimed = sqlx::query!(
+            r#"
+        UPDATE queue
+        SET lease_owner = ?, lease_expires = ?
+        WHERE job_id = (
+            SELECT job_id FROM queue
+            WHERE lease_owner IS NULL
+                AND NOT EXISTS (SELECT 1 FROM settings WHERE key = 'queue_paused' AND value = 'true')
+            ORDER BY priority, round, enqueued_time, job_id
+            LIMIT 1
+        )
+        RETURNING job_id
+        "#,
+            owner,
+            expires
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(claimed.map(|row| row.job_id as u32))
+    }
+
+    async fn queue_position(&self, id: u32) -> sqlx::Result<Option<u32>> {
+        let position = sqlx::query_scalar!(
+            r#"
+        SELECT COUNT(*) AS "position!: i64"
+        FROM queue AS q, queue AS t
+        WHERE t.job_id = ? AND t.lease_owner IS NULL AND q.lease_owner IS NULL
+            AND (q.priority, q.round, q.enqueued_time, q.job_id)
+                <= (t.priority, t.round, t.enqueued_time, t.job_id)
+        "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((position > 0).then_some(position as u32))
+    }
+
+    async fn renew_lease(&self, id: u32, owner: &str, lease: Duration) -> sqlx::Result<bool> {
+        let expires = lease_deadline(lease);
+        let renewed = sqlx::query!(
+            r#"
+        UPDATE queue SET lease_expires = ? WHERE job_id = ? AND lease_owner = ?
+        "#,
+            expires,
+            id,
+            owner
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(renewed > 0)
+    }
+
+    async fn finish_job(
+        &self,
+        id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> sqlx::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let released = sqlx::query!(
+            r#"
+        DELETE FROM queue WHERE job_id = ? AND lease_owner = ?
+        "#,
+            id,
+            owner
+        )
+        .execute(tx.as_mut())
+        .await?
+        .rows_affected();
+        if released == 0 {
+            return Ok(false);
+        }
+
+        match result {
+            Some(result) => write_result(&mut tx, id, result).await?,
+            None => {
+                let now = create_timestamp();
+                sqlx::query!(
+                    r#"
+                UPDATE jobs
+                SET state = 'Finished', result = 'System Error', updated_time = ?
+                WHERE id = ?
+                "#,
+                    now,
+                    id
+                )
+                .execute(tx.as_mut())
+                .await?;
+                sqlx::query!(
+                    r#"
+                UPDATE job_case
+                SET result = 'Skipped'
+                WHERE job_id = ? AND result IN ('Waiting', 'Running')
+                "#,
+                    id
+                )
+                .execute(tx.as_mut())
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn save_case_result(
+        &self,
+        id: u32,
+        owner: &str,
+        case: &CaseResult,
+    ) -> sqlx::Result<bool> {
+        let saved = sqlx::query!(
+            r#"
+        UPDATE job_case
+        SET result = ?, time_us = ?, memory_kb = ?, score = ?, info = ?
+        WHERE job_id = ? AND case_index = ?
+            AND EXISTS (SELECT 1 FROM queue WHERE job_id = ? AND lease_owner = ?)
+        "#,
+            case.result,
+            case.time,
+            case.memory,
+            case.score,
+            case.info,
+            id,
+            case.id,
+            id,
+            owner
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(saved > 0)
+    }
+
+    async fn dequeue_unclaimed_job(&self, id: u32) -> sqlx::Result<bool> {
+        let dequeued = sqlx::query!(
+            r#"
+        DELETE FROM queue WHERE job_id = ? AND lease_owner IS NULL
+        "#,
+            id
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(dequeued > 0)
+    }
+
+    async fn dequeue_unclaimed_jobs(&self) -> sqlx::Result<Vec<u32>> {
+        let dequeued = sqlx::query!(
+            r#"
+        DELETE FROM queue WHERE lease_owner IS NULL RETURNING job_id
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(dequeued.into_iter().map(|row| row.job_id as u32).collect())
+    }
+
+    async fn count_unclaimed_jobs(&self) -> sqlx::Result<u32> {
+        let count = sqlx::query_scalar!(
+            r#"
+        SELECT COUNT(*) AS "count!: i64" FROM queue WHERE lease_owner IS NULL
+        "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u32)
+    }
+
+    async fn fetch_queued_jobs(&self) -> sqlx::Result<Vec<QueuedJob>> {
+        let rows = sqlx::query!(
+            r#"
+        SELECT job_id, priority, user_id, enqueued_time FROM queue
+        WHERE lease_owner IS NULL
+        ORDER BY priority, round, enqueued_time, job_id
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = chrono::Utc::now();
+        let jobs = rows
+            .into_iter()
+            .map(|row| {
+                queued_job(
+                    row.job_id,
+                    row.priority,
+                    row.user_id,
+                    row.enqueued_time,
+                    now,
+                )
+            })
+            .collect();
+        Ok(jobs)
+    }
+
+    async fn requeue_expired_jobs(&self) -> sqlx::Result<Vec<u32>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let expired = sqlx::query!(
+            r#"
+        UPDATE queue
+        SET lease_owner = NULL, lease_expires = NULL
+        WHERE lease_expires < ?
+        RETURNING job_id
+        "#,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut ids = Vec::with_capacity(expired.len());
+        for row in expired {
+            let id = row.job_id as u32;
+            self.revert_job_to_queueing(id).await?;
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    async fn update_worker(&self, name: &str, job_id: Option<u32>) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let case_index = job_id.map(|_| 0);
+        sqlx::query!(
+            r#"
+        INSERT INTO workers (name, job_id, case_index, last_seen) VALUES (?, ?, ?, ?)
+        ON CONFLICT (name) DO UPDATE
+        SET job_id = excluded.job_id, case_index = excluded.case_index, last_seen = excluded.last_seen
+        "#,
+            name,
+            job_id,
+            case_index,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_worker_idle(&self, name: &str, seen_after: i64) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query!(
+            r#"
+        INSERT INTO workers (name, job_id, case_index, last_seen) VALUES (?, NULL, NULL, ?)
+        ON CONFLICT (name) DO UPDATE
+        SET job_id = NULL, case_index = NULL, last_seen = excluded.last_seen
+        WHERE workers.job_id IS NOT NULL OR workers.last_seen < ?
+        "#,
+            name,
+            now,
+            seen_after
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn advance_worker(&self, name: &str, job_id: u32, case_index: u32) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query!(
+            r#"
+        UPDATE workers
+        SET case_index = MIN(? + 1, (SELECT MAX(case_index) FROM job_case WHERE job_id = ?)),
+            last_seen = ?
+        WHERE name = ? AND job_id = ?
+        "#,
+            case_index,
+            job_id,
+            now,
+            name,
+            job_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn touch_worker(&self, name: &str) -> sqlx::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query!(
+            r#"
+        UPDATE workers SET last_seen = ? WHERE name = ?
+        "#,
+            now,
+            name
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn fetch_workers(&self, seen_after: i64) -> sqlx::Result<Vec<WorkerStatus>> {
+        let rows = sqlx::query!(
+            r#"
+        SELECT name AS "name!", job_id, case_index, last_seen FROM workers ORDER BY name
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let workers = rows
+            .into_iter()
+            .map(|row| {
+                worker_status(
+                    row.name,
+                    row.job_id,
+                    row.case_index,
+                    row.last_seen,
+                    seen_after,
+                )
+            })
+            .collect();
+        Ok(workers)
+    }
+
+    async fn set_queue_paused(&self, paused: bool) -> sqlx::Result<()> {
+        let value = paused.to_string();
+        sqlx::query!(
+            r#"
+        INSERT INTO settings (key, value) VALUES ('queue_paused', ?)
+        ON CONFLICT (key) DO UPDATE SET value = excluded.value
+        "#,
+            value
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn queue_paused(&self) -> sqlx::Result<bool> {
+        let value = sqlx::query_scalar!(
+            r#"
+        SELECT value FROM settings WHERE key = 'queue_paused'
+        "#
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(value.is_some_and(|value| value == "true"))
+    }
+
+    async fn take_rate_tokens(&self, buckets: &[TokenBucket], now: i64) -> sqlx::Result<Vec<f64>> {
+        // The first statement takes the write lock, so that concurrent servers see each
+        // other's takes
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"
+        DELETE FROM rate_limits
+        WHERE tokens + rate * MAX(? - updated, 0) / 1000.0 >= capacity
+        "#,
+            now
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        let mut tokens = Vec::with_capacity(buckets.len());
+        for bucket in buckets {
+            let refilled = sqlx::query_scalar!(
+                r#"
+            INSERT INTO rate_limits (key, tokens, capacity, rate, updated)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (key) DO UPDATE SET
+                tokens = MIN(
+                    excluded.capacity,
+                    rate_limits.tokens
+                        + excluded.rate * MAX(excluded.updated - rate_limits.updated, 0) / 1000.0
+                ),
+                capacity = excluded.capacity,
+                rate = excluded.rate,
+                updated = MAX(excluded.updated, rate_limits.updated)
+            RETURNING tokens
+            "#,
+                bucket.key,
+                bucket.capacity,
+                bucket.capacity,
+                bucket.rate,
+                now
+            )
+            .fetch_one(tx.as_mut())
+            .await?;
+            tokens.push(refilled);
+        }
+
+        if tokens.iter().all(|&tokens| tokens >= 1.0) {
+            for bucket in buckets {
+                sqlx::query!(
+                    r#"
+                UPDATE rate_limits SET tokens = tokens - 1 WHERE key = ?
+                "#,
+                    bucket.key
+                )
+                .execute(tx.as_mut())
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(tokens)
+    }
+
+    async fn fetch_jobs_by_query(&self, query: &JobsQueryParams) -> sqlx::Result<Vec<JobRecord>> {
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT id FROM jobs WHERE 1=1");
+
+        if let Some(user_id) = query.user_id {
+            qb.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(ref user_name) = query.user_name {
+            qb.push(" AND user_id IN (SELECT id FROM users WHERE name = ")
+                .push_bind(user_name)
+                .push(")");
+        }
+        if let Some(contest_id) = query.contest_id {
+            qb.push(" AND contest_id = ").push_bind(contest_id);
+        }
+        if let Some(problem_id) = query.problem_id {
+            qb.push(" AND problem_id = ").push_bind(problem_id);
+        }
+        if let Some(ref language) = query.language {
+            qb.push(" AND language = ").push_bind(language);
+        }
+        if let Some(ref from) = query.from {
+            qb.push(" AND created_time >= ").push_bind(from);
+        }
+        if let Some(ref to) = query.to {
+            qb.push(" AND created_time <= ").push_bind(to);
+        }
+        if let Some(ref state) = query.state {
+            qb.push(" AND state = ").push_bind(state);
+        }
+        if let Some(ref result) = query.result {
+            qb.push(" AND result = ").push_bind(result);
+        }
+        qb.push(" ORDER BY created_time");
+
+        let job_ids = qb.build_query_as::<(u32,)>().fetch_all(&self.pool).await?;
+        let mut jobs = Vec::new();
+
+        for (id,) in job_ids {
+            jobs.push(self.fetch_job(id).await?);
+        }
+        Ok(jobs)
+    }
+
+    async fn get_users(&self) -> sqlx::Result<Vec<User>> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+        SELECT id as "id: u32", name
+        FROM users
+        ORDER BY id
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    async fn user_name_exists(&self, name: &str, exclude_id: Option<u32>) -> sqlx::Result<bool> {
+        if let Some(id) = exclude_id {
+            let result = sqlx::query!(
+                r#"
+            SELECT 1 as "exists_flag: i32" FROM users WHERE name = ? AND id != ?
+            "#,
+                name,
+                id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(result.is_some())
+        } else {
+            let result = sqlx::query!(
+                r#"
+            SELECT 1 as "exists_flag: i32" FROM users WHERE name = ?
+            "#,
+                name
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(result.is_some())
+        }
+    }
+
+    async fn create_user(&self, name: &str) -> sqlx::Result<User> {
+        let new_id = self.get_next_user_id().await?;
+
+        sqlx::query!(
+            r#"
+        INSERT INTO users (id, name) VALUES (?, ?)
+        "#,
+            new_id,
+            name
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(User {
+            id: new_id,
+            name: name.to_string(),
+        })
+    }
+
+    async fn update_user(&self, id: u32, name: &str) -> sqlx::Result<User> {
+        sqlx::query!(
+            r#"
+        UPDATE users SET name = ? WHERE id = ?
+        "#,
+            name,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(User {
+            id,
+            name: name.to_string(),
+        })
+    }
+
+    async fn fetch_user_jobs(&self, user_id: u32) -> sqlx::Result<Vec<JobRecord>> {
+        let job_ids = sqlx::query!(
+            r#"
+        SELECT id FROM jobs WHERE user_id = ? ORDER BY created_time
+        "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut jobs = Vec::new();
+        for row in job_ids {
+            if let Ok(job) = self.fetch_job(row.id as u32).await {
+                jobs.push(job);
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    async fn import_data(
+        &self,
+        users: &[User],
+        jobs: &[JobRecord],
+        clear: bool,
+    ) -> sqlx::Result<ImportOutcome> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        if clear {
+            clear_data(&mut tx).await?;
+        }
+
+        let mut outcome = ImportOutcome::default();
+        for user in users {
+            outcome.users.push(import_user(&mut tx, user).await?);
+        }
+        // Imported or already there, either way the jobs keep their user
+        let mut present = HashSet::new();
+        for user in users {
+            if user_present(&mut tx, user).await? {
+                present.insert(user.id);
+            }
+        }
+        for job in jobs {
+            outcome
+                .jobs
+                .push(if !present.contains(&job.submission.user_id) {
+                    JobImport::UserDiffers
+                } else if import_job(&mut tx, job).await? {
+                    JobImport::Imported
+                } else {
+                    JobImport::IdTaken
+                });
+        }
+
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    /// `VACUUM INTO` reads the database in a single transaction, so writers carry on
+    /// meanwhile. The snapshot is written next to `file` first, so that `file` only
+    /// ever appears complete.
+    async fn backup(&self, file: &Path) -> anyhow::Result<()> {
+        if file.exists() {
+            bail!("{} already exists", file.display());
+        }
+        let partial = PathBuf::from(format!("{}.partial", file.display()));
+        let _ = fs::remove_file(&partial);
+
+        sqlx::query("VACUUM INTO ?")
+            .bind(partial.to_string_lossy())
+            .execute(&self.pool)
+            .await?;
+        fs::rename(&partial, file)?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
@@ -0,0 +1,171 @@
+//! Startup of the workers judging jobs, shared by the server and `oj-judge`
+
+use std::process::Command;
+use std::sync::Arc;
+
+use anyhow::bail;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{JudgeArgs, LanguageConfig, ProblemConfig, SandboxBackendKind, ServerConfig};
+use crate::sandbox::{
+    ArtifactStore, CheckerCache, CompileCache, Sandbox, SelfTestStatus, assign_cpu_sets,
+    format_self_test_table, seccomp, self_test,
+};
+use crate::worker::{JobSource, worker};
+
+/// Check if a command exists in the system PATH
+pub fn check_command_exists(command: &str) -> bool {
+    Command::new("which")
+        .arg(command)
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Sandbox settings of the workers of this process
+pub struct JudgeEnvironment {
+    pub backend: SandboxBackendKind,
+    /// Sandbox id of the first worker, the others following it
+    pub first_sandbox_id: u8,
+    /// CPU set of each worker, starting from the first, empty when not pinned
+    pub cpu_sets: Vec<Vec<usize>>,
+}
+
+impl JudgeEnvironment {
+    /// Checks that the tools the sandboxes need exist and assigns CPUs to the workers
+    pub fn prepare(
+        args: &JudgeArgs,
+        server_config: &ServerConfig,
+        languages: &LanguageConfig,
+    ) -> anyhow::Result<Self> {
+        let backend = args
+            .sandbox_backend
+            .unwrap_or(server_config.sandbox_backend);
+        match backend {
+            SandboxBackendKind::Isolate => {
+                if !check_command_exists("isolate") {
+                    bail!(
+                        "Required command 'isolate' not found. Please check out installation guide."
+                    );
+                }
+            }
+            SandboxBackendKind::Process => {
+                log::warn!("WARNING: Using the INSECURE process sandbox backend!");
+                log::warn!(
+                    "Submissions run unconfined on this machine, use it for development only."
+                );
+            }
+        }
+
+        let Some(first_sandbox_id) = args
+            .sandbox_offset
+            .checked_add(1)
+            .filter(|first| first.checked_add(args.threads.saturating_sub(1)).is_some())
+        else {
+            bail!("Sandbox ids above 255 are not supported, lower --sandbox-offset or --threads");
+        };
+
+        // Programs of languages with a seccomp policy are started through the wrapper
+        if languages.iter().any(|l| l.seccomp.is_some())
+            && let Err(e) = seccomp::wrapper_path()
+        {
+            bail!("{e}. Please build it along with this binary.");
+        }
+
+        let cpu_sets = match &server_config.cpu_pinning {
+            Some(pinning) => assign_cpu_sets(pinning, args.threads)
+                .map_err(|e| anyhow::anyhow!("Failed to pin workers to CPUs: {e}"))?,
+            None => vec![Vec::new(); args.threads as usize],
+        };
+
+        Ok(Self {
+            backend,
+            first_sandbox_id,
+            cpu_sets,
+        })
+    }
+
+    /// Runs the self-test in the sandbox of the first worker before the worker uses it
+    ///
+    /// With `doctor`, prints the results and exits. Otherwise logs them and exits if
+    /// a check failed.
+    pub fn self_test(&self, languages: &LanguageConfig, doctor: bool) {
+        log::info!("Running self-test of the judging environment...");
+        let cpus = self.cpu_sets.first().cloned().unwrap_or_default();
+        let checks = match self_test(self.backend, self.first_sandbox_id, cpus, languages) {
+            Ok(checks) => checks,
+            Err(e) => {
+                log::error!("Failed to run self-test: {e}");
+                std::process::exit(1);
+            }
+        };
+        let table = format_self_test_table(&checks);
+        let passed = checks.iter().all(|c| c.status != SelfTestStatus::Fail);
+        if doctor {
+            print!("{table}");
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        for line in table.lines() {
+            if passed {
+                log::info!("{line}");
+            } else {
+                log::error!("{line}");
+            }
+        }
+        if !passed {
+            log::error!("Self-test failed, fix the environment or pass --skip-self-test");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compiles the checkers of all problems up front, so that the first jobs don't have
+/// to wait for them
+pub fn build_checker_cache(
+    problems: &ProblemConfig,
+    languages: &LanguageConfig,
+) -> anyhow::Result<CheckerCache> {
+    let checker_cache = CheckerCache::build()?;
+    for problem in problems.iter().filter(|p| p.misc.checker.is_some()) {
+        if checker_cache.prepare(problem.clone(), languages).is_err() {
+            log::warn!(
+                "Checker of problem {} failed to compile, its jobs will fail",
+                problem.id
+            );
+        }
+    }
+    Ok(checker_cache)
+}
+
+/// Builds a sandbox for each worker and spawns the workers claiming jobs from `source`
+pub fn spawn_workers<S: JobSource>(
+    workers: &mut JoinSet<anyhow::Result<()>>,
+    environment: JudgeEnvironment,
+    source: Arc<S>,
+    checker_cache: Arc<CheckerCache>,
+    compile_cache: Arc<CompileCache>,
+    artifact_store: Arc<ArtifactStore>,
+    shutdown_token: CancellationToken,
+) -> anyhow::Result<()> {
+    for (i, cpus) in (environment.first_sandbox_id..=u8::MAX).zip(environment.cpu_sets) {
+        if !cpus.is_empty() {
+            log::info!("Worker {i} runs programs on CPUs {cpus:?}");
+        }
+        let sandbox = Sandbox::build(
+            i,
+            environment.backend,
+            cpus,
+            compile_cache.clone(),
+            artifact_store.clone(),
+        )?;
+        workers.spawn(worker(
+            i,
+            source.clone(),
+            checker_cache.clone(),
+            sandbox,
+            shutdown_token.clone(),
+        ));
+    }
+    Ok(())
+}
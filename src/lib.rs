@@ -1,6 +1,9 @@
 pub mod config;
 pub mod database;
+pub mod judge;
 pub mod queue;
+pub mod rate_limit;
+pub mod remote;
 pub mod routes;
 pub mod sandbox;
 pub mod web_server;
@@ -1,24 +1,17 @@
-use std::process::Command;
 use std::sync::Arc;
 
 use clap::Parser;
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 
-use oj::config::{CliArgs, Config};
+use oj::config::{CliArgs, CliCommand, Config};
 use oj::database as db;
+use oj::judge::{JudgeEnvironment, build_checker_cache, check_command_exists, spawn_workers};
 use oj::queue::JobQueue;
+use oj::routes::ProblemFiles;
+use oj::sandbox::{ArtifactStore, CompileCache};
 use oj::web_server::build_server;
-use oj::worker::worker;
-
-/// Check if a command exists in the system PATH
-fn check_command_exists(command: &str) -> bool {
-    Command::new("which")
-        .arg(command)
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
-}
+use oj::worker::LocalJobs;
 
 /// Check if the current user is root and warn if so
 fn check_running_user() {
@@ -34,25 +27,11 @@ fn check_running_user() {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let cli = CliArgs::parse();
-    let n_threads = cli.threads;
-    if n_threads == 0 {
-        panic!("The number of worker threads must not be 0");
-    }
+    let n_threads = cli.judge.threads;
     let log_level = if cli.verbose { "debug" } else { "info" };
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or(log_level));
 
-    // Check if required commands exist
-    if !check_command_exists("isolate") {
-        log::error!("Required command 'isolate' not found. Please check out installation guide.");
-        std::process::exit(1);
-    }
-
-    if !check_command_exists("sqlite3") {
-        log::error!("Required command 'sqlite3' not found. Please install SQLite3.");
-        std::process::exit(1);
-    }
-
     // Check running user and warn if running as root
     check_running_user();
 
@@ -60,34 +39,136 @@ async fn main() -> std::io::Result<()> {
         server: server_config,
         problems: problem_config,
         languages: language_config,
+        database: database_config,
     } = cli.read_config()?;
 
-    let db_path = db::get_db_path();
-    if cli.flush_data {
-        db::remove_db(&db_path);
+    // Check if required commands exist, sqlite3 being needed with SQLite only
+    if !database_config.is_postgres() && !check_command_exists("sqlite3") {
+        log::error!("Required command 'sqlite3' not found. Please install SQLite3.");
+        std::process::exit(1);
+    }
+
+    // Data management commands only touch the database
+    let data_command = match &cli.command {
+        Some(CliCommand::Backup { file }) => {
+            Some(db::backup_database(&database_config, file).await)
+        }
+        Some(CliCommand::Restore { file }) => {
+            Some(db::restore_database(&database_config, file).await)
+        }
+        Some(CliCommand::Export {
+            file,
+            contest,
+            from,
+            to,
+        }) => {
+            let filter = db::ExportFilter {
+                contest_id: *contest,
+                from: from.clone(),
+                to: to.clone(),
+            };
+            Some(db::export_database(&database_config, &problem_config, &filter, file).await)
+        }
+        Some(CliCommand::Import { file, mode }) => {
+            Some(db::import_database(&database_config, file, *mode).await)
+        }
+        _ => None,
+    };
+    if let Some(result) = data_command {
+        if let Err(e) = result {
+            log::error!("{e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
     }
-    let db_pool = db::init_db(&db_path)
-        .await
-        .expect("Failed to initialize database");
+
+    // Without workers the server only produces jobs, which `oj-judge` consumes
+    let doctor = cli.command == Some(CliCommand::Doctor);
+    let environment = if n_threads > 0 || doctor {
+        let environment =
+            match JudgeEnvironment::prepare(&cli.judge, &server_config, &language_config) {
+                Ok(environment) => environment,
+                Err(e) => {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                }
+            };
+        if doctor || !cli.judge.skip_self_test {
+            environment.self_test(&language_config, doctor);
+        }
+        Some(environment)
+    } else {
+        log::info!("No judging threads, jobs are left to oj-judge");
+        None
+    };
+
+    if cli.flush_data
+        && let Err(e) = db::remove_db(&database_config).await
+    {
+        log::warn!("Unable to remove database: {e}");
+    }
+    let pools = async {
+        let db_pool = db::init_db(&database_config).await?;
+        let read_pool = db::init_read_pool(&database_config).await?;
+        anyhow::Ok((db_pool, read_pool))
+    };
+    let (db_pool, read_pool) = match pools.await {
+        Ok(pools) => pools,
+        Err(e) => {
+            log::error!("Failed to initialize database: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let compile_cache_size = server_config.compile_cache_size_mb.unwrap_or(512) * 1024 * 1024;
+    let compile_cache =
+        CompileCache::build(compile_cache_size).expect("Failed to create compile cache");
+    let artifact_store = ArtifactStore::build(server_config.artifacts.clone())
+        .expect("Failed to create artifact store");
 
     let problem_config = Arc::new(problem_config);
     let language_config = Arc::new(language_config);
-    let db_pool = Arc::new(db_pool);
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(db_pool.clone(), &server_config.queue));
+    let compile_cache = Arc::new(compile_cache);
+    let artifact_store = Arc::new(artifact_store);
+    // Remote judge nodes download problem data by content hash
+    let problem_files = Arc::new(match server_config.judge_secret {
+        Some(_) => ProblemFiles::build(&problem_config),
+        None => ProblemFiles::default(),
+    });
     let shutdown_token = CancellationToken::new();
 
     // ======= PREPARATION END, EXECUTION START =======
 
+    if server_config.backup.is_some() && database_config.is_postgres() {
+        log::warn!("Scheduled backups are skipped for PostgreSQL databases, use pg_dump");
+    } else if let Some(backup_config) = server_config.backup.clone() {
+        let read_pool = read_pool.clone();
+        let token = shutdown_token.clone();
+        tokio::spawn(async move {
+            db::run_scheduled_backups(read_pool.as_ref(), backup_config, token).await;
+        });
+    }
+
     let mut workers = JoinSet::new();
-    for i in 1..=n_threads {
-        workers.spawn(worker(
-            i,
-            problem_config.clone(),
-            language_config.clone(),
-            db_pool.clone(),
-            job_queue.clone(),
+    if let Some(environment) = environment {
+        let checker_cache = build_checker_cache(&problem_config, &language_config)
+            .expect("Failed to create checker cache");
+        let source = Arc::new(LocalJobs {
+            queue: job_queue.clone(),
+            problems: problem_config.clone(),
+            languages: language_config.clone(),
+        });
+        spawn_workers(
+            &mut workers,
+            environment,
+            source,
+            Arc::new(checker_cache),
+            compile_cache.clone(),
+            artifact_store.clone(),
             shutdown_token.clone(),
-        ));
+        )
+        .expect("Failed to initialize workers");
     }
 
     let server = build_server(
@@ -95,7 +176,11 @@ async fn main() -> std::io::Result<()> {
         problem_config,
         language_config,
         db_pool,
+        read_pool,
         job_queue,
+        compile_cache,
+        artifact_store,
+        problem_files,
     )
     .expect("Failed to build server");
 
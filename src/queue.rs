@@ -1,42 +1,363 @@
-use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Notify;
 
-use crate::routes::JobMessage;
+use crate::config::{
+    LanguageConfig, OneLanguageConfig, OneProblemConfig, ProblemConfig, QueueConfig,
+};
+use crate::database::Storage;
+use crate::routes::{CaseResult, JobRecord, JobSubmission};
 
-#[derive(Default)]
+/// How often judges look for jobs pushed by other processes
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often blocking submissions check whether their job has finished
+const RESULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Priority level of a queued job, earlier levels are judged first
+///
+/// Jobs of a contest (any non-zero `contest_id`) count as a running contest, those
+/// outside of contests as practice. As `POST /jobs` only accepts contests that exist,
+/// clients can't raise the priority of their jobs by naming a made-up contest.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    Contest,
+    Practice,
+    ContestRejudge,
+    PracticeRejudge,
+}
+
+impl JobPriority {
+    pub fn of(submission: &JobSubmission, rejudge: bool) -> Self {
+        match (rejudge, submission.contest_id != 0) {
+            (false, true) => Self::Contest,
+            (false, false) => Self::Practice,
+            (true, true) => Self::ContestRejudge,
+            (true, false) => Self::PracticeRejudge,
+        }
+    }
+
+    /// Inverse of `priority as u32`, the level stored in the database
+    pub fn from_level(level: u32) -> Self {
+        match level {
+            0 => Self::Contest,
+            1 => Self::Practice,
+            2 => Self::ContestRejudge,
+            _ => Self::PracticeRejudge,
+        }
+    }
+}
+
+/// A job waiting to be claimed, see `GET /admin/queue`
+#[derive(Serialize, Debug)]
+pub struct QueuedJob {
+    pub id: u32,
+    pub user_id: u32,
+    pub priority: JobPriority,
+    pub enqueued_time: String,
+    /// Seconds since the job was queued
+    pub age_secs: u64,
+}
+
+/// What a worker is doing, see `GET /admin/workers`
+#[derive(Serialize, Debug)]
+pub struct WorkerStatus {
+    /// Name the worker leases jobs under, see `lease_owner`
+    pub name: String,
+    /// Idle, Compiling, Running, or Offline when it hasn't been seen for a lease
+    pub state: &'static str,
+    pub job_id: Option<u32>,
+    /// Case being judged while Running
+    pub case: Option<u32>,
+    pub last_seen: String,
+}
+
+/// A job leased to a judge, with the configs needed to judge it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClaimedJob {
+    pub job: JobRecord,
+    pub problem: OneProblemConfig,
+    pub language: OneLanguageConfig,
+    /// Language the problem's checker is written in, if it has one
+    pub checker_language: Option<OneLanguageConfig>,
+}
+
+impl ClaimedJob {
+    /// Languages to compile the checker with, see `CheckerCache::prepare`
+    pub fn checker_languages(&self) -> LanguageConfig {
+        self.checker_language.iter().cloned().collect()
+    }
+}
+
+/// Durable job queue, stored in the database shared by the server and the judges
+///
+/// Judges lease the jobs they claim and keep the lease alive with heartbeats. Jobs
+/// whose lease expires, e.g. because their judge died, are re-queued automatically.
 pub struct JobQueue {
-    queue: Mutex<VecDeque<JobMessage>>,
+    storage: Arc<dyn Storage>,
+    lease: Duration,
+    max_length: Option<u32>,
+    blocking_timeout: Option<Duration>,
+    /// Wakes up the judges of this process when a job is pushed from it
     notify: Notify,
 }
 
 impl JobQueue {
-    pub fn new() -> Self {
+    pub fn new(storage: Arc<dyn Storage>, config: &QueueConfig) -> Self {
         Self {
-            queue: Mutex::new(VecDeque::new()),
+            storage,
+            lease: config.lease(),
+            max_length: config.max_length,
+            blocking_timeout: config.blocking_timeout_secs.map(Duration::from_secs),
             notify: Notify::new(),
         }
     }
 
-    pub fn push(&self, job: JobMessage) {
-        self.queue.lock().unwrap().push_back(job);
+    /// Whether the number of jobs waiting to be claimed has reached `max_length`
+    ///
+    /// Only a hint, as other jobs may be queued right after; `submit` and `rejudge`
+    /// check the length again as they queue.
+    pub async fn is_full(&self) -> sqlx::Result<bool> {
+        let Some(max_length) = self.max_length else {
+            return Ok(false);
+        };
+        Ok(self.storage.count_unclaimed_jobs().await? >= max_length)
+    }
+
+    /// Creates a job with `len` cases and queues it, returning its id, or `None` if
+    /// the queue is full
+    pub async fn submit(&self, submission: &JobSubmission, len: u32) -> sqlx::Result<Option<u32>> {
+        let priority = JobPriority::of(submission, false) as u32;
+        let job_id = self
+            .storage
+            .create_queued_job(submission, len, priority, self.max_length)
+            .await?;
+        if job_id.is_some() {
+            self.notify.notify_one();
+        }
+        Ok(job_id)
+    }
+
+    /// Reverts a judged job to Queueing and queues it again, returning the number of
+    /// its cases, or `None` if the queue is full
+    pub async fn rejudge(
+        &self,
+        job_id: u32,
+        submission: &JobSubmission,
+    ) -> sqlx::Result<Option<usize>> {
+        let priority = JobPriority::of(submission, true) as u32;
+        let cases = self
+            .storage
+            .requeue_job(job_id, priority, submission.user_id, self.max_length)
+            .await?;
+        if cases.is_some() {
+            self.notify.notify_one();
+        }
+        Ok(cases)
+    }
+
+    /// Queues a job whatever the length of the queue, see `Storage::enqueue_job` for
+    /// the order jobs are claimed in
+    pub async fn push(
+        &self,
+        job_id: u32,
+        submission: &JobSubmission,
+        priority: JobPriority,
+    ) -> sqlx::Result<()> {
+        self.storage
+            .enqueue_job(job_id, priority as u32, submission.user_id)
+            .await?;
         self.notify.notify_one();
+        Ok(())
     }
 
-    pub async fn pop(&self) -> JobMessage {
+    /// Leases the next job to `owner` and marks it Running, if any is queued and the
+    /// queue isn't paused
+    ///
+    /// Jobs that can't be judged with the given configs are discarded as System Errors.
+    pub async fn try_claim(
+        &self,
+        owner: &str,
+        problems: &ProblemConfig,
+        languages: &LanguageConfig,
+    ) -> sqlx::Result<Option<ClaimedJob>> {
+        let expired = self.storage.requeue_expired_jobs().await?;
+        if !expired.is_empty() {
+            log::warn!("Lease of jobs {expired:?} expired, re-queued them");
+        }
+
+        while let Some(job_id) = self.storage.claim_job(owner, self.lease).await? {
+            let job = match self.storage.fetch_job(job_id).await {
+                Ok(job) => job,
+                Err(e) => {
+                    log::error!("Failed to fetch job {job_id} from database, job discarded: {e}");
+                    self.finish(job_id, owner, None).await?;
+                    continue;
+                }
+            };
+
+            let problem = problems.iter().find(|p| p.id == job.submission.problem_id);
+            let language = languages.iter().find(|l| l.name == job.submission.language);
+            let (Some(problem), Some(language)) = (problem, language) else {
+                log::error!(
+                    "Missing config for problem {} or language {}, job {job_id} discarded",
+                    job.submission.problem_id,
+                    job.submission.language
+                );
+                self.finish(job_id, owner, None).await?;
+                continue;
+            };
+            let checker_language = problem.misc.checker.as_ref().and_then(|checker| {
+                languages
+                    .iter()
+                    .find(|l| l.name == checker.language)
+                    .cloned()
+            });
+
+            self.storage.update_job_to_running(job_id).await?;
+            self.storage.update_worker(owner, Some(job_id)).await?;
+            return Ok(Some(ClaimedJob {
+                job,
+                problem: problem.clone(),
+                language: language.clone(),
+                checker_language,
+            }));
+        }
+
+        let seen_after = chrono::Utc::now() - self.heartbeat_interval();
+        self.storage
+            .mark_worker_idle(owner, seen_after.timestamp_millis())
+            .await?;
+        Ok(None)
+    }
+
+    /// Waits until a job can be claimed and leases it to `owner`
+    pub async fn pop(
+        &self,
+        owner: &str,
+        problems: &ProblemConfig,
+        languages: &LanguageConfig,
+    ) -> ClaimedJob {
         loop {
-            if let Some(job) = self.queue.lock().unwrap().pop_front() {
-                return job;
+            match self.try_claim(owner, problems, languages).await {
+                Ok(Some(claimed)) => return claimed,
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to claim a job from the queue: {e}"),
+            }
+
+            tokio::select! {
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep(CLAIM_POLL_INTERVAL) => {}
             }
-            self.notify.notified().await;
         }
     }
 
-    pub fn cancel_job(&self, job_id: u32) -> bool {
-        let mut queue = self.queue.lock().unwrap();
-        let before_len = queue.len();
-        queue.retain(|j| j.id() != job_id);
-        before_len != queue.len()
+    /// Interval at which judges should renew the lease of the job they run
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.lease / 3
+    }
+
+    /// Renews the lease of a job, returning whether `owner` still holds it
+    pub async fn heartbeat(&self, job_id: u32, owner: &str) -> sqlx::Result<bool> {
+        self.storage.touch_worker(owner).await?;
+        self.storage.renew_lease(job_id, owner, self.lease).await
+    }
+
+    /// Saves the result of a case judged by `owner`, returning whether it still held
+    /// the lease
+    pub async fn report_case(
+        &self,
+        job_id: u32,
+        owner: &str,
+        case: &CaseResult,
+    ) -> sqlx::Result<bool> {
+        if !self.storage.save_case_result(job_id, owner, case).await? {
+            return Ok(false);
+        }
+        self.storage.advance_worker(owner, job_id, case.id).await?;
+        Ok(true)
+    }
+
+    /// Removes a job judged by `owner` and saves its result, or discards the job
+    /// without one as a System Error
+    ///
+    /// Returns whether `owner` still held the lease, the result is dropped otherwise.
+    pub async fn finish(
+        &self,
+        job_id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> sqlx::Result<bool> {
+        self.storage.update_worker(owner, None).await?;
+        self.storage.finish_job(job_id, owner, result).await
+    }
+
+    /// Removes a job that no judge has claimed yet, returning whether it did
+    pub async fn cancel_job(&self, job_id: u32) -> sqlx::Result<bool> {
+        self.storage.dequeue_unclaimed_job(job_id).await
+    }
+
+    /// Removes and cancels all jobs that no judge has claimed yet, returning their ids
+    pub async fn drain(&self) -> sqlx::Result<Vec<u32>> {
+        let drained = self.storage.dequeue_unclaimed_jobs().await?;
+        for &job_id in &drained {
+            self.storage.update_job_to_canceled(job_id).await?;
+        }
+        Ok(drained)
+    }
+
+    /// Stops or resumes judges from claiming jobs, those already claimed still finish
+    pub async fn set_paused(&self, paused: bool) -> sqlx::Result<()> {
+        self.storage.set_queue_paused(paused).await
     }
+
+    pub async fn is_paused(&self) -> sqlx::Result<bool> {
+        self.storage.queue_paused().await
+    }
+
+    /// Jobs waiting to be claimed, in the order they will be
+    pub async fn queued_jobs(&self) -> sqlx::Result<Vec<QueuedJob>> {
+        self.storage.fetch_queued_jobs().await
+    }
+
+    /// Workers of all judges that claimed from this queue
+    pub async fn workers(&self) -> sqlx::Result<Vec<WorkerStatus>> {
+        let seen_after = chrono::Utc::now() - self.lease;
+        self.storage
+            .fetch_workers(seen_after.timestamp_millis())
+            .await
+    }
+
+    /// 1-based position of a job among those waiting to be claimed, if it is one
+    pub async fn position(&self, job_id: u32) -> sqlx::Result<Option<u32>> {
+        self.storage.queue_position(job_id).await
+    }
+
+    /// Waits until a job is finished or canceled, whichever judge runs it
+    ///
+    /// Gives up after the blocking timeout, returning the job as it is then.
+    pub async fn wait_for_result(&self, job_id: u32) -> sqlx::Result<JobRecord> {
+        let deadline = self
+            .blocking_timeout
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            let record = self.storage.fetch_job(job_id).await?;
+            if record.state == "Finished" || record.state == "Canceled" {
+                return Ok(record);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                log::info!("Stopped waiting for job {job_id} in state {}", record.state);
+                return Ok(record);
+            }
+            tokio::time::sleep(RESULT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Name under which a worker leases jobs, unique across processes and machines
+pub fn lease_owner(worker_id: u8) -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    format!("{}:{}:{worker_id}", host.trim(), std::process::id())
 }
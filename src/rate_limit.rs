@@ -0,0 +1,212 @@
+//! Token bucket rate limiting of submissions, per user and per client IP
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use actix_web::HttpRequest;
+
+use crate::config::{RateLimitConfig, TokenBucketConfig};
+use crate::database::Storage;
+
+/// Rate limiter of `POST /jobs`
+///
+/// Buckets are kept in the database, so that they survive restarts of the server and
+/// are shared by all servers using the same database. Changes to the limits apply to
+/// the existing buckets as they are refilled.
+#[derive(Default)]
+pub struct RateLimiter {
+    per_user: Option<TokenBucketConfig>,
+    per_ip: Option<TokenBucketConfig>,
+    client_ip_header: Option<String>,
+}
+
+/// A token bucket of the rate limiter, see `Storage::take_rate_tokens`
+pub struct TokenBucket {
+    /// What the bucket limits, e.g. `user:1` or `ip:10.0.0.1`
+    pub key: String,
+    /// Tokens the bucket holds when full
+    pub capacity: f64,
+    /// Tokens added per second
+    pub rate: f64,
+}
+
+impl TokenBucket {
+    fn new(key: String, config: &TokenBucketConfig) -> Self {
+        Self {
+            key,
+            capacity: (config.submissions + config.burst) as f64,
+            rate: config.submissions as f64 / config.window_secs.max(1) as f64,
+        }
+    }
+
+    /// Time until the bucket holds a whole token, zero if it already does
+    fn wait(&self, tokens: f64) -> Duration {
+        if tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        if self.rate <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64((1.0 - tokens) / self.rate)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            per_user: config.per_user,
+            per_ip: config.per_ip,
+            client_ip_header: config.client_ip_header.clone(),
+        }
+    }
+
+    /// IP address of the client that sent a request
+    ///
+    /// With `client_ip_header` set, this is the last address in that header, the one
+    /// added by the reverse proxy in front of the server. Otherwise, or if the header is
+    /// missing, it is the address of the peer.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let forwarded = self.client_ip_header.as_ref().and_then(|name| {
+            let value = req.headers().get_all(name.as_str()).last()?.to_str().ok()?;
+            value.rsplit(',').next()?.trim().parse().ok()
+        });
+        forwarded.or_else(|| req.peer_addr().map(|addr| addr.ip()))
+    }
+
+    /// Takes a token from the buckets of the user and of the IP, if both have one
+    ///
+    /// Otherwise takes nothing and returns how long to wait until both have one.
+    pub async fn check(
+        &self,
+        storage: &dyn Storage,
+        user_id: u32,
+        ip: Option<IpAddr>,
+    ) -> sqlx::Result<Result<(), Duration>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        self.check_at(storage, user_id, ip, now).await
+    }
+
+    async fn check_at(
+        &self,
+        storage: &dyn Storage,
+        user_id: u32,
+        ip: Option<IpAddr>,
+        now: i64,
+    ) -> sqlx::Result<Result<(), Duration>> {
+        let mut buckets = Vec::with_capacity(2);
+        if let Some(config) = &self.per_user {
+            buckets.push(TokenBucket::new(format!("user:{user_id}"), config));
+        }
+        if let (Some(config), Some(ip)) = (&self.per_ip, ip) {
+            buckets.push(TokenBucket::new(format!("ip:{ip}"), config));
+        }
+        if buckets.is_empty() {
+            return Ok(Ok(()));
+        }
+
+        let tokens = storage.take_rate_tokens(&buckets, now).await?;
+        let wait = buckets
+            .iter()
+            .zip(tokens)
+            .map(|(bucket, tokens)| bucket.wait(tokens))
+            .max()
+            .unwrap_or_default();
+        Ok(if wait.is_zero() { Ok(()) } else { Err(wait) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::DatabaseConfig;
+
+    fn limiter(
+        per_user: Option<TokenBucketConfig>,
+        per_ip: Option<TokenBucketConfig>,
+    ) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            per_user,
+            per_ip,
+            client_ip_header: None,
+        })
+    }
+
+    async fn memory_storage() -> Arc<dyn Storage> {
+        let config = DatabaseConfig {
+            path: Some("sqlite::memory:".to_string()),
+            ..Default::default()
+        };
+        crate::database::init_db(&config).await.unwrap()
+    }
+
+    const TWO_PER_MINUTE: TokenBucketConfig = TokenBucketConfig {
+        submissions: 2,
+        window_secs: 60,
+        burst: 1,
+    };
+
+    #[tokio::test]
+    async fn test_burst_then_refill() {
+        let storage = memory_storage().await;
+        let limiter = limiter(Some(TWO_PER_MINUTE), None);
+        let check = |user_id, now| limiter.check_at(storage.as_ref(), user_id, None, now);
+        let start = 1_000_000;
+
+        // Full bucket: the submissions of a window plus the burst
+        for _ in 0..3 {
+            assert!(check(1, start).await.unwrap().is_ok());
+        }
+        let wait = check(1, start).await.unwrap().unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+        // Other users have their own bucket
+        assert!(check(2, start).await.unwrap().is_ok());
+
+        let later = start + 30_000;
+        assert!(check(1, later).await.unwrap().is_ok());
+        assert!(check(1, later).await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejection_takes_no_token() {
+        let storage = memory_storage().await;
+        let one = TokenBucketConfig {
+            submissions: 1,
+            window_secs: 60,
+            burst: 0,
+        };
+        let limiter = limiter(Some(TWO_PER_MINUTE), Some(one));
+        let check = |ip| limiter.check_at(storage.as_ref(), 1, ip, 1_000_000);
+        let ip = Some(IpAddr::from([10, 0, 0, 1]));
+
+        assert!(check(ip).await.unwrap().is_ok());
+        // The IP is out of tokens, so the user keeps theirs
+        assert!(check(ip).await.unwrap().is_err());
+        assert!(check(None).await.unwrap().is_ok());
+        assert!(check(None).await.unwrap().is_ok());
+        assert!(check(None).await.unwrap().is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_client_ip_from_trusted_header() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            client_ip_header: Some("X-Forwarded-For".to_string()),
+            ..Default::default()
+        });
+        let peer = "192.168.0.1:4242".parse().unwrap();
+
+        // The proxy appends the address it got the request from
+        let req = actix_web::test::TestRequest::default()
+            .peer_addr(peer)
+            .insert_header(("X-Forwarded-For", "1.2.3.4, 10.0.0.7"))
+            .to_http_request();
+        assert_eq!(limiter.client_ip(&req), Some(IpAddr::from([10, 0, 0, 7])));
+
+        let req = actix_web::test::TestRequest::default()
+            .peer_addr(peer)
+            .to_http_request();
+        assert_eq!(limiter.client_ip(&req), Some(peer.ip()));
+        assert_eq!(RateLimiter::default().client_ip(&req), Some(peer.ip()));
+    }
+}
@@ -0,0 +1,239 @@
+//! Client side of the judge node protocol, see `routes::judge`
+
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow, bail};
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::config::OneProblemConfig;
+use crate::queue::ClaimedJob;
+use crate::routes::{
+    CaseProgress, CaseResult, ClaimResponse, JobRecord, JudgeResult, NodeRequest, hash_content,
+};
+use crate::worker::JobSource;
+
+/// How often idle nodes ask the server for a job
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Jobs claimed from an oj server over HTTP
+///
+/// Problem data is downloaded once and cached on disk by content hash, so it is only
+/// fetched again when it changes on the server.
+pub struct RemoteJobs {
+    client: reqwest::Client,
+    server: String,
+    secret: String,
+    lease: Duration,
+    /// Directory of the cached problem data files, named by hash
+    data_dir: PathBuf,
+    /// Serializes downloads, so that concurrent workers don't fetch the same file
+    download_lock: tokio::sync::Mutex<()>,
+    /// Hashes verified to be in the cache since startup
+    verified: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl RemoteJobs {
+    /// Creates a client of the server at `server`, whose leases last `lease`
+    pub fn build(server: &str, secret: &str, lease: Duration) -> anyhow::Result<Self> {
+        use directories::ProjectDirs;
+
+        let proj_dirs = ProjectDirs::from("", "", "oj")
+            .ok_or_else(|| anyhow!("Unable to find user directory"))?;
+        let data_dir = proj_dirs.cache_dir().join("problem-data");
+        fs::create_dir_all(&data_dir)?;
+
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            server: server.trim_end_matches('/').to_string(),
+            secret: secret.to_string(),
+            lease,
+            data_dir,
+            download_lock: tokio::sync::Mutex::new(()),
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Sends a request about a job, returning whether the node still holds its lease
+    async fn post_job(
+        &self,
+        job_id: u32,
+        action: &str,
+        body: &impl Serialize,
+    ) -> anyhow::Result<bool> {
+        let response = self
+            .client
+            .post(format!("{}/judge/jobs/{job_id}/{action}", self.server))
+            .bearer_auth(&self.secret)
+            .json(body)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::BAD_REQUEST => Ok(false),
+            status => bail!("Server responded {status}: {}", response.text().await?),
+        }
+    }
+
+    /// Claims a job if any is queued, with the paths in its problem config pointing
+    /// to the local copies of the problem data
+    async fn try_claim(&self, owner: &str) -> anyhow::Result<Option<ClaimedJob>> {
+        let response = self
+            .client
+            .post(format!("{}/judge/claim", self.server))
+            .bearer_auth(&self.secret)
+            .json(&NodeRequest {
+                node: owner.to_string(),
+            })
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::NO_CONTENT => return Ok(None),
+            status => bail!("Server responded {status}: {}", response.text().await?),
+        }
+
+        let ClaimResponse { mut claimed, files } = response.json().await?;
+        let mut local_paths = HashMap::new();
+        for (path, hash) in files {
+            let local_path = self
+                .fetch_file(&hash)
+                .await
+                .with_context(|| format!("Failed to fetch problem data {path}"))?;
+            local_paths.insert(path, local_path.to_string_lossy().to_string());
+        }
+        localize_problem(&mut claimed.problem, &local_paths);
+
+        // Special judges and interactors shipped with the data must be executable
+        let misc = &claimed.problem.misc;
+        for command in [&misc.special_judge, &misc.interactor]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(program) = command.first()
+                && local_paths.values().any(|path| path == program)
+            {
+                tokio::fs::set_permissions(program, fs::Permissions::from_mode(0o755)).await?;
+            }
+        }
+        Ok(Some(claimed))
+    }
+
+    /// Returns the path of a problem data file in the cache, downloading it if needed
+    async fn fetch_file(&self, hash: &str) -> anyhow::Result<PathBuf> {
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid file hash {hash}");
+        }
+        if let Some(path) = self.verified.lock().unwrap().get(hash) {
+            return Ok(path.clone());
+        }
+
+        let _guard = self.download_lock.lock().await;
+        let path = self.data_dir.join(hash);
+        let cached = match tokio::fs::read(&path).await {
+            Ok(content) => hash_content(&content) == hash,
+            Err(_) => false,
+        };
+        if !cached {
+            log::info!("Downloading problem data file {hash}");
+            let response = self
+                .client
+                .get(format!("{}/judge/files/{hash}", self.server))
+                .bearer_auth(&self.secret)
+                .send()
+                .await?
+                .error_for_status()?;
+            let content = response.bytes().await?;
+            if hash_content(&content) != hash {
+                bail!("Downloaded file does not match hash {hash}");
+            }
+            // Write to a temporary file first, so that a partial download is never used
+            let partial_path = self.data_dir.join(format!("{hash}.part"));
+            tokio::fs::write(&partial_path, &content).await?;
+            tokio::fs::rename(&partial_path, &path).await?;
+        }
+
+        self.verified
+            .lock()
+            .unwrap()
+            .insert(hash.to_string(), path.clone());
+        Ok(path)
+    }
+}
+
+impl JobSource for RemoteJobs {
+    async fn claim(&self, owner: &str) -> ClaimedJob {
+        loop {
+            match self.try_claim(owner).await {
+                Ok(Some(claimed)) => return claimed,
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to claim a job from {}: {e:#}", self.server),
+            }
+            tokio::time::sleep(CLAIM_POLL_INTERVAL).await;
+        }
+    }
+
+    fn heartbeat_interval(&self) -> Duration {
+        self.lease / 3
+    }
+
+    async fn heartbeat(&self, job_id: u32, owner: &str) -> anyhow::Result<bool> {
+        let body = NodeRequest {
+            node: owner.to_string(),
+        };
+        self.post_job(job_id, "heartbeat", &body).await
+    }
+
+    async fn report_case(&self, job_id: u32, owner: &str, case: &CaseResult) -> anyhow::Result<()> {
+        let body = CaseProgress {
+            node: owner.to_string(),
+            case: case.clone(),
+        };
+        self.post_job(job_id, "cases", &body).await?;
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        job_id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> anyhow::Result<bool> {
+        let body = JudgeResult {
+            node: owner.to_string(),
+            result: result.cloned(),
+        };
+        self.post_job(job_id, "result", &body).await
+    }
+}
+
+/// Replaces the paths of the files a problem config refers to with local ones
+fn localize_problem(problem: &mut OneProblemConfig, local_paths: &HashMap<String, String>) {
+    let localize = |path: &mut String| {
+        if let Some(local_path) = local_paths.get(path) {
+            *path = local_path.clone();
+        }
+    };
+    for case in &mut problem.cases {
+        localize(&mut case.input_file);
+        localize(&mut case.answer_file);
+    }
+    if let Some(checker) = &mut problem.misc.checker {
+        localize(&mut checker.source);
+    }
+    let misc = &mut problem.misc;
+    for command in [&mut misc.special_judge, &mut misc.interactor]
+        .into_iter()
+        .flatten()
+    {
+        command.iter_mut().for_each(localize);
+    }
+}
@@ -1,15 +1,24 @@
+mod admin;
 mod contests;
 mod jobs;
+mod judge;
 mod users;
 
+pub use admin::*;
 pub use contests::*;
 pub use jobs::*;
+pub use judge::*;
 pub use users::*;
 
+use std::sync::Arc;
+
 use actix_web::error::{InternalError, JsonPayloadError, QueryPayloadError};
+use actix_web::http::header;
 use actix_web::{HttpRequest, HttpResponse, Responder, post};
 use serde::Serialize;
 
+use crate::database::Storage;
+
 #[derive(Serialize)]
 struct ErrorResponse {
     reason: &'static str,
@@ -39,6 +48,73 @@ pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_
     InternalError::from_response(err, response).into()
 }
 
+/// Pool of read-only connections that queries run on, so that they don't wait for the
+/// writes of the workers, see `database::init_read_pool`
+pub struct ReadPool(pub Arc<dyn Storage>);
+
+/// Bearer token that admin endpoints require, `None` disables them
+pub struct AdminToken(pub Option<String>);
+
+/// Shared secret that remote judge nodes send as bearer token, `None` disables the
+/// judge endpoints
+pub struct JudgeSecret(pub Option<String>);
+
+/// Checks the `Authorization: Bearer <token>` header of an admin request
+///
+/// Returns the error response to send if the request is not authorized.
+fn require_admin(req: &HttpRequest, admin_token: &AdminToken) -> Result<(), HttpResponse> {
+    require_bearer(
+        req,
+        admin_token.0.as_deref(),
+        "Admin endpoints are disabled.",
+        "Invalid admin token.",
+    )
+}
+
+/// Whether a request carries the admin token, for endpoints that admins use with
+/// fewer restrictions
+fn is_admin(req: &HttpRequest, admin_token: &AdminToken) -> bool {
+    require_admin(req, admin_token).is_ok()
+}
+
+/// Checks the `Authorization: Bearer <secret>` header of a judge node request
+fn require_judge(req: &HttpRequest, judge_secret: &JudgeSecret) -> Result<(), HttpResponse> {
+    require_bearer(
+        req,
+        judge_secret.0.as_deref(),
+        "Judge endpoints are disabled.",
+        "Invalid judge secret.",
+    )
+}
+
+fn require_bearer(
+    req: &HttpRequest,
+    expected: Option<&str>,
+    disabled_message: &str,
+    invalid_message: &str,
+) -> Result<(), HttpResponse> {
+    let forbidden = |message: &str| {
+        HttpResponse::Forbidden().json(ErrorResponseWithMessage {
+            reason: "ERR_FORBIDDEN",
+            code: 7,
+            message: message.to_string(),
+        })
+    };
+
+    let Some(expected) = expected else {
+        return Err(forbidden(disabled_message));
+    };
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(expected) {
+        return Err(forbidden(invalid_message));
+    }
+    Ok(())
+}
+
 /// NOTE: DO NOT REMOVE: used in automatic testing
 #[post("/internal/exit")]
 #[allow(unreachable_code)]
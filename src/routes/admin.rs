@@ -0,0 +1,245 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post, web};
+use serde::{Deserialize, Serialize};
+
+use super::{AdminToken, ErrorResponse, ErrorResponseWithMessage, ReadPool, require_admin};
+use crate::config::ProblemConfig;
+use crate::database::{self as db, Archive, ExportFilter, ImportMode, Storage};
+use crate::queue::{JobQueue, QueuedJob, WorkerStatus};
+use crate::sandbox::CompileCache;
+
+/// Largest archive accepted by `POST /admin/import`, far above the JSON body limit
+const IMPORT_LIMIT: usize = 256 * 1024 * 1024;
+
+#[derive(Serialize)]
+pub struct QueueOverview {
+    pub paused: bool,
+    pub jobs: Vec<QueuedJob>,
+}
+
+#[derive(Serialize)]
+pub struct WorkersOverview {
+    pub paused: bool,
+    pub workers: Vec<WorkerStatus>,
+}
+
+#[derive(Serialize)]
+pub struct PauseState {
+    pub paused: bool,
+}
+
+#[derive(Serialize)]
+pub struct DrainedJobs {
+    pub canceled: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[delete("/admin/compile-cache")]
+pub async fn delete_compile_cache_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    compile_cache: web::Data<CompileCache>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+
+    let compile_cache = compile_cache.into_inner();
+    match web::block(move || compile_cache.purge()).await {
+        Ok(purge) => HttpResponse::Ok().json(purge),
+        Err(e) => {
+            log::error!("Failed to purge compile cache: {e}");
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                reason: "ERR_INTERNAL",
+                code: 6,
+            })
+        }
+    }
+}
+
+#[get("/admin/queue")]
+pub async fn get_queue_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    job_queue: web::Data<JobQueue>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+
+    let overview = async {
+        Ok::<_, sqlx::Error>(QueueOverview {
+            paused: job_queue.is_paused().await?,
+            jobs: job_queue.queued_jobs().await?,
+        })
+    };
+    match overview.await {
+        Ok(overview) => HttpResponse::Ok().json(overview),
+        Err(e) => queue_error("list queued jobs", e),
+    }
+}
+
+#[get("/admin/workers")]
+pub async fn get_workers_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    job_queue: web::Data<JobQueue>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+
+    let overview = async {
+        Ok::<_, sqlx::Error>(WorkersOverview {
+            paused: job_queue.is_paused().await?,
+            workers: job_queue.workers().await?,
+        })
+    };
+    match overview.await {
+        Ok(overview) => HttpResponse::Ok().json(overview),
+        Err(e) => queue_error("list workers", e),
+    }
+}
+
+#[post("/admin/workers/pause")]
+pub async fn post_pause_workers_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    job_queue: web::Data<JobQueue>,
+) -> impl Responder {
+    set_paused(&req, &admin_token, &job_queue, true).await
+}
+
+#[post("/admin/workers/resume")]
+pub async fn post_resume_workers_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    job_queue: web::Data<JobQueue>,
+) -> impl Responder {
+    set_paused(&req, &admin_token, &job_queue, false).await
+}
+
+async fn set_paused(
+    req: &HttpRequest,
+    admin_token: &AdminToken,
+    job_queue: &JobQueue,
+    paused: bool,
+) -> HttpResponse {
+    if let Err(response) = require_admin(req, admin_token) {
+        return response;
+    }
+
+    match job_queue.set_paused(paused).await {
+        Ok(()) => {
+            log::info!("Judging {}", if paused { "paused" } else { "resumed" });
+            HttpResponse::Ok().json(PauseState { paused })
+        }
+        Err(e) => queue_error("pause or resume judging", e),
+    }
+}
+
+#[post("/admin/queue/drain")]
+pub async fn post_drain_queue_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    job_queue: web::Data<JobQueue>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+
+    match job_queue.drain().await {
+        Ok(canceled) => {
+            log::info!("Drained the queue, canceled jobs {canceled:?}");
+            HttpResponse::Ok().json(DrainedJobs { canceled })
+        }
+        Err(e) => queue_error("drain the queue", e),
+    }
+}
+
+#[get("/admin/export")]
+pub async fn get_export_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    read_pool: web::Data<ReadPool>,
+    problems: web::Data<ProblemConfig>,
+    filter: web::Query<ExportFilter>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+    if let Err(e) = filter.validate() {
+        return invalid_argument(e.to_string());
+    }
+
+    match db::export_archive(read_pool.0.as_ref(), &problems, &filter).await {
+        Ok(archive) => {
+            log::info!(
+                "Exported {} users and {} jobs",
+                archive.users.len(),
+                archive.jobs.len()
+            );
+            HttpResponse::Ok().json(archive)
+        }
+        Err(e) => queue_error("export data", e),
+    }
+}
+
+#[post("/admin/import")]
+pub async fn post_import_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    storage: web::Data<dyn Storage>,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+
+    let body = match payload.to_bytes_limited(IMPORT_LIMIT).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return invalid_argument(format!("Failed to read archive: {e}")),
+        Err(_) => {
+            return invalid_argument(format!(
+                "Archive larger than {} MiB.",
+                IMPORT_LIMIT / 1024 / 1024
+            ));
+        }
+    };
+    let archive = match serde_json::from_slice::<Archive>(&body) {
+        Ok(archive) => archive,
+        Err(e) => return invalid_argument(format!("Invalid archive: {e}")),
+    };
+    if let Err(e) = archive.validate() {
+        return invalid_argument(e.to_string());
+    }
+
+    match db::import_archive(storage.get_ref(), archive, query.mode).await {
+        Ok(summary) => {
+            log::info!("Imported archive in {:?} mode: {summary:?}", query.mode);
+            HttpResponse::Ok().json(summary)
+        }
+        Err(e) => queue_error("import data", e),
+    }
+}
+
+fn invalid_argument(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponseWithMessage {
+        reason: "ERR_INVALID_ARGUMENT",
+        code: 1,
+        message,
+    })
+}
+
+fn queue_error(action: &str, e: sqlx::Error) -> HttpResponse {
+    log::error!("Failed to {action}: {e}");
+    HttpResponse::InternalServerError().json(ErrorResponse {
+        reason: "ERR_EXTERNAL",
+        code: 5,
+    })
+}
@@ -2,10 +2,8 @@ use std::collections::HashMap;
 
 use actix_web::{HttpResponse, Responder, get, web};
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
 
-use super::{ErrorResponse, ErrorResponseWithMessage, User};
-use crate::database as db;
+use super::{ErrorResponse, ErrorResponseWithMessage, ReadPool, User};
 
 #[derive(Deserialize, Debug)]
 pub struct RanklistQuery {
@@ -34,7 +32,7 @@ pub struct UserScore {
 pub async fn get_ranklist_handler(
     path: web::Path<u32>,
     query: web::Query<RanklistQuery>,
-    pool: web::Data<SqlitePool>,
+    read_pool: web::Data<ReadPool>,
     problems: web::Data<crate::config::ProblemConfig>,
 ) -> impl Responder {
     let contest_id = path.into_inner();
@@ -49,34 +47,38 @@ pub async fn get_ranklist_handler(
     }
 
     // Validate scoring_rule
-    if let Some(ref rule) = query.scoring_rule {
-        if rule != "latest" && rule != "highest" {
-            return HttpResponse::BadRequest().json(ErrorResponseWithMessage {
-                reason: "ERR_INVALID_ARGUMENT",
-                code: 1,
-                message: format!("Invalid scoring_rule: {rule}"),
-            });
-        }
+    if let Some(ref rule) = query.scoring_rule
+        && rule != "latest"
+        && rule != "highest"
+    {
+        return HttpResponse::BadRequest().json(ErrorResponseWithMessage {
+            reason: "ERR_INVALID_ARGUMENT",
+            code: 1,
+            message: format!("Invalid scoring_rule: {rule}"),
+        });
     }
 
     // Validate tie_breaker
-    if let Some(ref breaker) = query.tie_breaker {
-        if breaker != "submission_time" && breaker != "submission_count" && breaker != "user_id" {
-            return HttpResponse::BadRequest().json(ErrorResponseWithMessage {
-                reason: "ERR_INVALID_ARGUMENT",
-                code: 1,
-                message: format!("Invalid tie_breaker: {breaker}"),
-            });
-        }
+    if let Some(ref breaker) = query.tie_breaker
+        && breaker != "submission_time"
+        && breaker != "submission_count"
+        && breaker != "user_id"
+    {
+        return HttpResponse::BadRequest().json(ErrorResponseWithMessage {
+            reason: "ERR_INVALID_ARGUMENT",
+            code: 1,
+            message: format!("Invalid tie_breaker: {breaker}"),
+        });
     }
 
-    match db::get_global_ranklist(
-        query.scoring_rule.clone(),
-        query.tie_breaker.clone(),
-        problems.into_inner(),
-        pool.into_inner(),
-    )
-    .await
+    match read_pool
+        .0
+        .get_global_ranklist(
+            query.scoring_rule.clone(),
+            query.tie_breaker.clone(),
+            problems.into_inner(),
+        )
+        .await
     {
         Ok(ranklist) => HttpResponse::Ok().json(ranklist),
         Err(e) => {
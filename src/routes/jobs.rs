@@ -1,26 +1,28 @@
+mod artifacts;
 mod delete;
 mod get;
 mod post;
 mod put;
 
+pub use artifacts::{get_case_artifacts_handler, get_job_artifacts_handler};
 pub use delete::delete_job_handler;
 pub use get::{get_job_by_id_handler, get_jobs_handler};
 pub use post::post_job_handler;
 pub use put::put_job_handler;
 
-use actix_web::{HttpResponse, Responder, delete, get, post, put, web};
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post, put, web};
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
-use tokio::sync::oneshot;
 
-use super::{ErrorResponse, ErrorResponseWithMessage};
+use super::{AdminToken, ErrorResponse, ErrorResponseWithMessage, ReadPool, is_admin};
 use crate::config::{LanguageConfig, ProblemConfig};
 use crate::create_timestamp;
-use crate::database as db;
+use crate::database::Storage;
 use crate::queue::JobQueue;
+use crate::rate_limit::RateLimiter;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JobSubmission {
     pub user_id: u32,
     pub contest_id: u32,
@@ -29,7 +31,7 @@ pub struct JobSubmission {
     pub language: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JobRecord {
     pub id: u32,
     pub created_time: String,
@@ -41,35 +43,27 @@ pub struct JobRecord {
     pub cases: Vec<CaseResult>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Response of `GET /jobs/{id}`, a job along with its place in the judging queue
+#[derive(Serialize, Debug)]
+pub struct JobStatus {
+    #[serde(flatten)]
+    pub record: JobRecord,
+    /// 1-based position among the jobs waiting to be judged, only while queueing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CaseResult {
     pub id: u32, // index of the case
     pub result: String,
     pub time: u32,   // time in microseconds
     pub memory: u32, // memory in KB
+    pub score: f64,  // part of the job score earned by this case
     pub info: String,
 }
 
-pub enum JobMessage {
-    FireAndForget {
-        job_id: u32,
-    },
-    Blocking {
-        job_id: u32,
-        responder: oneshot::Sender<JobRecord>,
-    },
-}
-
-impl JobMessage {
-    pub fn id(&self) -> u32 {
-        match self {
-            Self::FireAndForget { job_id } => *job_id,
-            Self::Blocking { job_id, .. } => *job_id,
-        }
-    }
-}
-
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct JobsQueryParams {
     pub user_id: Option<u32>,
     pub user_name: Option<String>,
@@ -0,0 +1,140 @@
+use actix_web::HttpRequest;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+
+use super::*;
+use crate::routes::{AdminToken, ReadPool, require_admin};
+use crate::sandbox::{ArtifactStore, CaseArtifacts};
+
+#[derive(Serialize)]
+struct CaseArtifactsResponse {
+    job_id: u32,
+    case_id: u32,
+    #[serde(flatten)]
+    artifacts: CaseArtifacts,
+}
+
+#[get("/jobs/{id}/artifacts/{case}")]
+pub async fn get_case_artifacts_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    read_pool: web::Data<ReadPool>,
+    artifacts: web::Data<ArtifactStore>,
+    path: web::Path<(u32, u32)>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+    let (job_id, case_id) = path.into_inner();
+
+    let job = match find_job(job_id, &read_pool).await {
+        Ok(job) => job,
+        Err(response) => return response,
+    };
+    if case_id as usize >= job.cases.len() {
+        return HttpResponse::NotFound().json(ErrorResponseWithMessage {
+            reason: "ERR_NOT_FOUND",
+            code: 3,
+            message: format!("Case {case_id} of job {job_id} not found."),
+        });
+    }
+
+    let artifacts = artifacts.into_inner();
+    let case_artifacts = match web::block(move || artifacts.case_artifacts(job_id, case_id)).await {
+        Ok(case_artifacts) => case_artifacts,
+        Err(e) => {
+            log::error!("Failed to read artifacts of job {job_id}: {e}");
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                reason: "ERR_INTERNAL",
+                code: 6,
+            });
+        }
+    };
+
+    match case_artifacts {
+        Some(case_artifacts) if case_artifacts.meta.is_some() => {
+            HttpResponse::Ok().json(CaseArtifactsResponse {
+                job_id,
+                case_id,
+                artifacts: case_artifacts,
+            })
+        }
+        Some(_) => HttpResponse::NotFound().json(ErrorResponseWithMessage {
+            reason: "ERR_NOT_FOUND",
+            code: 3,
+            message: format!("Artifacts of case {case_id} of job {job_id} are not available."),
+        }),
+        None => artifacts_pruned(job_id),
+    }
+}
+
+#[get("/jobs/{id}/artifacts")]
+pub async fn get_job_artifacts_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    read_pool: web::Data<ReadPool>,
+    artifacts: web::Data<ArtifactStore>,
+    path: web::Path<(u32,)>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &admin_token) {
+        return response;
+    }
+    let job_id = path.into_inner().0;
+
+    if let Err(response) = find_job(job_id, &read_pool).await {
+        return response;
+    }
+
+    let artifacts = artifacts.into_inner();
+    let bundle = web::block(move || artifacts.bundle(job_id))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|bundle| bundle);
+    match bundle {
+        Ok(Some(bundle)) => HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(format!(
+                    "job-{job_id}-artifacts.zip"
+                ))],
+            })
+            .body(bundle),
+        Ok(None) => artifacts_pruned(job_id),
+        Err(e) => {
+            log::error!("Failed to bundle artifacts of job {job_id}: {e}");
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                reason: "ERR_INTERNAL",
+                code: 6,
+            })
+        }
+    }
+}
+
+/// Fetches a job, or returns the error response if it doesn't exist
+async fn find_job(job_id: u32, read_pool: &ReadPool) -> Result<JobRecord, HttpResponse> {
+    match read_pool.0.fetch_job(job_id).await {
+        Ok(job) => Ok(job),
+        Err(sqlx::Error::RowNotFound) => {
+            Err(HttpResponse::NotFound().json(ErrorResponseWithMessage {
+                reason: "ERR_NOT_FOUND",
+                code: 3,
+                message: format!("Job {job_id} not found."),
+            }))
+        }
+        Err(e) => {
+            log::error!("Failed to retrieve job record from database: {e}");
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                reason: "ERR_EXTERNAL",
+                code: 5,
+            }))
+        }
+    }
+}
+
+fn artifacts_pruned(job_id: u32) -> HttpResponse {
+    HttpResponse::NotFound().json(ErrorResponseWithMessage {
+        reason: "ERR_NOT_FOUND",
+        code: 3,
+        message: format!("Artifacts of job {job_id} have been pruned."),
+    })
+}
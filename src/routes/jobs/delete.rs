@@ -3,12 +3,12 @@ use super::*;
 #[delete("/jobs/{id}")]
 pub async fn delete_job_handler(
     job_queue: web::Data<JobQueue>,
-    pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<(u32,)>,
 ) -> impl Responder {
     let job_id = path.into_inner().0;
-    if job_queue.cancel_job(job_id) {
-        match db::update_job_to_canceled(job_id, pool.into_inner()).await {
+    match job_queue.cancel_job(job_id).await {
+        Ok(true) => match storage.update_job_to_canceled(job_id).await {
             Ok(_) => HttpResponse::Ok().finish(),
             Err(e) => {
                 log::error!("Failed to update job {job_id} status to Canceled: {e}");
@@ -17,9 +17,8 @@ pub async fn delete_job_handler(
                     code: 5,
                 })
             }
-        }
-    } else {
-        match db::find_job(job_id, pool.into_inner()).await {
+        },
+        Ok(false) => match storage.find_job(job_id).await {
             Ok(exists) if exists => {
                 // Job exists in the database but not in queueing state
                 HttpResponse::BadRequest().json(ErrorResponseWithMessage {
@@ -43,6 +42,13 @@ pub async fn delete_job_handler(
                     code: 5,
                 })
             }
+        },
+        Err(e) => {
+            log::error!("Failed to remove job {job_id} from queue: {e}");
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                reason: "ERR_EXTERNAL",
+                code: 5,
+            })
         }
     }
 }
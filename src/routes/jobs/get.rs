@@ -2,19 +2,19 @@ use super::*;
 
 #[get("/jobs")]
 pub async fn get_jobs_handler(
-    pool: web::Data<SqlitePool>,
+    read_pool: web::Data<ReadPool>,
     query: web::Query<JobsQueryParams>,
 ) -> impl Responder {
-    if let Some(from_str) = &query.from {
-        if DateTime::parse_from_rfc3339(from_str).is_err() {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                reason: "ERR_INVALID_ARGUMENT",
-                code: 1,
-            });
-        }
+    if let Some(from_str) = &query.from
+        && DateTime::parse_from_rfc3339(from_str).is_err()
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            reason: "ERR_INVALID_ARGUMENT",
+            code: 1,
+        });
     }
 
-    let jobs = db::fetch_jobs_by_query(query, pool.into_inner()).await;
+    let jobs = read_pool.0.fetch_jobs_by_query(&query).await;
 
     match jobs {
         Ok(records) => {
@@ -33,15 +33,24 @@ pub async fn get_jobs_handler(
 
 #[get("/jobs/{id}")]
 pub async fn get_job_by_id_handler(
-    pool: web::Data<SqlitePool>,
+    job_queue: web::Data<JobQueue>,
+    read_pool: web::Data<ReadPool>,
     path: web::Path<(u32,)>,
 ) -> impl Responder {
     let job_id = path.into_inner().0;
 
-    match db::fetch_job(job_id, pool.into_inner()).await {
-        Ok(record) => {
+    let record = read_pool.0.fetch_job(job_id).await;
+    let position = match &record {
+        Ok(record) if record.state == "Queueing" => job_queue.position(job_id).await,
+        _ => Ok(None),
+    };
+    match record.and_then(|record| Ok((record, position?))) {
+        Ok((record, queue_position)) => {
             log::info!("Got the record of job {job_id} from database");
-            HttpResponse::Ok().json(record)
+            HttpResponse::Ok().json(JobStatus {
+                record,
+                queue_position,
+            })
         }
         Err(sqlx::Error::RowNotFound) => {
             log::info!("Got nothing with job id {job_id} from database");
@@ -1,9 +1,13 @@
 use super::*;
 
 #[post("/jobs")]
+#[allow(clippy::too_many_arguments)]
 pub async fn post_job_handler(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    rate_limiter: web::Data<RateLimiter>,
     job_queue: web::Data<JobQueue>,
-    pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     problems: web::Data<ProblemConfig>,
     languages: web::Data<LanguageConfig>,
     blocking: web::Data<bool>,
@@ -22,8 +26,18 @@ pub async fn post_job_handler(
         });
     }
 
+    // Contests aren't implemented yet, so that none but the global contest 0 exists.
+    // Checking this keeps clients from claiming contest priority for their jobs.
+    if body.contest_id != 0 {
+        return HttpResponse::NotFound().json(ErrorResponseWithMessage {
+            reason: "ERR_NOT_FOUND",
+            code: 3,
+            message: format!("Contest {} not found.", body.contest_id),
+        });
+    }
+
     // Check if user ID exists
-    let user_exists = match db::find_user(body.user_id, pool.clone().into_inner()).await {
+    let user_exists = match storage.find_user(body.user_id).await {
         Ok(exists) => exists,
         Err(e) => {
             log::error!("Failed to check user existence: {e}");
@@ -41,14 +55,55 @@ pub async fn post_job_handler(
         });
     }
 
+    // Checked again as the job is queued, but rejecting early spares the rate limit
+    if let Err(response) = check_queue_capacity(&job_queue).await {
+        return response;
+    }
+
+    // Admins are exempt, e.g. to submit reference solutions in bulk
+    if !is_admin(&req, &admin_token) {
+        let ip = rate_limiter.client_ip(&req);
+        let checked = match rate_limiter.check(storage.as_ref(), body.user_id, ip).await {
+            Ok(checked) => checked,
+            Err(e) => {
+                log::error!(
+                    "Failed to check the rate limit of user {}: {e}",
+                    body.user_id
+                );
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    reason: "ERR_EXTERNAL",
+                    code: 5,
+                });
+            }
+        };
+        if let Err(wait) = checked {
+            let retry_after = (wait.as_secs_f64().ceil() as u64).max(1);
+            log::info!(
+                "Rejected submission of user {} from {ip:?}, rate limit exceeded",
+                body.user_id
+            );
+            return HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after))
+                .json(ErrorResponseWithMessage {
+                    reason: "ERR_RATE_LIMIT",
+                    code: 4,
+                    message: format!("Too many submissions, retry after {retry_after} seconds."),
+                });
+        }
+    }
+
     let problem = problems.as_ref().get(found_problem_idx.unwrap()).unwrap();
     let total_cases = 1 + problem.cases.len() as u32; // Compile is case 0
 
-    let job_id = match db::create_job(&body, pool.into_inner(), total_cases).await {
-        Ok(id) => {
+    let job_id = match job_queue.submit(&body, total_cases).await {
+        Ok(Some(id)) => {
             log::info!("Inserted job {id} into databse");
             id
         }
+        Ok(None) => {
+            log::warn!("Rejected a job because the queue is full");
+            return queue_full();
+        }
         Err(e) => {
             log::error!("Failed to insert job into database: {e}");
             return HttpResponse::InternalServerError().json(ErrorResponse {
@@ -68,6 +123,34 @@ pub async fn post_job_handler(
     .await
 }
 
+/// Returns the error response to send instead of queueing a job if the queue is full
+pub(super) async fn check_queue_capacity(job_queue: &JobQueue) -> Result<(), HttpResponse> {
+    match job_queue.is_full().await {
+        Ok(false) => Ok(()),
+        Ok(true) => {
+            log::warn!("Rejected a job because the queue is full");
+            Err(queue_full())
+        }
+        Err(e) => {
+            log::error!("Failed to count queued jobs: {e}");
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                reason: "ERR_EXTERNAL",
+                code: 5,
+            }))
+        }
+    }
+}
+
+/// Response to a job that isn't queued because `queue.max_length` jobs are waiting
+pub(super) fn queue_full() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(ErrorResponseWithMessage {
+        reason: "ERR_QUEUE_FULL",
+        code: 8,
+        message: "Judging queue is full, try again later.".to_string(),
+    })
+}
+
+/// Responds to a job that has just been queued, once judged if `blocking`
 pub(super) async fn handle_job_submission(
     job_id: u32,
     job_queue: &JobQueue,
@@ -76,16 +159,9 @@ pub(super) async fn handle_job_submission(
     cases_count: usize,
 ) -> HttpResponse {
     if blocking {
-        let (tx, rx) = oneshot::channel::<JobRecord>();
-        let job_message = JobMessage::Blocking {
-            job_id,
-            responder: tx,
-        };
-
-        job_queue.push(job_message);
         log::debug!("Sent blocking job {job_id} to queue");
 
-        match rx.await {
+        match job_queue.wait_for_result(job_id).await {
             Ok(response) => {
                 log::info!("Received final result of blocking job {}", response.id);
                 HttpResponse::Ok().json(response)
@@ -99,9 +175,6 @@ pub(super) async fn handle_job_submission(
             }
         }
     } else {
-        let job_message = JobMessage::FireAndForget { job_id };
-
-        job_queue.push(job_message);
         log::debug!("Sent non-blocking job {job_id} to queue");
 
         // Pre-allocate the cases vector with known capacity to avoid reallocations
@@ -112,6 +185,7 @@ pub(super) async fn handle_job_submission(
                 result: "Waiting".to_string(),
                 time: 0,
                 memory: 0,
+                score: 0.0,
                 info: String::new(),
             });
         }
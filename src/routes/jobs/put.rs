@@ -3,16 +3,16 @@ use super::*;
 #[put("/jobs/{id}")]
 pub async fn put_job_handler(
     job_queue: web::Data<JobQueue>,
-    pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<(u32,)>,
     blocking: web::Data<bool>,
 ) -> impl Responder {
     let job_id = path.into_inner().0;
 
-    match db::fetch_job(job_id, pool.clone().into_inner()).await {
+    match storage.fetch_job(job_id).await {
         Ok(record) if record.state == "Finished" || record.state == "Canceled" => {
-            match db::revert_job_to_queueing(job_id, pool.into_inner()).await {
-                Ok(reverted_cases) => {
+            match job_queue.rejudge(job_id, &record.submission).await {
+                Ok(Some(reverted_cases)) => {
                     super::post::handle_job_submission(
                         job_id,
                         job_queue.get_ref(),
@@ -22,6 +22,10 @@ pub async fn put_job_handler(
                     )
                     .await
                 }
+                Ok(None) => {
+                    log::warn!("Rejected rejudging job {job_id} because the queue is full");
+                    super::post::queue_full()
+                }
                 Err(e) => {
                     log::error!("Failed to revert job to queueing in database: {e}");
                     HttpResponse::InternalServerError().json(ErrorResponse {
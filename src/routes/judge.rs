@@ -0,0 +1,313 @@
+//! Endpoints of the protocol that remote judge nodes (`oj-judge --remote`) speak
+//!
+//! A node claims a job along with the configs to judge it with, downloads the problem
+//! data it doesn't have cached by content hash, keeps the lease alive with heartbeats
+//! while judging, reports each case as soon as it is judged and finally submits the
+//! result. All requests carry the shared secret as bearer token.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    CaseResult, ErrorResponse, ErrorResponseWithMessage, JobRecord, JudgeSecret, require_judge,
+};
+use crate::config::{LanguageConfig, OneProblemConfig, ProblemConfig};
+use crate::queue::{ClaimedJob, JobQueue};
+
+/// Problem data files that judge nodes may download, by content hash
+///
+/// Files are hashed when a job needing them is claimed, and hashed again whenever
+/// their size or modification time changes, so that data swapped while the server
+/// runs is served as it is on disk.
+#[derive(Default)]
+pub struct ProblemFiles {
+    /// Last hash of each file, by path as written in the problem config
+    hashed: Mutex<HashMap<String, HashedFile>>,
+    /// Path of each file, by any hash it had
+    paths: Mutex<HashMap<String, PathBuf>>,
+}
+
+/// A file as it was when last hashed
+struct HashedFile {
+    hash: String,
+    len: u64,
+    modified: SystemTime,
+}
+
+impl ProblemFiles {
+    /// Hashes the files of all problems ahead of the first claims
+    pub fn build(problems: &ProblemConfig) -> Self {
+        let files = Self::default();
+        let hashed = problems
+            .iter()
+            .map(|problem| files.hashes(problem).len())
+            .sum::<usize>();
+        log::info!("Hashed {hashed} problem data files for judge nodes");
+        files
+    }
+
+    /// Current hash of each file that judging a problem reads, by path
+    ///
+    /// Files that can't be read are left out, so that nodes fail the jobs needing them.
+    pub fn hashes(&self, problem: &OneProblemConfig) -> HashMap<String, String> {
+        let mut hashes = HashMap::new();
+        for path in problem_paths(problem) {
+            if hashes.contains_key(path) {
+                continue;
+            }
+            match self.hash(path) {
+                Ok(hash) => {
+                    hashes.insert(path.clone(), hash);
+                }
+                Err(e) => {
+                    log::warn!("Failed to read problem data {path}, judge nodes won't get it: {e}")
+                }
+            }
+        }
+        hashes
+    }
+
+    /// Hashes a file unless it is unchanged since it was last hashed
+    fn hash(&self, path: &str) -> std::io::Result<String> {
+        let metadata = fs::metadata(path)?;
+        let (len, modified) = (metadata.len(), metadata.modified()?);
+        if let Some(file) = self.hashed.lock().unwrap().get(path)
+            && file.len == len
+            && file.modified == modified
+        {
+            return Ok(file.hash.clone());
+        }
+
+        let hash = hash_content(&fs::read(path)?);
+        self.paths
+            .lock()
+            .unwrap()
+            .insert(hash.clone(), PathBuf::from(path));
+        let file = HashedFile {
+            hash: hash.clone(),
+            len,
+            modified,
+        };
+        self.hashed.lock().unwrap().insert(path.to_string(), file);
+        Ok(hash)
+    }
+
+    /// Content of the file with a hash, if a file still has that content
+    fn read(&self, hash: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(path) = self.paths.lock().unwrap().get(hash).cloned() else {
+            return Ok(None);
+        };
+        let content = fs::read(path)?;
+        Ok((hash_content(&content) == hash).then_some(content))
+    }
+}
+
+/// Hex SHA-256 of a problem data file, which identifies it in the judge protocol
+pub fn hash_content(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Paths of the files that judging a problem reads
+///
+/// Besides case data and checker sources, these are the arguments of the special
+/// judge and interactor commands that name files, such as the programs themselves.
+fn problem_paths(problem: &OneProblemConfig) -> impl Iterator<Item = &String> {
+    let cases = problem
+        .cases
+        .iter()
+        .flat_map(|case| [&case.input_file, &case.answer_file]);
+    let checker = problem.misc.checker.iter().map(|checker| &checker.source);
+    let commands = [&problem.misc.special_judge, &problem.misc.interactor]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|arg| !arg.contains('%') && Path::new(arg).is_file());
+    cases.chain(checker).chain(commands)
+}
+
+/// Body of judge node requests about a job, naming the node that holds its lease
+#[derive(Serialize, Deserialize)]
+pub struct NodeRequest {
+    pub node: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClaimResponse {
+    #[serde(flatten)]
+    pub claimed: ClaimedJob,
+    /// Content hash of each file the problem config refers to, by path
+    pub files: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CaseProgress {
+    pub node: String,
+    pub case: CaseResult,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JudgeResult {
+    pub node: String,
+    /// Final result of the job, or `None` to discard a job that can't be judged
+    pub result: Option<JobRecord>,
+}
+
+#[post("/judge/claim")]
+pub async fn post_judge_claim_handler(
+    req: HttpRequest,
+    judge_secret: web::Data<JudgeSecret>,
+    job_queue: web::Data<JobQueue>,
+    problems: web::Data<ProblemConfig>,
+    languages: web::Data<LanguageConfig>,
+    files: web::Data<ProblemFiles>,
+    body: web::Json<NodeRequest>,
+) -> impl Responder {
+    if let Err(response) = require_judge(&req, &judge_secret) {
+        return response;
+    }
+
+    match job_queue.try_claim(&body.node, &problems, &languages).await {
+        Ok(Some(claimed)) => {
+            log::info!("Judge node {} claimed job {}", body.node, claimed.job.id);
+            let problem = claimed.problem.clone();
+            let files = match web::block(move || files.hashes(&problem)).await {
+                Ok(files) => files,
+                Err(e) => {
+                    log::error!("Failed to hash problem data of job {}: {e}", claimed.job.id);
+                    return internal_error();
+                }
+            };
+            HttpResponse::Ok().json(ClaimResponse { claimed, files })
+        }
+        Ok(None) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            log::error!("Failed to claim a job for judge node {}: {e}", body.node);
+            external_error()
+        }
+    }
+}
+
+#[post("/judge/jobs/{id}/heartbeat")]
+pub async fn post_judge_heartbeat_handler(
+    req: HttpRequest,
+    judge_secret: web::Data<JudgeSecret>,
+    job_queue: web::Data<JobQueue>,
+    path: web::Path<(u32,)>,
+    body: web::Json<NodeRequest>,
+) -> impl Responder {
+    if let Err(response) = require_judge(&req, &judge_secret) {
+        return response;
+    }
+    let job_id = path.into_inner().0;
+
+    lease_response(
+        job_id,
+        &body.node,
+        job_queue.heartbeat(job_id, &body.node).await,
+    )
+}
+
+#[post("/judge/jobs/{id}/cases")]
+pub async fn post_judge_case_handler(
+    req: HttpRequest,
+    judge_secret: web::Data<JudgeSecret>,
+    job_queue: web::Data<JobQueue>,
+    path: web::Path<(u32,)>,
+    body: web::Json<CaseProgress>,
+) -> impl Responder {
+    if let Err(response) = require_judge(&req, &judge_secret) {
+        return response;
+    }
+    let job_id = path.into_inner().0;
+
+    let reported = job_queue.report_case(job_id, &body.node, &body.case).await;
+    lease_response(job_id, &body.node, reported)
+}
+
+#[post("/judge/jobs/{id}/result")]
+pub async fn post_judge_result_handler(
+    req: HttpRequest,
+    judge_secret: web::Data<JudgeSecret>,
+    job_queue: web::Data<JobQueue>,
+    path: web::Path<(u32,)>,
+    body: web::Json<JudgeResult>,
+) -> impl Responder {
+    if let Err(response) = require_judge(&req, &judge_secret) {
+        return response;
+    }
+    let job_id = path.into_inner().0;
+
+    let finished = job_queue
+        .finish(job_id, &body.node, body.result.as_ref())
+        .await;
+    if let Ok(true) = finished {
+        log::info!("Judge node {} finished job {job_id}", body.node);
+    }
+    lease_response(job_id, &body.node, finished)
+}
+
+#[get("/judge/files/{hash}")]
+pub async fn get_judge_file_handler(
+    req: HttpRequest,
+    judge_secret: web::Data<JudgeSecret>,
+    files: web::Data<ProblemFiles>,
+    path: web::Path<(String,)>,
+) -> impl Responder {
+    if let Err(response) = require_judge(&req, &judge_secret) {
+        return response;
+    }
+    let hash = path.into_inner().0;
+
+    let read_hash = hash.clone();
+    match web::block(move || files.read(&read_hash)).await {
+        Ok(Ok(Some(content))) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(content),
+        Ok(Ok(None)) => HttpResponse::NotFound().json(ErrorResponseWithMessage {
+            reason: "ERR_NOT_FOUND",
+            code: 3,
+            message: format!("File {hash} not found."),
+        }),
+        err => {
+            log::error!("Failed to read problem data file {hash}: {err:?}");
+            internal_error()
+        }
+    }
+}
+
+/// Responds to a request that requires the node to hold the lease of a job
+fn lease_response(job_id: u32, node: &str, held: sqlx::Result<bool>) -> HttpResponse {
+    match held {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::BadRequest().json(ErrorResponseWithMessage {
+            reason: "ERR_INVALID_STATE",
+            code: 2,
+            message: format!("Job {job_id} not leased to {node}."),
+        }),
+        Err(e) => {
+            log::error!("Failed to update job {job_id} of judge node {node}: {e}");
+            external_error()
+        }
+    }
+}
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(ErrorResponse {
+        reason: "ERR_INTERNAL",
+        code: 6,
+    })
+}
+
+fn external_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(ErrorResponse {
+        reason: "ERR_EXTERNAL",
+        code: 5,
+    })
+}
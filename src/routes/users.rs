@@ -1,11 +1,8 @@
-use std::sync::Arc;
-
 use actix_web::{HttpResponse, Responder, get, post, web};
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
 
-use super::{ErrorResponse, ErrorResponseWithMessage};
-use crate::database as db;
+use super::{ErrorResponse, ErrorResponseWithMessage, ReadPool};
+use crate::database::Storage;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
@@ -20,8 +17,8 @@ pub struct UserRequest {
 }
 
 #[get("/users")]
-pub async fn get_users_handler(pool: web::Data<SqlitePool>) -> impl Responder {
-    match db::get_users(pool.into_inner()).await {
+pub async fn get_users_handler(read_pool: web::Data<ReadPool>) -> impl Responder {
+    match read_pool.0.get_users().await {
         Ok(users) => HttpResponse::Ok().json(users),
         Err(e) => {
             log::error!("Failed to fetch users: {e}");
@@ -36,24 +33,22 @@ pub async fn get_users_handler(pool: web::Data<SqlitePool>) -> impl Responder {
 #[post("/users")]
 pub async fn post_users_handler(
     body: web::Json<UserRequest>,
-    pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
 ) -> impl Responder {
-    let pool = pool.into_inner();
-
     // Check if we're updating an existing user or creating a new one
     if let Some(user_id) = body.id {
-        update_user(user_id, &body.name, pool).await
+        update_user(user_id, &body.name, storage.get_ref()).await
     } else {
-        create_user(&body.name, pool).await
+        create_user(&body.name, storage.get_ref()).await
     }
 }
 
-async fn update_user(user_id: u32, new_name: &str, pool: Arc<SqlitePool>) -> HttpResponse {
+async fn update_user(user_id: u32, new_name: &str, storage: &dyn Storage) -> HttpResponse {
     // Check if user exists
-    match db::find_user(user_id, pool.clone()).await {
+    match storage.find_user(user_id).await {
         Ok(true) => {
             // User exists, check for name conflicts with other users
-            match db::user_name_exists(new_name, Some(user_id), pool.clone()).await {
+            match storage.user_name_exists(new_name, Some(user_id)).await {
                 Ok(true) => {
                     // Name already exists for another user
                     HttpResponse::BadRequest().json(ErrorResponseWithMessage {
@@ -64,7 +59,7 @@ async fn update_user(user_id: u32, new_name: &str, pool: Arc<SqlitePool>) -> Htt
                 }
                 Ok(false) => {
                     // No conflict, update the user
-                    match db::update_user(user_id, new_name, pool).await {
+                    match storage.update_user(user_id, new_name).await {
                         Ok(user) => HttpResponse::Ok().json(user),
                         Err(e) => {
                             log::error!("Failed to update user: {e}");
@@ -102,9 +97,9 @@ async fn update_user(user_id: u32, new_name: &str, pool: Arc<SqlitePool>) -> Htt
     }
 }
 
-async fn create_user(name: &str, pool: Arc<SqlitePool>) -> HttpResponse {
+async fn create_user(name: &str, storage: &dyn Storage) -> HttpResponse {
     // Check if name already exists
-    match db::user_name_exists(name, None, pool.clone()).await {
+    match storage.user_name_exists(name, None).await {
         Ok(true) => {
             // Name already exists
             HttpResponse::BadRequest().json(ErrorResponseWithMessage {
@@ -115,7 +110,7 @@ async fn create_user(name: &str, pool: Arc<SqlitePool>) -> HttpResponse {
         }
         Ok(false) => {
             // Name doesn't exist, create new user
-            match db::create_user(name, pool).await {
+            match storage.create_user(name).await {
                 Ok(user) => HttpResponse::Ok().json(user),
                 Err(e) => {
                     log::error!("Failed to create user in database: {e}");
@@ -1,21 +1,39 @@
+mod artifacts;
+mod backend;
+mod checker;
+mod checker_cache;
 mod compile;
+mod compile_cache;
+mod cpu;
 mod init;
+mod interactive;
+pub mod seccomp;
+mod self_test;
 mod testing;
 
 use std::collections::HashMap;
 use std::fs;
+use std::os::fd::AsRawFd;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::time::Instant;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail};
-use chrono::Local;
 
 use crate::config::{
-    JudgeType, MicroSecond, OneCaseConfig, OneLanguageConfig, OneProblemConfig, Second,
+    JudgeType, MicroSecond, OneCaseConfig, OneLanguageConfig, OneProblemConfig, SandboxBackendKind,
+    SeccompPolicy, Second, WrongAnswerInfo,
 };
-use crate::routes::JobRecord;
+use crate::routes::{CaseResult, JobRecord};
+use backend::{RunLimits, RunSpec, SandboxBackend};
+
+pub use artifacts::{ArtifactStore, CaseArtifacts};
+pub use checker_cache::CheckerCache;
+pub use compile_cache::{CompileCache, CompileCachePurge};
+pub use cpu::assign_cpu_sets;
+pub use self_test::{SelfTestCheck, SelfTestStatus, format_self_test_table, self_test};
 
 // Sandbox configuration constants
 const COMPILE_TIME_LIMIT: f64 = 30.0; // seconds
@@ -28,16 +46,33 @@ const RUNTIME_PROCESSES: u32 = 4;
 const RUNTIME_OPEN_FILES: u32 = 30;
 const RUNTIME_FILE_SIZE: u32 = 16384; // KB
 
+const COMPILE_ENV: &[(&str, &str)] = &[
+    ("RUSTUP_HOME", "/opt/oj/rust/rustup"),
+    ("CARGO_HOME", "/opt/oj/rust/cargo"),
+    (
+        "PATH",
+        "/opt/oj/rust/cargo/bin:/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+    ),
+];
+const RUNTIME_ENV: &[(&str, &str)] = &[(
+    "PATH",
+    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+)];
+
+// Tolerance used by the float judge type when `misc.epsilon` is not set
+const DEFAULT_FLOAT_EPSILON: f64 = 1e-6;
+
+// Maximum number of characters shown per side in Wrong Answer snippets
+const WRONG_ANSWER_SNIPPET_LEN: usize = 40;
+
+// Resource limits of trusted checkers (special judges and interactors), which run outside the sandbox
+const CHECKER_MEMORY_LIMIT: u64 = 1 << 30; // bytes
+const SPJ_TIME_LIMIT: f64 = 10.0; // seconds
+const INTERACTOR_EXTRA_TIME: f64 = 1.0; // seconds on top of the case time limit
+
 // Sandbox cache directory permissions
 const CACHE_DIR_PERMISSIONS: u32 = 0o700;
 
-/// Result of compilation process
-#[derive(Debug)]
-struct CompilationResult {
-    success: bool,
-    cache_dir: PathBuf,
-}
-
 /// Paths used during compilation
 #[derive(Debug)]
 struct CompilationPaths {
@@ -46,11 +81,12 @@ struct CompilationPaths {
     meta: PathBuf,
 }
 
-/// Paths used during test case execution
+/// Paths used during test case execution, all on the host
 #[derive(Debug)]
 struct TestCasePaths {
     stdin: PathBuf,
     stdout: PathBuf,
+    stderr: PathBuf,
     meta: PathBuf,
 }
 
@@ -64,68 +100,167 @@ struct TestCaseResult {
     stdout_content: String,
 }
 
-/// A sandbox environment for compiling and executing code safely using isolate
+/// Outcome of checking program output against the expected answer
+#[derive(Debug)]
+struct OutputCheck {
+    /// Fraction of the case score awarded, in [0, 1]
+    fraction: f64,
+    info: String,
+    /// Set when the checker itself failed, which overrides the fraction
+    error: Option<&'static str>,
+}
+
+impl OutputCheck {
+    /// Creates a rejected check carrying a message for the case info
+    fn wrong(info: String) -> Self {
+        Self::partial(0.0, info)
+    }
+
+    /// Creates a check awarding the given fraction of the case score
+    fn partial(fraction: f64, info: String) -> Self {
+        Self {
+            fraction,
+            info,
+            error: None,
+        }
+    }
+
+    /// Creates a check for a checker that failed to produce a verdict
+    fn failed(error: &'static str, info: String) -> Self {
+        Self {
+            fraction: 0.0,
+            info,
+            error: Some(error),
+        }
+    }
+
+    #[cfg(test)]
+    fn is_correct(&self) -> bool {
+        self.error.is_none() && self.fraction >= 1.0
+    }
+
+    /// Whether the checker gave a verdict awarding nothing
+    fn is_rejected(&self) -> bool {
+        self.error.is_none() && self.fraction <= 0.0
+    }
+}
+
+impl From<bool> for OutputCheck {
+    fn from(correct: bool) -> Self {
+        Self::partial(if correct { 1.0 } else { 0.0 }, String::new())
+    }
+}
+
+/// A sandbox environment for compiling and executing code safely
 ///
 /// The Sandbox provides an isolated environment where user-submitted code can be
-/// compiled and executed with resource limits and security restrictions.
+/// compiled and executed with resource limits and security restrictions, enforced
+/// by its backend (isolate by default).
 pub struct Sandbox {
     /// Unique identifier for this sandbox instance
     id: u8,
-    /// Path to the sandbox's working directory (inside isolate)
+    /// Isolation mechanism used to run programs
+    backend: Box<dyn SandboxBackend>,
+    /// Path to the sandbox's working directory
     box_dir: PathBuf,
-    /// Path to the cache directory for temporary files
-    cache_dir: PathBuf,
+    /// Compilation results shared by all sandboxes
+    compile_cache: Arc<CompileCache>,
+    /// Artifacts of judged jobs shared by all sandboxes
+    artifacts: Arc<ArtifactStore>,
+    /// Lock on the sandbox id, held as long as the sandbox exists
+    _lock: fs::File,
 }
 
 impl Sandbox {
     /// Creates a new sandbox instance with the given ID
-    pub fn build(id: u8) -> anyhow::Result<Self> {
-        let cache_dir = Self::setup_cache_directory(id)?;
-        let box_dir = Self::initialize_isolate_sandbox(id)?;
+    ///
+    /// Programs run in the sandbox are pinned to `cpus`, unless it's empty.
+    pub fn build(
+        id: u8,
+        backend: SandboxBackendKind,
+        cpus: Vec<usize>,
+        compile_cache: Arc<CompileCache>,
+        artifacts: Arc<ArtifactStore>,
+    ) -> anyhow::Result<Self> {
+        let lock = lock_sandbox_id(id)?;
+        let backend = backend::create_backend(backend, id, cpus);
+        let box_dir = backend.init()?;
 
         log::info!("Sandbox {id} initialized successfully");
         Ok(Self {
             id,
+            backend,
             box_dir,
-            cache_dir,
+            compile_cache,
+            artifacts,
+            _lock: lock,
         })
     }
 
     /// Main entry point for running a job in the sandbox
+    ///
+    /// `on_case` is called with the result of the compilation and of each test case
+    /// as soon as it is known.
     pub fn run(
         &self,
         mut job: JobRecord,
         problem: OneProblemConfig,
         language: OneLanguageConfig,
+        on_case: &mut dyn FnMut(&CaseResult),
     ) -> anyhow::Result<JobRecord> {
         self.reinit()?;
+        let job_dir = self.artifacts.create_job_dir(job.id)?;
 
-        // Step 1: Compile the source code
-        let compilation_result = self.compile_source_code(&mut job, &language)?;
-        if !compilation_result.success {
-            return Ok(job);
-        }
+        let judged = (|| {
+            // Step 1: Compile the source code
+            let compiled = self.compile_source_code(&mut job, &language, &job_dir)?;
+            on_case(&job.cases[0]);
+            if compiled {
+                // Step 2: Run test cases
+                self.run_test_cases(&mut job, &problem, language.seccomp, &job_dir, on_case)?;
+            }
+            anyhow::Ok(())
+        })();
+
+        // Step 3: Apply the artifact retention policy, even if judging failed
+        self.artifacts.finish_job(&job, &job_dir);
 
-        // Step 2: Run test cases
-        self.run_test_cases(&mut job, &problem, compilation_result.cache_dir)?;
+        judged.map(|()| job)
+    }
+}
 
-        Ok(job)
+/// Locks a sandbox id for this process, failing if another process holds it
+///
+/// Isolate boxes and the directories of the process backend are shared by all
+/// processes on the machine, which would wipe each other's boxes. The lock is released
+/// when the file is closed, also if the process dies.
+fn lock_sandbox_id(id: u8) -> anyhow::Result<fs::File> {
+    let path = std::env::temp_dir().join(format!("oj-sandbox-{id}.lock"));
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .or_else(|_| fs::File::open(&path))?;
+    // SAFETY: `file` stays open during the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() == std::io::ErrorKind::WouldBlock {
+            bail!(
+                "Sandbox {id} is used by another judging process on this machine, \
+                 give each process its own sandboxes with --sandbox-offset"
+            );
+        }
+        bail!("Failed to lock sandbox {id}: {e}");
     }
+    Ok(file)
 }
 
 impl Drop for Sandbox {
     fn drop(&mut self) {
-        let out = Command::new("isolate")
-            .arg("-b")
-            .arg(self.id.to_string())
-            .arg("--cg")
-            .arg("--cleanup")
-            .output();
-
-        if out.is_ok_and(|c| c.status.success()) {
-            log::info!("Sandbox {} cleaned up", self.id);
-        } else {
-            log::error!("Sandbox {} failed to clean up", self.id);
+        match self.backend.cleanup() {
+            Ok(()) => log::info!("Sandbox {} cleaned up", self.id),
+            Err(e) => log::error!("Sandbox {} failed to clean up: {e}", self.id),
         }
     }
 }
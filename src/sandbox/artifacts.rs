@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+use std::io::{Cursor, Write};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::*;
+use crate::config::ArtifactRetention;
+
+pub(super) const COMPILE_META_FILE: &str = "compile.meta";
+pub(super) const COMPILE_LOG_FILE: &str = "compile.log";
+
+/// Judging artifacts of each job, such as meta files and program outputs
+///
+/// Artifacts of a job live in `<cache>/artifacts/<job id>/`: the compile meta file
+/// and compiler output, and for each case `N` its meta file `N.meta`, the program
+/// output `N.out` and stderr `N.err`, and depending on the judge type the checker
+/// output `N.spj` or `N.interactor`. The directory is replaced when
+/// the job is rejudged. After each job, the directories of other jobs are pruned
+/// according to the retention policy, starting from the least recently judged.
+///
+/// Artifacts stay on the host that judged the job, so the server only sees those of
+/// jobs judged by its own workers or by judges sharing its cache directory.
+pub struct ArtifactStore {
+    dir: PathBuf,
+    retention: ArtifactRetention,
+    /// Jobs currently being judged, whose directories are never pruned
+    active_jobs: Mutex<HashSet<u32>>,
+}
+
+impl ArtifactStore {
+    /// Creates the artifact store under the user cache directory
+    pub fn build(retention: ArtifactRetention) -> anyhow::Result<Self> {
+        use directories::ProjectDirs;
+
+        let proj_dirs = ProjectDirs::from("", "", "oj")
+            .ok_or_else(|| anyhow!("Unable to find user directory"))?;
+
+        let cache_base_dir = proj_dirs.cache_dir();
+        fs::create_dir_all(cache_base_dir)?;
+        fs::set_permissions(
+            cache_base_dir,
+            fs::Permissions::from_mode(CACHE_DIR_PERMISSIONS),
+        )?;
+
+        remove_legacy_artifacts(cache_base_dir);
+
+        let dir = cache_base_dir.join("artifacts");
+        fs::create_dir_all(&dir)?;
+
+        let store = Self {
+            dir,
+            retention,
+            active_jobs: Mutex::new(HashSet::new()),
+        };
+        store.prune();
+        Ok(store)
+    }
+
+    /// Creates an artifact store in `dir` that never prunes, for sandboxes that judge
+    /// no jobs, such as the one of the self-test
+    pub(super) fn scratch(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            retention: ArtifactRetention {
+                keep_last: Some(0),
+                max_age_hours: Some(0),
+                max_size_mb: Some(0),
+                failed_only: false,
+            },
+            active_jobs: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Returns the artifact directory of a job, if its artifacts are still retained
+    pub fn job_dir(&self, job_id: u32) -> Option<PathBuf> {
+        let dir = self.dir.join(job_id.to_string());
+        dir.is_dir().then_some(dir)
+    }
+
+    /// Reads the artifacts recorded for a case, where case 0 is compilation
+    ///
+    /// Returns `None` if the artifacts of the job have been pruned.
+    pub fn case_artifacts(&self, job_id: u32, case_id: u32) -> Option<CaseArtifacts> {
+        let dir = self.job_dir(job_id)?;
+        let read = |name: String| {
+            fs::read(dir.join(name))
+                .ok()
+                .map(|content| String::from_utf8_lossy(&content).into_owned())
+        };
+
+        let compile_log = read(COMPILE_LOG_FILE.to_string());
+        if case_id == 0 {
+            return Some(CaseArtifacts {
+                stdout: None,
+                stderr: None,
+                meta: read(COMPILE_META_FILE.to_string()),
+                checker_log: None,
+                compile_log,
+            });
+        }
+
+        Some(CaseArtifacts {
+            stdout: read(format!("{case_id}.out")),
+            stderr: read(format!("{case_id}.err")),
+            meta: read(format!("{case_id}.meta")),
+            checker_log: read(format!("{case_id}.spj"))
+                .or_else(|| read(format!("{case_id}.interactor"))),
+            compile_log,
+        })
+    }
+
+    /// Bundles all artifacts of a job into a zip archive
+    ///
+    /// Returns `None` if the artifacts of the job have been pruned.
+    pub fn bundle(&self, job_id: u32) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(dir) = self.job_dir(job_id) else {
+            return Ok(None);
+        };
+
+        let mut files: Vec<_> = fs::read_dir(&dir)?
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .map(|entry| entry.file_name())
+            .collect();
+        files.sort();
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for name in files {
+            zip.start_file(format!("job-{job_id}/{}", name.to_string_lossy()), options)?;
+            zip.write_all(&fs::read(dir.join(&name))?)?;
+        }
+
+        Ok(Some(zip.finish()?.into_inner()))
+    }
+
+    /// Creates an empty artifact directory for a job that is about to be judged
+    pub(super) fn create_job_dir(&self, job_id: u32) -> anyhow::Result<PathBuf> {
+        self.active_jobs.lock().unwrap().insert(job_id);
+
+        let dir = self.dir.join(job_id.to_string());
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Drops artifacts that the policy doesn't keep once a job is judged, then prunes
+    pub(super) fn finish_job(&self, job: &JobRecord, dir: &Path) {
+        if self.retention.failed_only {
+            if job.result == "Accepted" {
+                let _ = fs::remove_dir_all(dir);
+            } else {
+                // Case 0 is compilation, whose artifacts are kept for any failure
+                for case in job.cases.iter().skip(1) {
+                    if case.result == "Accepted" {
+                        remove_case_artifacts(dir, case.id);
+                    }
+                }
+            }
+        }
+
+        self.active_jobs.lock().unwrap().remove(&job.id);
+        self.prune();
+    }
+
+    /// Removes artifacts of inactive jobs that exceed the retention limits
+    fn prune(&self) {
+        let active_jobs = self.active_jobs.lock().unwrap();
+
+        let mut entries = self.entries();
+        entries.retain(|(job_id, ..)| !active_jobs.contains(job_id));
+        // Most recently judged first
+        entries.sort_by_key(|(_, _, _, judged_at)| std::cmp::Reverse(*judged_at));
+
+        let keep_last = self.retention.keep_last();
+        let max_age = self.retention.max_age();
+        let max_size = self.retention.max_size();
+        let mut kept_size = 0;
+
+        for (idx, (job_id, path, size, judged_at)) in entries.into_iter().enumerate() {
+            let too_many = keep_last.is_some_and(|n| idx >= n);
+            let too_old =
+                max_age.is_some_and(|max_age| judged_at.elapsed().is_ok_and(|age| age > max_age));
+            let too_large = max_size.is_some_and(|max_size| kept_size + size > max_size);
+
+            if too_many || too_old || too_large {
+                if fs::remove_dir_all(&path).is_ok() {
+                    log::debug!("Pruned artifacts of job {job_id}");
+                }
+            } else {
+                kept_size += size;
+            }
+        }
+    }
+
+    /// Lists job artifact directories with their job id, size and last judge time
+    fn entries(&self) -> Vec<(u32, PathBuf, u64, SystemTime)> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        read_dir
+            .flatten()
+            .filter_map(|entry| {
+                let job_id = entry.file_name().to_str()?.parse::<u32>().ok()?;
+                let judged_at = entry.metadata().and_then(|m| m.modified()).ok()?;
+                let path = entry.path();
+                let size = fs::read_dir(&path)
+                    .ok()?
+                    .flatten()
+                    .filter_map(|f| f.metadata().ok())
+                    .map(|m| m.len())
+                    .sum();
+                Some((job_id, path, size, judged_at))
+            })
+            .collect()
+    }
+}
+
+/// Artifacts recorded for a single case, each `None` if it wasn't recorded
+///
+/// Stdout isn't recorded for interactive problems, as it goes to the interactor.
+#[derive(Debug, Serialize)]
+pub struct CaseArtifacts {
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub meta: Option<String>,
+    /// Output of the special judge or the interactor
+    pub checker_log: Option<String>,
+    pub compile_log: Option<String>,
+}
+
+/// Removes the artifacts of older versions, which were kept forever in timestamped
+/// directories under `<cache>/<sandbox id>/`
+fn remove_legacy_artifacts(cache_base_dir: &Path) {
+    let Ok(read_dir) = fs::read_dir(cache_base_dir) else {
+        return;
+    };
+    for entry in read_dir.flatten() {
+        let is_sandbox_id = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.parse::<u8>().is_ok());
+        if is_sandbox_id && fs::remove_dir_all(entry.path()).is_ok() {
+            log::info!("Removed legacy artifacts in {}", entry.path().display());
+        }
+    }
+}
+
+/// Removes every artifact of a case, which are the files named `<case id>.*`
+fn remove_case_artifacts(dir: &Path, case_id: u32) {
+    let prefix = format!("{case_id}.");
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in read_dir.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
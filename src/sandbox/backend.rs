@@ -0,0 +1,99 @@
+mod isolate;
+mod process;
+
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+
+use super::cpu::pin_to_cpus;
+use super::*;
+use crate::config::SandboxBackendKind;
+
+pub(super) use isolate::IsolateBackend;
+pub(super) use process::ProcessBackend;
+
+/// Descriptor number under which [`RunSpec::pass_fd`] is given to the program
+pub(super) const PASSED_FD: RawFd = 3;
+
+/// Resource limits of a command run in a sandbox
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RunLimits {
+    /// Wall clock time limit in seconds
+    pub wall_time: f64,
+    /// Memory limit in KB, 0 for unlimited
+    pub memory: u32,
+    /// Stack size limit in KB if it should differ from the default, 0 for unlimited
+    pub stack: Option<u32>,
+    pub processes: u32,
+    pub open_files: u32,
+    /// Maximum size of a written file in KB
+    pub file_size: u32,
+}
+
+/// A command to run in the sandbox working directory
+///
+/// The standard streams are passed to the command as is, so they may be files or
+/// pipes on the host.
+pub(super) struct RunSpec<'a> {
+    pub command: &'a [&'a str],
+    pub env: &'a [(&'a str, &'a str)],
+    pub limits: RunLimits,
+    /// Whether the toolchains under `/opt/oj` must be available, as for compilers
+    pub toolchains: bool,
+    /// Further host directories that must be readable, such as the seccomp wrapper's
+    pub extra_dirs: &'a [PathBuf],
+    /// Path of the meta file to write, see `misc/isolate_meta.md` for its format
+    pub meta_path: &'a Path,
+    pub stdin: Stdio,
+    pub stdout: Stdio,
+    pub stderr: Stdio,
+    /// A further descriptor given to the program as [`PASSED_FD`], such as the pipe
+    /// the seccomp wrapper reports through
+    pub pass_fd: Option<OwnedFd>,
+}
+
+/// The mechanism that isolates and limits programs run by a [`Sandbox`]
+///
+/// Backends report the outcome of a run through a meta file in the format of
+/// isolate, so that the same outcome leads to the same verdict on every backend.
+pub(super) trait SandboxBackend: Send + Sync {
+    /// Resets the sandbox to an empty working directory and returns its path
+    fn init(&self) -> anyhow::Result<PathBuf>;
+
+    /// Runs a command in the working directory and waits for it to finish
+    fn run(&self, spec: RunSpec) -> anyhow::Result<()>;
+
+    /// Releases the sandbox once it's no longer used
+    fn cleanup(&self) -> anyhow::Result<()>;
+}
+
+/// Creates the backend of the given kind for sandbox `id`, running programs on `cpus`
+pub(super) fn create_backend(
+    kind: SandboxBackendKind,
+    id: u8,
+    cpus: Vec<usize>,
+) -> Box<dyn SandboxBackend> {
+    match kind {
+        SandboxBackendKind::Isolate => Box::new(IsolateBackend::new(id, cpus)),
+        SandboxBackendKind::Process => Box::new(ProcessBackend::new(id, cpus)),
+    }
+}
+
+/// Makes `fd` available to the program as [`PASSED_FD`], closing the host's copy once
+/// the command is dropped
+fn pass_fd(command: &mut Command, fd: OwnedFd) {
+    // SAFETY: only the async-signal-safe `dup2` and `fcntl` are called between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            let result = if fd.as_raw_fd() == PASSED_FD {
+                // `dup2` onto itself would keep the close-on-exec flag
+                libc::fcntl(PASSED_FD, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd.as_raw_fd(), PASSED_FD)
+            };
+            if result < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
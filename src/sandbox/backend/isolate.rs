@@ -0,0 +1,111 @@
+use super::*;
+
+/// Sandbox backend running programs in an isolate box with cgroups
+pub(in crate::sandbox) struct IsolateBackend {
+    id: u8,
+    /// CPUs the box runs on, inherited from the pinned isolate process
+    cpus: Vec<usize>,
+}
+
+impl IsolateBackend {
+    pub fn new(id: u8, cpus: Vec<usize>) -> Self {
+        Self { id, cpus }
+    }
+}
+
+impl SandboxBackend for IsolateBackend {
+    fn init(&self) -> anyhow::Result<PathBuf> {
+        let output = Command::new("isolate")
+            .arg("-b")
+            .arg(self.id.to_string())
+            .arg("--cg")
+            .arg("--init")
+            .output()
+            .map_err(|e| anyhow!("Failed to spawn isolate --init: {}", e))?;
+
+        if !output.status.success() {
+            bail!("isolate --init exited with non-zero status");
+        }
+
+        let root_dir_absolute = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if root_dir_absolute.is_empty() {
+            bail!(
+                "isolate --init produced empty stdout; stderr={}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(PathBuf::from(root_dir_absolute).join("box"))
+    }
+
+    fn run(&self, spec: RunSpec) -> anyhow::Result<()> {
+        let limits = spec.limits;
+        let mut args = vec![
+            "-b".to_string(),
+            self.id.to_string(),
+            "--cg".to_string(),
+            "--run".to_string(),
+            format!("--wall-time={:.4}", limits.wall_time),
+            format!("--cg-mem={}", limits.memory),
+            format!("--processes={}", limits.processes),
+            format!("--open-files={}", limits.open_files),
+            format!("--fsize={}", limits.file_size),
+        ];
+        if let Some(stack) = limits.stack {
+            args.push(format!("--stack={stack}"));
+        }
+        if spec.toolchains {
+            args.push("--dir=/opt/oj".to_string());
+            if Path::new("/etc/alternatives").exists() {
+                args.push("--dir=/etc/alternatives".to_string());
+            }
+        }
+        for dir in spec.extra_dirs {
+            args.push(format!("--dir={}", dir.display()));
+        }
+        for (key, value) in spec.env {
+            args.push("-E".to_string());
+            args.push(format!("{key}={value}"));
+        }
+        if spec.pass_fd.is_some() {
+            args.push("--inherit-fds".to_string());
+        }
+        args.push("-M".to_string());
+        args.push(spec.meta_path.to_string_lossy().to_string());
+        args.push("--silent".to_string());
+        args.push("--".to_string());
+
+        // The standard streams are inherited by the program inside the box
+        let mut command = Command::new("isolate");
+        command
+            .args(args)
+            .args(spec.command)
+            .stdin(spec.stdin)
+            .stdout(spec.stdout)
+            .stderr(spec.stderr);
+        if let Some(fd) = spec.pass_fd {
+            pass_fd(&mut command, fd);
+        }
+        pin_to_cpus(&mut command, &self.cpus);
+        let mut child = command.spawn()?;
+        // Close the host's copies of pipe ends, so that the other side sees EOF
+        drop(command);
+        child.wait()?;
+
+        Ok(())
+    }
+
+    fn cleanup(&self) -> anyhow::Result<()> {
+        let output = Command::new("isolate")
+            .arg("-b")
+            .arg(self.id.to_string())
+            .arg("--cg")
+            .arg("--cleanup")
+            .output()?;
+
+        if !output.status.success() {
+            bail!("isolate --cleanup exited with non-zero status");
+        }
+        Ok(())
+    }
+}
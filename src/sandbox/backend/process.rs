@@ -0,0 +1,324 @@
+use std::fmt::Write as _;
+use std::os::unix::process::CommandExt;
+
+use super::*;
+
+/// Interval between checks of a running program's wall time and memory usage
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// INSECURE sandbox backend running programs as plain host processes
+///
+/// Programs only get rlimits and an empty temporary working directory. They can
+/// read and write anything the judge user can and reach the network, and the
+/// process count limit is not enforced. Memory is measured as resident set size
+/// instead of cgroup usage. This backend exists for development and for testing
+/// the judging pipeline on machines without isolate, never use it in production.
+pub(in crate::sandbox) struct ProcessBackend {
+    dir: PathBuf,
+    cpus: Vec<usize>,
+}
+
+impl ProcessBackend {
+    pub fn new(id: u8, cpus: Vec<usize>) -> Self {
+        Self {
+            dir: std::env::temp_dir().join(format!("oj-sandbox-{id}")),
+            cpus,
+        }
+    }
+}
+
+impl SandboxBackend for ProcessBackend {
+    fn init(&self) -> anyhow::Result<PathBuf> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        fs::create_dir_all(&self.dir)?;
+        fs::set_permissions(&self.dir, fs::Permissions::from_mode(CACHE_DIR_PERMISSIONS))?;
+        Ok(self.dir.clone())
+    }
+
+    fn run(&self, spec: RunSpec) -> anyhow::Result<()> {
+        let limits = spec.limits;
+
+        // Resolve relative programs such as `./main` against the working directory
+        let program = match spec.command[0] {
+            program if program.contains('/') => self.dir.join(program),
+            program => PathBuf::from(program),
+        };
+
+        let mut command = Command::new(program);
+        command
+            .args(&spec.command[1..])
+            .current_dir(&self.dir)
+            .env_clear()
+            .stdin(spec.stdin)
+            .stdout(spec.stdout)
+            .stderr(spec.stderr);
+        // Nothing is mounted from `/opt/oj`, so keep the host toolchains reachable:
+        // extend PATH and fall back to the host environment for missing toolchain dirs
+        for (key, value) in spec.env {
+            let host_value = std::env::var(key).ok();
+            if *key == "PATH" {
+                let host_path = host_value.unwrap_or_default();
+                command.env(key, format!("{value}:{host_path}"));
+            } else if !value.starts_with("/opt/oj") || Path::new(value).exists() {
+                command.env(key, value);
+            } else if let Some(host_value) = host_value {
+                command.env(key, host_value);
+            }
+        }
+        if let Ok(home) = std::env::var("HOME") {
+            command.env("HOME", home);
+        }
+
+        let rlimits = [
+            (libc::RLIMIT_NOFILE, limits.open_files as libc::rlim_t),
+            (libc::RLIMIT_FSIZE, limits.file_size as libc::rlim_t * 1024),
+            // Safety net in case the wall clock polling falls behind
+            (
+                libc::RLIMIT_CPU,
+                limits.wall_time.ceil() as libc::rlim_t + 1,
+            ),
+        ];
+        let stack = limits
+            .stack
+            .filter(|&stack| stack > 0)
+            .map(|stack| stack as libc::rlim_t * 1024);
+        // SAFETY: only async-signal-safe `setpgid` and `setrlimit` calls are made between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                // Start a process group, so that the whole program can be killed at once
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let stack = stack.map(|stack| (libc::RLIMIT_STACK, stack));
+                for (resource, limit) in rlimits.into_iter().chain(stack) {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: limit,
+                        rlim_max: limit,
+                    };
+                    if libc::setrlimit(resource, &rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        if let Some(fd) = spec.pass_fd {
+            pass_fd(&mut command, fd);
+        }
+        pin_to_cpus(&mut command, &self.cpus);
+
+        let start_time = Instant::now();
+        let child = command.spawn()?;
+        // Close the host's copies of pipe ends, so that the other side sees EOF
+        drop(command);
+
+        let outcome = wait_with_limits(child.id() as libc::pid_t, start_time, &limits)?;
+        fs::write(spec.meta_path, outcome.to_meta(start_time.elapsed()))?;
+        Ok(())
+    }
+
+    fn cleanup(&self) -> anyhow::Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a program run by the process backend ended
+struct RunOutcome {
+    /// Raw wait status
+    status: libc::c_int,
+    usage: libc::rusage,
+    /// Peak resident set size in KB
+    max_rss: u64,
+    wall_time_exceeded: bool,
+    memory_exceeded: bool,
+}
+
+impl RunOutcome {
+    /// Formats the outcome as an isolate meta file
+    fn to_meta(&self, wall_time: Duration) -> String {
+        let to_secs = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+        let cpu_time = to_secs(self.usage.ru_utime) + to_secs(self.usage.ru_stime);
+
+        let mut meta = String::new();
+        if self.wall_time_exceeded {
+            meta.push_str("status:TO\nmessage:Time limit exceeded (wall clock)\nkilled:1\n");
+        }
+        let _ = writeln!(meta, "time:{cpu_time:.3}");
+        let _ = writeln!(meta, "time-wall:{:.3}", wall_time.as_secs_f64());
+        let _ = writeln!(meta, "max-rss:{}", self.max_rss);
+        let _ = writeln!(meta, "cg-mem:{}", self.max_rss);
+        if self.wall_time_exceeded {
+            return meta;
+        }
+        if self.memory_exceeded {
+            meta.push_str("cg-oom-killed:1\n");
+        }
+
+        // Like isolate, report a program killed by a signal by the signal, without exit code
+        if libc::WIFSIGNALED(self.status) {
+            let signal = libc::WTERMSIG(self.status);
+            let _ = writeln!(
+                meta,
+                "exitsig:{signal}\nstatus:SG\nmessage:Caught fatal signal {signal}"
+            );
+            return meta;
+        }
+        let exit_code = libc::WEXITSTATUS(self.status);
+        let _ = writeln!(meta, "exitcode:{exit_code}");
+        if exit_code != 0 {
+            let _ = writeln!(
+                meta,
+                "status:RE\nmessage:Exited with error status {exit_code}"
+            );
+        }
+        meta
+    }
+}
+
+/// Waits for a program, killing its process group once it exceeds a limit
+fn wait_with_limits(
+    pid: libc::pid_t,
+    start_time: Instant,
+    limits: &RunLimits,
+) -> anyhow::Result<RunOutcome> {
+    let deadline = start_time + Duration::from_secs_f64(limits.wall_time);
+    let mut outcome = RunOutcome {
+        status: 0,
+        // SAFETY: `rusage` is plain old data, for which all zeros is valid
+        usage: unsafe { std::mem::zeroed() },
+        max_rss: 0,
+        wall_time_exceeded: false,
+        memory_exceeded: false,
+    };
+
+    loop {
+        // SAFETY: `pid` is a child of this process that has not been reaped yet
+        let reaped =
+            unsafe { libc::wait4(pid, &mut outcome.status, libc::WNOHANG, &mut outcome.usage) };
+        if reaped == pid {
+            break;
+        }
+        if reaped < 0 {
+            bail!(
+                "Failed to wait for process {pid}: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        outcome.max_rss = outcome.max_rss.max(group_resident_set_size(pid));
+        if exceeds_memory_limit(outcome.max_rss, limits) {
+            outcome.memory_exceeded = true;
+        } else if Instant::now() >= deadline {
+            outcome.wall_time_exceeded = true;
+        } else {
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        // SAFETY: the process group was created by the child before exec
+        unsafe { libc::killpg(pid, libc::SIGKILL) };
+        // SAFETY: as above
+        unsafe { libc::wait4(pid, &mut outcome.status, 0, &mut outcome.usage) };
+        break;
+    }
+
+    // Peaks between polls only show up in the final resource usage
+    outcome.max_rss = outcome.max_rss.max(outcome.usage.ru_maxrss as u64);
+    if exceeds_memory_limit(outcome.max_rss, limits) {
+        outcome.memory_exceeded = true;
+    }
+    Ok(outcome)
+}
+
+/// Checks a memory usage in KB against the limit, where a limit of 0 means unlimited
+fn exceeds_memory_limit(memory: u64, limits: &RunLimits) -> bool {
+    limits.memory > 0 && memory > limits.memory as u64
+}
+
+/// Sums the current resident set sizes of all processes in a process group in KB
+///
+/// The whole group counts, as programs may run under a wrapper or fork.
+fn group_resident_set_size(pgid: libc::pid_t) -> u64 {
+    // SAFETY: `sysconf` has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join("stat")).ok())
+        .filter_map(|stat| {
+            // Fields after the command name, which may contain spaces, start with the state
+            let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+            let pgrp: libc::pid_t = fields.get(2)?.parse().ok()?;
+            let rss_pages: u64 = fields.get(21)?.parse().ok()?;
+            (pgrp == pgid).then_some(rss_pages * page_size / 1024)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_shell(script: &str, wall_time: f64) -> String {
+        let backend = ProcessBackend::new(200, Vec::new());
+        backend.init().unwrap();
+        let meta_path = std::env::temp_dir().join("oj-sandbox-200.meta");
+        backend
+            .run(RunSpec {
+                command: &["/bin/sh", "-c", script],
+                env: &[("PATH", "/usr/bin:/bin")],
+                limits: RunLimits {
+                    wall_time,
+                    memory: 262144,
+                    stack: None,
+                    processes: 4,
+                    open_files: 30,
+                    file_size: 1024,
+                },
+                toolchains: false,
+                extra_dirs: &[],
+                meta_path: &meta_path,
+                stdin: Stdio::null(),
+                stdout: Stdio::null(),
+                stderr: Stdio::null(),
+                pass_fd: None,
+            })
+            .unwrap();
+        backend.cleanup().unwrap();
+        fs::read_to_string(meta_path).unwrap()
+    }
+
+    #[test]
+    fn test_process_backend_meta() {
+        let meta = run_shell("exit 0", 5.0);
+        assert!(meta.contains("exitcode:0\n"));
+        assert!(!meta.contains("status:"));
+
+        let meta = run_shell("exit 3", 5.0);
+        assert!(meta.contains("exitcode:3\nstatus:RE\n"));
+
+        // Memory of child processes counts as well
+        let meta = run_shell(
+            "dd if=/dev/zero of=/dev/null bs=300M count=1 2>/dev/null",
+            5.0,
+        );
+        assert!(meta.contains("cg-oom-killed:1\n"), "{meta}");
+
+        let meta = run_shell("kill -SEGV $$", 5.0);
+        assert!(meta.contains("exitsig:11\nstatus:SG\n"), "{meta}");
+        assert!(!meta.contains("exitcode:"));
+
+        let meta = run_shell("sleep 5", 0.2);
+        assert!(meta.starts_with("status:TO\n"));
+        assert!(meta.contains("killed:1\n"));
+    }
+}
@@ -0,0 +1,197 @@
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ExitStatus};
+
+use super::*;
+
+/// Runs the special judge of a problem on the program output and returns its verdict
+///
+/// The special judge runs outside the sandbox. The first line of its stdout is the
+/// verdict, one of `Accepted`, `Wrong Answer` or `Partially Correct <fraction>` with
+/// the fraction in [0, 1], and the second line is reported as case info. Anything
+/// else, a non-zero exit code or a timeout is an `SPJ Error`.
+pub(super) fn run_special_judge(
+    output_file: &Path,
+    case_config: &OneCaseConfig,
+    problem: &OneProblemConfig,
+) -> anyhow::Result<OutputCheck> {
+    let Some(template) = problem
+        .misc
+        .special_judge
+        .as_ref()
+        .filter(|c| !c.is_empty())
+    else {
+        return Ok(OutputCheck::failed(
+            "SPJ Error",
+            "Special judge is not configured".to_string(),
+        ));
+    };
+
+    let output_path = output_file.to_string_lossy();
+    let verdict_path = output_file.with_extension("spj");
+    let start_time = Instant::now();
+    let child = build_checker_command(
+        template,
+        &[
+            ("%OUTPUT%", &output_path),
+            ("%ANSWER%", &case_config.answer_file),
+            ("%INPUT%", &case_config.input_file),
+        ],
+        SPJ_TIME_LIMIT,
+    )
+    .stdin(Stdio::null())
+    .stdout(fs::File::create(&verdict_path)?)
+    .stderr(Stdio::null())
+    .spawn();
+
+    let status = match child {
+        Ok(mut child) => wait_with_deadline(
+            &mut child,
+            start_time + Duration::from_secs_f64(SPJ_TIME_LIMIT),
+        )?,
+        Err(e) => {
+            log::error!("Failed to spawn special judge: {e}");
+            return Ok(OutputCheck::failed(
+                "SPJ Error",
+                "Failed to spawn special judge".to_string(),
+            ));
+        }
+    };
+
+    match status.and_then(|status| status.code()) {
+        Some(0) => {}
+        Some(code) => {
+            return Ok(OutputCheck::failed(
+                "SPJ Error",
+                format!("Special judge exited with code {code}"),
+            ));
+        }
+        None => {
+            return Ok(OutputCheck::failed(
+                "SPJ Error",
+                "Special judge was killed or exceeded its time limit".to_string(),
+            ));
+        }
+    }
+
+    let verdict = fs::read_to_string(&verdict_path).unwrap_or_default();
+    Ok(parse_special_judge_verdict(&verdict).unwrap_or_else(|| {
+        OutputCheck::failed(
+            "SPJ Error",
+            "Special judge produced an invalid verdict".to_string(),
+        )
+    }))
+}
+
+/// Parses the stdout of a special judge, see [`run_special_judge`]
+fn parse_special_judge_verdict(verdict: &str) -> Option<OutputCheck> {
+    let mut lines = verdict.lines();
+    let status = lines.next()?.trim();
+    let info = lines.next().unwrap_or_default().trim().to_string();
+
+    let fraction = match status {
+        "Accepted" => 1.0,
+        "Wrong Answer" => 0.0,
+        _ => parse_score_fraction(status.strip_prefix("Partially Correct")?)?,
+    };
+
+    Some(OutputCheck::partial(fraction, info))
+}
+
+/// Parses a score fraction reported by a checker, which must lie in [0, 1]
+pub(super) fn parse_score_fraction(text: &str) -> Option<f64> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|fraction| (0.0..=1.0).contains(fraction))
+}
+
+/// Builds a trusted checker command from its template, with resource limits applied
+///
+/// Each placeholder in `substitutions` is replaced in every argument. The checker
+/// gets `cpu_time_limit` seconds of CPU time and `CHECKER_MEMORY_LIMIT` of memory.
+pub(super) fn build_checker_command(
+    template: &[String],
+    substitutions: &[(&str, &str)],
+    cpu_time_limit: f64,
+) -> Command {
+    let args: Vec<String> = template
+        .iter()
+        .map(|arg| {
+            substitutions
+                .iter()
+                .fold(arg.clone(), |arg, (from, to)| arg.replace(from, to))
+        })
+        .collect();
+
+    let cpu_time_limit = cpu_time_limit.ceil() as libc::rlim_t;
+
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    // SAFETY: only async-signal-safe `setrlimit` calls are made between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            let cpu = libc::rlimit {
+                rlim_cur: cpu_time_limit,
+                rlim_max: cpu_time_limit,
+            };
+            let memory = libc::rlimit {
+                rlim_cur: CHECKER_MEMORY_LIMIT as libc::rlim_t,
+                rlim_max: CHECKER_MEMORY_LIMIT as libc::rlim_t,
+            };
+            if libc::setrlimit(libc::RLIMIT_CPU, &cpu) != 0
+                || libc::setrlimit(libc::RLIMIT_AS, &memory) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    command
+}
+
+/// Waits for a child process, killing it once the deadline has passed
+///
+/// Returns `None` if the child had to be killed.
+pub(super) fn wait_with_deadline(
+    child: &mut Child,
+    deadline: Instant,
+) -> anyhow::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_special_judge_verdict() {
+        let check = parse_special_judge_verdict("Accepted\nThe error is within bounds\n").unwrap();
+        assert_eq!(check.fraction, 1.0);
+        assert_eq!(check.info, "The error is within bounds");
+
+        let check = parse_special_judge_verdict("Partially Correct 0.6\nSuboptimal").unwrap();
+        assert_eq!(check.fraction, 0.6);
+        assert_eq!(check.info, "Suboptimal");
+
+        assert_eq!(
+            parse_special_judge_verdict("Wrong Answer")
+                .unwrap()
+                .fraction,
+            0.0
+        );
+        assert!(parse_special_judge_verdict("Partially Correct 1.5").is_none());
+        assert!(parse_special_judge_verdict("OK").is_none());
+        assert!(parse_special_judge_verdict("").is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::Mutex;
+
+use super::checker::wait_with_deadline;
+use super::compile::apply_template_and_join;
+use super::compile_cache::hash_source;
+use super::*;
+use crate::config::{CheckerSource, LanguageConfig};
+
+/// Compiled checker programs, cached on disk by content hash
+///
+/// Checkers are trusted code, so they are compiled directly on the host rather than
+/// in a contestant's sandbox, though with the same environment, so that they find the
+/// same toolchains. Each successful build lives in `<cache>/checkers/<hash>/`,
+/// where the hash covers the source and the language config, so a checker is only
+/// rebuilt when either changes. Failed builds are remembered in memory only, so that
+/// a restart retries them.
+pub struct CheckerCache {
+    dir: PathBuf,
+    /// Compiler logs of failed builds, by hash
+    failures: Mutex<HashMap<String, String>>,
+    /// Serializes compilation, so that concurrent workers don't build the same checker
+    compile_lock: Mutex<()>,
+}
+
+impl CheckerCache {
+    /// Creates the checker cache under the user cache directory
+    pub fn build() -> anyhow::Result<Self> {
+        use directories::ProjectDirs;
+
+        let proj_dirs = ProjectDirs::from("", "", "oj")
+            .ok_or_else(|| anyhow!("Unable to find user directory"))?;
+
+        let dir = proj_dirs.cache_dir().join("checkers");
+        fs::create_dir_all(&dir)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(CACHE_DIR_PERMISSIONS))?;
+
+        Ok(Self {
+            dir,
+            failures: Mutex::new(HashMap::new()),
+            compile_lock: Mutex::new(()),
+        })
+    }
+
+    /// Resolves the checker of a problem into a command that runs the compiled binary
+    ///
+    /// Problems without a checker source are returned unchanged. Otherwise the checker
+    /// is compiled if needed and set as the `special_judge` or `interactor` command,
+    /// depending on the judge type. On failure, the compiler log is returned.
+    pub fn prepare(
+        &self,
+        mut problem: OneProblemConfig,
+        languages: &LanguageConfig,
+    ) -> Result<OneProblemConfig, String> {
+        let Some(checker) = problem.misc.checker.clone() else {
+            return Ok(problem);
+        };
+
+        let binary = self.compile(&checker, languages)?;
+        let default_args: &[&str] = match problem.judge_type {
+            JudgeType::Interactive => &["%INPUT%", "%ANSWER%"],
+            _ => &["%OUTPUT%", "%ANSWER%"],
+        };
+        let mut command = vec![binary.to_string_lossy().to_string()];
+        match checker.args {
+            Some(args) => command.extend(args),
+            None => command.extend(default_args.iter().map(|arg| arg.to_string())),
+        }
+
+        match problem.judge_type {
+            JudgeType::Spj => problem.misc.special_judge = Some(command),
+            JudgeType::Interactive => problem.misc.interactor = Some(command),
+            _ => log::warn!(
+                "Checker of problem {} ignored for judge type {:?}",
+                problem.id,
+                problem.judge_type
+            ),
+        }
+
+        Ok(problem)
+    }
+
+    /// Returns the path of the compiled checker, compiling it if the source changed
+    fn compile(
+        &self,
+        checker: &CheckerSource,
+        languages: &LanguageConfig,
+    ) -> Result<PathBuf, String> {
+        let language = languages
+            .iter()
+            .find(|l| l.name == checker.language)
+            .ok_or_else(|| format!("Unknown checker language {}", checker.language))?;
+        let source = fs::read(&checker.source)
+            .map_err(|e| format!("Failed to read checker source {}: {e}", checker.source))?;
+
+        let hash = hash_source(language, &source);
+        let target_dir = self.dir.join(&hash);
+        let binary = target_dir.join("main");
+
+        let _guard = self.compile_lock.lock().unwrap();
+        if binary.exists() {
+            return Ok(binary);
+        }
+        if let Some(compile_log) = self.failures.lock().unwrap().get(&hash) {
+            return Err(compile_log.clone());
+        }
+
+        log::info!("Compiling checker {} ({hash})", checker.source);
+        match self.compile_into(&target_dir, &source, language) {
+            Ok(()) => Ok(binary),
+            Err(compile_log) => {
+                log::error!("Checker {} failed to compile", checker.source);
+                self.failures
+                    .lock()
+                    .unwrap()
+                    .insert(hash, compile_log.clone());
+                Err(compile_log)
+            }
+        }
+    }
+
+    /// Compiles a checker in a scratch directory, then moves it to `target_dir`
+    fn compile_into(
+        &self,
+        target_dir: &Path,
+        source: &[u8],
+        language: &OneLanguageConfig,
+    ) -> Result<(), String> {
+        let build_dir = target_dir.with_extension("build");
+        let _ = fs::remove_dir_all(&build_dir);
+        let log_path = build_dir.join("compile.log");
+
+        let spawned = (|| -> std::io::Result<_> {
+            fs::create_dir_all(&build_dir)?;
+            fs::write(build_dir.join(&language.file_name), source)?;
+
+            let mut mapping = HashMap::<&str, &str>::new();
+            mapping.insert("%INPUT%", &language.file_name);
+            mapping.insert("%OUTPUT%", "main");
+            let compile_command = apply_template_and_join(&language.command, &mapping);
+
+            let log_file = fs::File::create(&log_path)?;
+            Command::new("/bin/sh")
+                .args(["-c", &compile_command])
+                .env_clear()
+                .envs(COMPILE_ENV.iter().copied())
+                .current_dir(&build_dir)
+                .stdin(Stdio::null())
+                .stdout(log_file.try_clone()?)
+                .stderr(log_file)
+                .spawn()
+        })();
+
+        let status = match spawned {
+            Ok(mut child) => wait_with_deadline(
+                &mut child,
+                Instant::now() + Duration::from_secs_f64(COMPILE_TIME_LIMIT),
+            )
+            .map_err(|e| format!("Failed to wait for checker compiler: {e}"))?,
+            Err(e) => return Err(format!("Failed to start checker compiler: {e}")),
+        };
+
+        let mut compile_log = fs::read_to_string(&log_path).unwrap_or_default();
+        let compiled = status.is_some_and(|s| s.success()) && build_dir.join("main").exists();
+        if !compiled {
+            if status.is_none() {
+                compile_log.push_str("\nCompilation time limit exceeded");
+            }
+            let _ = fs::remove_dir_all(&build_dir);
+            return Err(compile_log);
+        }
+
+        fs::rename(&build_dir, target_dir)
+            .map_err(|e| format!("Failed to store compiled checker: {e}"))
+    }
+}
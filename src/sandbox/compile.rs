@@ -1,12 +1,16 @@
+use super::artifacts::{COMPILE_LOG_FILE, COMPILE_META_FILE};
 use super::*;
 
 impl Sandbox {
-    /// Compiles the source code and returns compilation result
+    /// Compiles the source code and returns whether compilation succeeded
+    ///
+    /// The compile meta file is kept in the job's artifact directory `job_dir`.
     pub(super) fn compile_source_code(
         &self,
         job: &mut JobRecord,
         language: &OneLanguageConfig,
-    ) -> anyhow::Result<CompilationResult> {
+        job_dir: &Path,
+    ) -> anyhow::Result<bool> {
         // Write source code to sandbox
         let source_name = &language.file_name;
         fs::write(
@@ -15,39 +19,27 @@ impl Sandbox {
         )?;
 
         // Set up compilation paths
-        let timestamped_cache_dir = self.create_timestamped_cache_dir()?;
         let executable_name = "main"; // NOTE: subject to change for `cargo`
         let compile_paths = CompilationPaths {
             executable: self.box_dir.join(executable_name),
             stdout: self.box_dir.join("compile_stdout.txt"),
-            meta: timestamped_cache_dir.join("compile.meta"),
+            meta: job_dir.join(COMPILE_META_FILE),
         };
 
-        // Generate and run compile command
-        let compile_command = self.generate_compile_command(language, source_name, executable_name);
-        self.execute_compile_command(&compile_command, &compile_paths)?;
+        // Reuse a cached result of the same source, or generate and run compile command
+        let cache_key = CompileCache::key(language, &job.submission.source_code);
+        if !self.compile_cache.restore(&cache_key, &compile_paths) {
+            let compile_command =
+                self.generate_compile_command(language, source_name, executable_name);
+            self.execute_compile_command(&compile_command, &compile_paths)?;
+            self.compile_cache.store(&cache_key, &compile_paths);
+        }
 
-        // Process compilation results
-        let compilation_success = self.process_compilation_results(
-            job,
-            &compile_paths,
-            &timestamped_cache_dir,
-            executable_name,
-        )?;
+        // Keep the compiler output with the job artifacts
+        let _ = fs::copy(&compile_paths.stdout, job_dir.join(COMPILE_LOG_FILE));
 
-        Ok(CompilationResult {
-            success: compilation_success,
-            cache_dir: timestamped_cache_dir,
-        })
-    }
-
-    /// Creates a timestamped cache directory for this run
-    fn create_timestamped_cache_dir(&self) -> anyhow::Result<PathBuf> {
-        let timestamped_cache_dir = self
-            .cache_dir
-            .join(Local::now().format("%y%m%d-%H-%M-%S").to_string());
-        fs::create_dir_all(&timestamped_cache_dir)?;
-        Ok(timestamped_cache_dir)
+        // Process compilation results
+        self.process_compilation_results(job, &compile_paths, job_dir, executable_name)
     }
 
     /// Generates the compile command by applying template substitutions
@@ -69,42 +61,27 @@ impl Sandbox {
         compile_command: &str,
         paths: &CompilationPaths,
     ) -> anyhow::Result<()> {
-        let sandbox_id = self.id.to_string();
-        let processes_arg = format!("--processes={COMPILE_PROCESSES}");
-        let open_files_arg = format!("--open-files={COMPILE_OPEN_FILES}");
-        let fsize_arg = format!("--fsize={COMPILE_FILE_SIZE}");
-        let wall_time_arg = format!("--wall-time={COMPILE_TIME_LIMIT}");
-        let memory_arg = format!("--cg-mem={COMPILE_MEMORY_LIMIT}");
-        let meta_path = paths.meta.to_string_lossy();
-        let dir_args = if Path::new("/etc/alternatives").exists() {
-            vec!["--dir=/opt/oj", "--dir=/etc/alternatives"]
-        } else {
-            vec!["--dir=/opt/oj"]
-        };
-
-        let _ = Command::new("isolate")
-            .args(dir_args)
-            .args([
-                "-b", &sandbox_id,
-                "--cg", "--run",
-                &processes_arg,
-                &open_files_arg,
-                &fsize_arg,
-                &wall_time_arg,
-                &memory_arg,
-                "-E", "RUSTUP_HOME=/opt/oj/rust/rustup",
-                "-E", "CARGO_HOME=/opt/oj/rust/cargo", 
-                "-E", "PATH=/opt/oj/rust/cargo/bin:/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
-                "-M", &meta_path,
-                "--silent",
-                "--stderr-to-stdout",
-                "-o", "compile_stdout.txt",
-                "--",
-                "/bin/sh", "-c", compile_command
-            ])
-            .output()?;
-
-        Ok(())
+        let compile_output = fs::File::create(&paths.stdout)?;
+
+        self.backend.run(RunSpec {
+            command: &["/bin/sh", "-c", compile_command],
+            env: COMPILE_ENV,
+            limits: RunLimits {
+                wall_time: COMPILE_TIME_LIMIT,
+                memory: COMPILE_MEMORY_LIMIT,
+                stack: None,
+                processes: COMPILE_PROCESSES,
+                open_files: COMPILE_OPEN_FILES,
+                file_size: COMPILE_FILE_SIZE,
+            },
+            toolchains: true,
+            extra_dirs: &[],
+            meta_path: &paths.meta,
+            stdin: Stdio::null(),
+            stdout: compile_output.try_clone()?.into(),
+            stderr: compile_output.into(),
+            pass_fd: None,
+        })
     }
 
     /// Processes compilation results and updates job status
@@ -155,7 +132,10 @@ impl Sandbox {
 /// This function takes a command template (array of strings) and a mapping
 /// of placeholders to actual values, then replaces all occurrences and
 /// joins the result into a single command string.
-fn apply_template_and_join(cmd_template: &[String], mapping: &HashMap<&str, &str>) -> String {
+pub(super) fn apply_template_and_join(
+    cmd_template: &[String],
+    mapping: &HashMap<&str, &str>,
+) -> String {
     let replaced: Vec<String> = cmd_template
         .iter()
         .map(|s| {
@@ -0,0 +1,264 @@
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::*;
+
+const EXECUTABLE_FILE: &str = "main";
+const OUTPUT_FILE: &str = "compile_stdout.txt";
+const META_FILE: &str = "compile.meta";
+
+/// Compilation results of contestant programs, cached on disk by source hash
+///
+/// Entries live in `<cache>/compile/<hash>/`, where the hash covers the language
+/// config and the normalized source. An entry holds the isolate meta file and the
+/// compiler output, plus the executable if compilation succeeded. Only deterministic
+/// outcomes are cached: a success, or the compiler rejecting the source. Once the
+/// total size exceeds the limit, least recently used entries are evicted.
+pub struct CompileCache {
+    dir: PathBuf,
+    /// Maximum total size of all entries in bytes, 0 disables the cache
+    max_size: u64,
+    /// Serializes restoring, eviction and purging against each other
+    lock: Mutex<()>,
+}
+
+/// Summary of a cache purge
+#[derive(Debug, Serialize)]
+pub struct CompileCachePurge {
+    pub removed_entries: u32,
+    pub freed_bytes: u64,
+}
+
+impl CompileCache {
+    /// Creates the compile cache under the user cache directory
+    pub fn build(max_size: u64) -> anyhow::Result<Self> {
+        use directories::ProjectDirs;
+
+        let proj_dirs = ProjectDirs::from("", "", "oj")
+            .ok_or_else(|| anyhow!("Unable to find user directory"))?;
+
+        let dir = proj_dirs.cache_dir().join("compile");
+        fs::create_dir_all(&dir)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(CACHE_DIR_PERMISSIONS))?;
+
+        Ok(Self {
+            dir,
+            max_size,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Computes the cache key of a source compiled with a language config
+    ///
+    /// Line endings and trailing whitespace at the end of the source are normalized,
+    /// as they never change the compilation result.
+    pub(super) fn key(language: &OneLanguageConfig, source_code: &str) -> String {
+        let normalized = source_code.replace("\r\n", "\n");
+        hash_source(language, normalized.trim_end().as_bytes())
+    }
+
+    /// Copies a cached compilation result to the given paths, returning whether it existed
+    ///
+    /// An entry of a successful compilation missing its executable is a miss. Other
+    /// processes sharing the cache directory may evict an entry while it is restored.
+    pub(super) fn restore(&self, key: &str, paths: &CompilationPaths) -> bool {
+        if self.max_size == 0 {
+            return false;
+        }
+
+        let _guard = self.lock.lock().unwrap();
+        let entry = self.dir.join(key);
+        let restored = (|| -> std::io::Result<()> {
+            let meta_content = fs::read_to_string(entry.join(META_FILE))?;
+            fs::copy(entry.join(OUTPUT_FILE), &paths.stdout)?;
+            if compiled(&meta_content) {
+                fs::copy(entry.join(EXECUTABLE_FILE), &paths.executable)?;
+            }
+            fs::write(&paths.meta, &meta_content)?;
+            // Mark the entry as recently used for eviction
+            fs::File::options()
+                .write(true)
+                .open(entry.join(META_FILE))?
+                .set_modified(SystemTime::now())
+        })();
+
+        if restored.is_ok() {
+            log::debug!("Compile cache hit for {key}");
+        }
+        restored.is_ok()
+    }
+
+    /// Stores a fresh compilation result, if its outcome is deterministic
+    pub(super) fn store(&self, key: &str, paths: &CompilationPaths) {
+        if self.max_size == 0 {
+            return;
+        }
+
+        // A status other than RE means the compiler was killed or isolate failed
+        let Ok(meta_content) = fs::read_to_string(&paths.meta) else {
+            return;
+        };
+        let cacheable = match meta_content.lines().find_map(|l| l.strip_prefix("status:")) {
+            None => paths.executable.exists(),
+            Some(status) => status == "RE",
+        };
+        if !cacheable {
+            return;
+        }
+
+        let entry = self.dir.join(key);
+        let staging = self.dir.join(format!("{key}.tmp-{}", std::process::id()));
+        let stored = (|| -> std::io::Result<()> {
+            fs::create_dir_all(&staging)?;
+            fs::copy(&paths.meta, staging.join(META_FILE))?;
+            fs::copy(&paths.stdout, staging.join(OUTPUT_FILE))?;
+            if paths.executable.exists() {
+                fs::copy(&paths.executable, staging.join(EXECUTABLE_FILE))?;
+            }
+            fs::rename(&staging, &entry)
+        })();
+
+        if let Err(e) = stored {
+            // Most likely another worker stored the same entry first
+            log::debug!("Compile cache entry {key} not stored: {e}");
+            let _ = fs::remove_dir_all(&staging);
+            return;
+        }
+
+        self.evict();
+    }
+
+    /// Removes least recently used entries until the cache fits its size limit
+    fn evict(&self) {
+        let _guard = self.lock.lock().unwrap();
+
+        let mut entries = self.entries();
+        let mut total_size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort_by_key(|(_, _, last_used)| *last_used);
+
+        for (path, size, _) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+            if fs::remove_dir_all(&path).is_ok() {
+                log::debug!("Evicted compile cache entry {}", path.display());
+                total_size -= size;
+            }
+        }
+    }
+
+    /// Removes every entry of the cache
+    pub fn purge(&self) -> CompileCachePurge {
+        let _guard = self.lock.lock().unwrap();
+
+        let mut purge = CompileCachePurge {
+            removed_entries: 0,
+            freed_bytes: 0,
+        };
+        for (path, size, _) in self.entries() {
+            if fs::remove_dir_all(&path).is_ok() {
+                purge.removed_entries += 1;
+                purge.freed_bytes += size;
+            }
+        }
+
+        log::info!(
+            "Purged {} compile cache entries ({} bytes)",
+            purge.removed_entries,
+            purge.freed_bytes
+        );
+        purge
+    }
+
+    /// Lists complete entries with their total size and last use time
+    fn entries(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        read_dir
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                // Skip entries still being staged by `store`
+                if path.extension().is_some() {
+                    return None;
+                }
+                let last_used = fs::metadata(path.join(META_FILE))
+                    .and_then(|m| m.modified())
+                    .ok()?;
+                let size = fs::read_dir(&path)
+                    .ok()?
+                    .flatten()
+                    .filter_map(|f| f.metadata().ok())
+                    .map(|m| m.len())
+                    .sum();
+                Some((path, size, last_used))
+            })
+            .collect()
+    }
+}
+
+/// Whether a compile meta file records a successful compilation, which has no status
+fn compiled(meta_content: &str) -> bool {
+    !meta_content.lines().any(|line| line.starts_with("status:"))
+}
+
+/// Hashes a source together with the language config used to build it
+pub(super) fn hash_source(language: &OneLanguageConfig, source: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(language.name.as_bytes());
+    hasher.update([0]);
+    hasher.update(language.file_name.as_bytes());
+    for arg in &language.command {
+        hasher.update([0]);
+        hasher.update(arg.as_bytes());
+    }
+    hasher.update([0]);
+    hasher.update(source);
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_without_executable_is_miss() {
+        let dir = std::env::temp_dir().join(format!("oj-compile-cache-{}", std::process::id()));
+        let cache = CompileCache {
+            dir: dir.join("cache"),
+            max_size: u64::MAX,
+            lock: Mutex::new(()),
+        };
+        let paths = CompilationPaths {
+            executable: dir.join("main"),
+            stdout: dir.join("stdout"),
+            meta: dir.join("meta"),
+        };
+        let write_entry = |key: &str, meta: &str| {
+            let entry = cache.dir.join(key);
+            fs::create_dir_all(&entry).unwrap();
+            fs::write(entry.join(META_FILE), meta).unwrap();
+            fs::write(entry.join(OUTPUT_FILE), "").unwrap();
+        };
+
+        // A successful compilation whose executable was evicted meanwhile
+        write_entry("compiled", "time:0.100\nexitcode:0\n");
+        assert!(!cache.restore("compiled", &paths));
+
+        // The compiler rejected the source, so there is no executable to restore
+        write_entry("rejected", "status:RE\nexitcode:1\n");
+        assert!(cache.restore("rejected", &paths));
+        assert!(!paths.executable.exists());
+
+        fs::write(cache.dir.join("compiled").join(EXECUTABLE_FILE), "binary").unwrap();
+        assert!(cache.restore("compiled", &paths));
+        assert_eq!(fs::read_to_string(&paths.executable).unwrap(), "binary");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
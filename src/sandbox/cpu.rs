@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+use std::os::unix::process::CommandExt;
+
+use super::*;
+use crate::config::CpuPinning;
+
+/// Assigns a CPU set to each of `workers` sandboxes, the first for the first worker
+///
+/// Configured sets are used in order. Otherwise every worker gets one of the cores
+/// this process may run on, leaving the first core to the server when there are
+/// cores to spare. Workers share cores round-robin when there are not enough,
+/// unless pinning is strict, in which case that's an error.
+pub fn assign_cpu_sets(pinning: &CpuPinning, workers: u8) -> anyhow::Result<Vec<Vec<usize>>> {
+    assign_from(pinning, workers as usize, &available_cpus()?)
+}
+
+fn assign_from(
+    pinning: &CpuPinning,
+    workers: usize,
+    available: &[usize],
+) -> anyhow::Result<Vec<Vec<usize>>> {
+    let sets = match &pinning.cpus {
+        Some(sets) => {
+            for cpu in sets.iter().flatten() {
+                if !available.contains(cpu) {
+                    bail!("CPU {cpu} is not available to this process");
+                }
+            }
+            if sets.is_empty() || sets.iter().any(Vec::is_empty) {
+                bail!("CPU sets must not be empty");
+            }
+            sets.clone()
+        }
+        None => {
+            let spare = usize::from(available.len() > workers);
+            available[spare..].iter().map(|&cpu| vec![cpu]).collect()
+        }
+    };
+
+    let mut seen = HashSet::new();
+    let dedicated = sets.iter().flatten().all(|cpu| seen.insert(cpu));
+    if pinning.strict {
+        if !dedicated {
+            bail!("CPU sets overlap, so cores are not dedicated to workers");
+        }
+        if workers > sets.len() {
+            bail!(
+                "{workers} workers requested, but only {} have a dedicated CPU set",
+                sets.len()
+            );
+        }
+    } else if workers > sets.len() {
+        log::warn!(
+            "{workers} workers share {} CPU sets, timing will be less precise",
+            sets.len()
+        );
+    }
+
+    Ok(sets.iter().cycle().take(workers).cloned().collect())
+}
+
+/// Returns the CPUs this process is allowed to run on
+fn available_cpus() -> anyhow::Result<Vec<usize>> {
+    // SAFETY: `cpu_set_t` is plain old data, for which all zeros is an empty set
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: `set` has the size passed to the call
+    if unsafe { libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        bail!(
+            "Failed to get CPU affinity: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok((0..libc::CPU_SETSIZE as usize)
+        // SAFETY: `cpu` is below `CPU_SETSIZE`
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+/// Makes a command run on the given CPUs only, an empty set leaves it unpinned
+pub(super) fn pin_to_cpus(command: &mut Command, cpus: &[usize]) {
+    if cpus.is_empty() {
+        return;
+    }
+    // SAFETY: `cpu_set_t` is plain old data, for which all zeros is an empty set
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        // SAFETY: out of range CPUs are rejected by `assign_cpu_sets`
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // SAFETY: only the async-signal-safe `sched_setaffinity` is called between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_cpu_sets() {
+        let auto = CpuPinning::default();
+        let strict = CpuPinning {
+            strict: true,
+            ..CpuPinning::default()
+        };
+        let available = [0, 1, 2, 3];
+
+        // The first core is left to the server when there are spare cores
+        assert_eq!(
+            assign_from(&auto, 2, &available).unwrap(),
+            [vec![1], vec![2]]
+        );
+        assert_eq!(
+            assign_from(&strict, 4, &available).unwrap(),
+            [vec![0], vec![1], vec![2], vec![3]]
+        );
+        assert_eq!(
+            assign_from(&auto, 5, &available).unwrap(),
+            [vec![0], vec![1], vec![2], vec![3], vec![0]]
+        );
+        assert!(assign_from(&strict, 5, &available).is_err());
+
+        let configured = CpuPinning {
+            cpus: Some(vec![vec![2, 3], vec![3]]),
+            strict: false,
+        };
+        assert_eq!(
+            assign_from(&configured, 3, &available).unwrap(),
+            [vec![2, 3], vec![3], vec![2, 3]]
+        );
+        let overlapping = CpuPinning {
+            strict: true,
+            ..configured
+        };
+        assert!(assign_from(&overlapping, 2, &available).is_err());
+        let unavailable = CpuPinning {
+            cpus: Some(vec![vec![7]]),
+            strict: false,
+        };
+        assert!(assign_from(&unavailable, 1, &available).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use super::checker::{build_checker_command, parse_score_fraction, wait_with_deadline};
+use super::seccomp::{apply_seccomp_report, program_command};
+use super::testing::runtime_limits;
+use super::*;
+
+impl Sandbox {
+    /// Runs a single test case of an interactive problem and returns the result
+    ///
+    /// The contestant's program runs inside the sandbox with the case limits, while
+    /// the trusted interactor runs outside of it with fixed limits. The two are
+    /// connected through pipes, and only the interactor sees the case input file.
+    /// The interactor's exit code decides the verdict: 0 accepts, 1 rejects, 2 awards
+    /// the score fraction on the first line of its stderr, and anything else is a
+    /// system error. The rest of its stderr is reported as case info.
+    ///
+    /// The interactor's verdict is returned alongside the program result, unless the
+    /// program already failed in a way that the interactor cannot overrule.
+    pub(super) fn run_interactive_test_case(
+        &self,
+        case_idx: usize,
+        case_config: &OneCaseConfig,
+        problem: &OneProblemConfig,
+        seccomp: Option<SeccompPolicy>,
+        cache_dir: &Path,
+    ) -> anyhow::Result<(TestCaseResult, Option<OutputCheck>)> {
+        let mut result = TestCaseResult {
+            time: 0,
+            memory: 0,
+            error: None,
+            info: String::new(),
+            stdout_content: String::new(),
+        };
+
+        let Some(interactor_template) = problem.misc.interactor.as_ref().filter(|c| !c.is_empty())
+        else {
+            result.error = Some("System Error");
+            result.info = "Interactor is not configured".to_string();
+            return Ok((result, None));
+        };
+
+        let meta_path = cache_dir.join(format!("{case_idx}.meta"));
+        let interactor_log_path = cache_dir.join(format!("{case_idx}.interactor"));
+        let program_stderr_path = cache_dir.join(format!("{case_idx}.err"));
+
+        // Connect the program and the interactor with a pipe in each direction
+        let (interactor_stdin, program_stdout) = std::io::pipe()?;
+        let (program_stdin, interactor_stdout) = std::io::pipe()?;
+
+        // Start the interactor first, as running the program blocks until it exits
+        let start_time = Instant::now();
+        let input_file = &case_config.input_file;
+        let answer_file = &case_config.answer_file;
+        let interactor = build_checker_command(
+            interactor_template,
+            &[("%INPUT%", input_file), ("%ANSWER%", answer_file)],
+            Second::from(case_config.time_limit).0 + INTERACTOR_EXTRA_TIME,
+        )
+        .stdin(interactor_stdin)
+        .stdout(interactor_stdout)
+        .stderr(fs::File::create(&interactor_log_path)?)
+        .spawn();
+
+        let mut interactor = match interactor {
+            Ok(child) => child,
+            Err(e) => {
+                log::error!("Failed to spawn interactor: {e}");
+                result.error = Some("System Error");
+                result.info = "Failed to spawn interactor".to_string();
+                return Ok((result, None));
+            }
+        };
+
+        // If the interactor exits early, the pipes are closed and the program
+        // terminates on its own
+        let program = program_command(seccomp)?;
+        let command: Vec<&str> = program.command.iter().map(String::as_str).collect();
+        self.backend.run(RunSpec {
+            command: &command,
+            env: RUNTIME_ENV,
+            limits: runtime_limits(case_config),
+            toolchains: false,
+            extra_dirs: &program.extra_dirs,
+            meta_path: &meta_path,
+            stdin: program_stdin.into(),
+            stdout: program_stdout.into(),
+            stderr: fs::File::create(&program_stderr_path)?.into(),
+            pass_fd: program.report_writer,
+        })?;
+        let elapsed_time = start_time.elapsed();
+
+        let wall_time_limit = Second::from(case_config.time_limit).0 + 0.5 + INTERACTOR_EXTRA_TIME;
+        let interactor_status = wait_with_deadline(
+            &mut interactor,
+            start_time + Duration::from_secs_f64(wall_time_limit),
+        )?;
+        let interactor_log = fs::read_to_string(&interactor_log_path)
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+
+        // Read meta file for execution information
+        if let Ok(meta_content) = fs::read_to_string(&meta_path) {
+            self.process_meta_content(&meta_content, &mut result);
+        } else {
+            result.error = Some("System Error");
+            result.info = "Failed to read meta file".to_string();
+            return Ok((result, None));
+        }
+
+        // Use external wall timer to modify the result
+        if elapsed_time.as_micros() as u32 > case_config.time_limit.0 {
+            result.time = elapsed_time.as_micros() as u32;
+            result.error = Some("Time Limit Exceeded");
+        }
+        apply_seccomp_report(program.report_reader, &mut result);
+
+        // Resource limit verdicts of the program take precedence over the interactor
+        if matches!(
+            result.error,
+            Some(
+                "Time Limit Exceeded"
+                    | "Memory Limit Exceeded"
+                    | "Restricted Function"
+                    | "System Error"
+            )
+        ) {
+            return Ok((result, None));
+        }
+
+        let exit_code = interactor_status.and_then(|status| status.code());
+        let check = judge_interaction(exit_code, &interactor_log, &mut result);
+
+        Ok((result, Some(check)))
+    }
+}
+
+/// Turns the exit code and stderr of the interactor into a verdict
+///
+/// A runtime error of the program is dropped only if the interactor rejected the
+/// interaction, since the program may just have been cut off by it. A program that
+/// crashed keeps its runtime error when the interactor awarded any score.
+fn judge_interaction(
+    exit_code: Option<i32>,
+    interactor_log: &str,
+    result: &mut TestCaseResult,
+) -> OutputCheck {
+    let check = match exit_code {
+        Some(0) => OutputCheck::partial(1.0, interactor_log.to_string()),
+        Some(1) => OutputCheck::wrong(interactor_log.to_string()),
+        Some(2) => {
+            let (fraction, info) = interactor_log
+                .split_once('\n')
+                .unwrap_or((interactor_log, ""));
+            match parse_score_fraction(fraction) {
+                Some(fraction) => OutputCheck::partial(fraction, info.trim().to_string()),
+                None => OutputCheck::failed(
+                    "System Error",
+                    "Interactor reported an invalid score fraction".to_string(),
+                ),
+            }
+        }
+        Some(code) => OutputCheck::failed(
+            "System Error",
+            format!("Interactor exited with code {code}: {interactor_log}"),
+        ),
+        None => OutputCheck::failed(
+            "System Error",
+            "Interactor was killed or exceeded its time limit".to_string(),
+        ),
+    };
+
+    if check.is_rejected() {
+        result.error = None;
+    }
+    check
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_error_stands_unless_rejected() {
+        let crashed = || TestCaseResult {
+            time: 0,
+            memory: 0,
+            error: Some("Runtime Error"),
+            info: String::new(),
+            stdout_content: String::new(),
+        };
+
+        let mut result = crashed();
+        let check = judge_interaction(Some(2), "0.5\nHalf of the queries", &mut result);
+        assert_eq!(check.fraction, 0.5);
+        assert_eq!(result.error, Some("Runtime Error"));
+
+        let mut result = crashed();
+        judge_interaction(Some(0), "", &mut result);
+        assert_eq!(result.error, Some("Runtime Error"));
+
+        // Cut off by the interactor, which gives the verdict
+        let mut result = crashed();
+        let check = judge_interaction(Some(1), "Wrong guess", &mut result);
+        assert_eq!(result.error, None);
+        assert_eq!(check.info, "Wrong guess");
+
+        let mut result = crashed();
+        judge_interaction(Some(2), "0\n", &mut result);
+        assert_eq!(result.error, None);
+    }
+}
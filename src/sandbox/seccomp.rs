@@ -0,0 +1,724 @@
+//! Seccomp filtering of contestant programs
+//!
+//! Programs of languages with a `seccomp` policy are started through the
+//! `oj-seccomp` wrapper, which installs a default-deny syscall filter before
+//! executing them. Syscalls outside the allowlist are not failed silently, but
+//! handed to the wrapper as seccomp user notifications. The wrapper then kills the
+//! program and reports the attempted syscall, from which the judge reports
+//! `Restricted Function`.
+//!
+//! Reports go through a pipe the wrapper gets as descriptor [`PASSED_FD`], rather
+//! than through the box, so that the program can't forge them. The program doesn't
+//! inherit the descriptor, and can't take it from the wrapper through `/proc`.
+
+use std::ffi::CString;
+use std::io::{PipeReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use clap::ValueEnum;
+
+use super::backend::PASSED_FD;
+use super::*;
+use crate::config::SeccompPolicy;
+
+/// Name of the wrapper binary, installed next to the `oj` binary
+const WRAPPER_NAME: &str = "oj-seccomp";
+
+/// Exit status of the wrapper after stopping a program, as if killed by SIGSYS
+const VIOLATION_EXIT_CODE: i32 = 128 + libc::SIGSYS;
+
+/// `AUDIT_ARCH_X86_64` from `linux/audit.h`
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+
+/// `SECCOMP_IOCTL_NOTIF_RECV`, i.e. `_IOWR('!', 0, struct seccomp_notif)`
+const SECCOMP_IOCTL_NOTIF_RECV: libc::c_ulong = 0xc050_2100;
+
+/// Syscalls allowed for compiled C, C++ and Rust programs
+///
+/// Covers the dynamic loader, the C and Rust runtimes, memory management and I/O
+/// on files in the box. `sendmsg` is how the wrapper hands the filter's listener
+/// over before exec, the program has no socket left to use it with afterwards.
+const COMPILED_SYSCALLS: &[&str] = &[
+    "read",
+    "write",
+    "readv",
+    "writev",
+    "pread64",
+    "pwrite64",
+    "lseek",
+    "open",
+    "openat",
+    "close",
+    "stat",
+    "fstat",
+    "lstat",
+    "newfstatat",
+    "statx",
+    "access",
+    "faccessat",
+    "faccessat2",
+    "readlink",
+    "readlinkat",
+    "getcwd",
+    "ioctl",
+    "fcntl",
+    "poll",
+    "ppoll",
+    "mmap",
+    "munmap",
+    "mremap",
+    "mprotect",
+    "madvise",
+    "brk",
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigreturn",
+    "sigaltstack",
+    "getpid",
+    "gettid",
+    "getuid",
+    "geteuid",
+    "getgid",
+    "getegid",
+    "uname",
+    "arch_prctl",
+    "set_tid_address",
+    "set_robust_list",
+    "rseq",
+    "prlimit64",
+    "getrlimit",
+    "getrusage",
+    "sysinfo",
+    "sched_getaffinity",
+    "sched_yield",
+    "futex",
+    "getrandom",
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "nanosleep",
+    "gettimeofday",
+    "time",
+    "times",
+    "tgkill",
+    "exit",
+    "exit_group",
+    "execve",
+    "sendmsg",
+];
+
+/// Syscalls allowed for interpreters on top of [`COMPILED_SYSCALLS`]
+///
+/// Interpreters and VMs also start threads and helper processes and manage files
+/// in the box, but networking, tracing and system administration stay blocked.
+const INTERPRETER_SYSCALLS: &[&str] = &[
+    "clone",
+    "clone3",
+    "fork",
+    "vfork",
+    "execveat",
+    "wait4",
+    "waitid",
+    "kill",
+    "getppid",
+    "getpgrp",
+    "getpgid",
+    "getsid",
+    "getgroups",
+    "getresuid",
+    "getresgid",
+    "prctl",
+    "pipe",
+    "pipe2",
+    "dup",
+    "dup2",
+    "dup3",
+    "select",
+    "pselect6",
+    "epoll_create1",
+    "epoll_ctl",
+    "epoll_wait",
+    "epoll_pwait",
+    "eventfd2",
+    "getdents64",
+    "chdir",
+    "fchdir",
+    "mkdir",
+    "mkdirat",
+    "rmdir",
+    "unlink",
+    "unlinkat",
+    "rename",
+    "renameat",
+    "renameat2",
+    "ftruncate",
+    "fsync",
+    "fdatasync",
+    "fadvise64",
+    "chmod",
+    "fchmod",
+    "umask",
+    "utimensat",
+    "statfs",
+    "fstatfs",
+    "membarrier",
+    "get_mempolicy",
+    "sched_getparam",
+    "sched_getscheduler",
+    "timerfd_create",
+    "timerfd_settime",
+];
+
+/// What the wrapper recorded about a run of the program
+#[derive(Debug, PartialEq)]
+enum SeccompReport {
+    /// The program attempted a syscall outside the allowlist
+    Violation(String),
+    /// The wrapper failed to set up the filter or to start the program
+    Failure(String),
+}
+
+impl SeccompReport {
+    fn parse(content: &str) -> Self {
+        let content = content.trim();
+        match content.split_once(' ') {
+            Some(("syscall", name)) => Self::Violation(name.to_string()),
+            Some(("error", message)) => Self::Failure(message.to_string()),
+            _ => Self::Failure(format!("Malformed seccomp report: {content}")),
+        }
+    }
+}
+
+/// The command running the compiled program, with what its run needs
+pub(super) struct ProgramCommand {
+    pub command: Vec<String>,
+    /// Host directories that must be available inside the box, such as the wrapper's
+    pub extra_dirs: Vec<PathBuf>,
+    /// Write end of the wrapper's report pipe, to pass to the run
+    pub report_writer: Option<OwnedFd>,
+    /// Read end of the wrapper's report pipe, for [`apply_seccomp_report`]
+    pub report_reader: Option<PipeReader>,
+}
+
+/// Returns the command running the compiled program
+///
+/// With a policy, the program is started through the wrapper, whose directory
+/// must then be available inside the box.
+pub(super) fn program_command(seccomp: Option<SeccompPolicy>) -> anyhow::Result<ProgramCommand> {
+    let Some(policy) = seccomp else {
+        return Ok(ProgramCommand {
+            command: vec!["./main".to_string()],
+            extra_dirs: Vec::new(),
+            report_writer: None,
+            report_reader: None,
+        });
+    };
+
+    let wrapper = wrapper_path()?;
+    let wrapper_dir = wrapper.parent().map(Path::to_path_buf);
+    let (reader, writer) = std::io::pipe()?;
+    // The report is read once the wrapper exited, it must not block on descendants
+    // SAFETY: setting a flag of a descriptor owned by `reader`
+    if unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(ProgramCommand {
+        command: vec![
+            wrapper.to_string_lossy().to_string(),
+            policy_name(policy).to_string(),
+            "./main".to_string(),
+        ],
+        extra_dirs: wrapper_dir.into_iter().collect(),
+        report_writer: Some(writer.into()),
+        report_reader: Some(reader),
+    })
+}
+
+/// Overrides the case result with the outcome reported by the wrapper, if any
+///
+/// A violation explains whatever else went wrong with the program, so it takes
+/// precedence over the verdict from the meta file.
+pub(super) fn apply_seccomp_report(report: Option<PipeReader>, result: &mut TestCaseResult) {
+    let mut content = String::new();
+    if let Some(mut report) = report {
+        // Reading stops at the end of the pipe, or once it's empty if a writer is left
+        let _ = report.read_to_string(&mut content);
+    }
+    if content.is_empty() {
+        return;
+    }
+
+    match SeccompReport::parse(&content) {
+        SeccompReport::Violation(syscall) => {
+            result.error = Some("Restricted Function");
+            result.info = syscall;
+        }
+        SeccompReport::Failure(message) => {
+            log::error!("Seccomp wrapper failed: {message}");
+            result.error = Some("System Error");
+            result.info = message;
+        }
+    }
+}
+
+/// Returns the path of the wrapper binary, which must exist next to this executable
+pub fn wrapper_path() -> anyhow::Result<PathBuf> {
+    let wrapper = std::env::current_exe()?.with_file_name(WRAPPER_NAME);
+    if !wrapper.is_file() {
+        bail!("Seccomp wrapper {} not found", wrapper.display());
+    }
+    Ok(wrapper)
+}
+
+fn policy_name(policy: SeccompPolicy) -> &'static str {
+    match policy {
+        SeccompPolicy::Compiled => "compiled",
+        SeccompPolicy::Interpreter => "interpreter",
+    }
+}
+
+/// Entry point of the `oj-seccomp` wrapper, returning its exit status
+///
+/// Usage: `oj-seccomp <policy> <program> [args...]`. Runs the program under the
+/// policy and exits the same way it did. Setup failures are reported as well, so
+/// that the judge doesn't blame the program for them.
+pub fn wrapper_main(args: &[String]) -> i32 {
+    let mut report = take_report_fd();
+    match supervise(args, &mut report) {
+        Ok(code) => code,
+        Err(e) => {
+            if let Some(report) = &mut report {
+                let _ = writeln!(report, "error {e}");
+            }
+            1
+        }
+    }
+}
+
+/// Takes the report pipe passed by the judge, out of reach of the program
+///
+/// The descriptor is closed on exec, and the wrapper made non-dumpable, which keeps
+/// the program from opening it through `/proc/<wrapper>/fd`.
+fn take_report_fd() -> Option<fs::File> {
+    // SAFETY: only flags of the passed descriptor and of this process are changed
+    unsafe {
+        if libc::fcntl(PASSED_FD, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+            return None;
+        }
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+        Some(fs::File::from_raw_fd(PASSED_FD))
+    }
+}
+
+fn supervise(args: &[String], report: &mut Option<fs::File>) -> anyhow::Result<i32> {
+    let [policy, program, ..] = args else {
+        bail!("Usage: {WRAPPER_NAME} <policy> <program> [args...]");
+    };
+    let policy = SeccompPolicy::from_str(policy, false)
+        .map_err(|_| anyhow!("Unknown seccomp policy {policy}"))?;
+    if !cfg!(target_arch = "x86_64") {
+        bail!("Seccomp policies are only supported on x86_64");
+    }
+    let filter = build_filter(policy)?;
+
+    // Everything the child needs is prepared up front, it must not allocate after fork
+    let program = CString::new(program.as_str())?;
+    let argv = args[1..]
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut argv_ptrs: Vec<*const libc::c_char> = argv.iter().map(|arg| arg.as_ptr()).collect();
+    argv_ptrs.push(std::ptr::null());
+
+    let mut sockets = [0; 2];
+    // SAFETY: `sockets` has room for the two descriptors
+    let created = unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            sockets.as_mut_ptr(),
+        )
+    };
+    if created != 0 {
+        bail!(
+            "Failed to create socket pair: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else
+    let (socket, child_socket) = unsafe {
+        (
+            OwnedFd::from_raw_fd(sockets[0]),
+            OwnedFd::from_raw_fd(sockets[1]),
+        )
+    };
+
+    // SAFETY: the wrapper is single-threaded, and the child only makes raw syscalls
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        bail!("Failed to fork: {}", std::io::Error::last_os_error());
+    }
+    if pid == 0 {
+        // SAFETY: all pointers come from values that outlive the call
+        unsafe { exec_filtered(&filter, child_socket.as_raw_fd(), &program, &argv_ptrs) }
+    }
+    drop(child_socket);
+
+    let Some(listener) = receive_fd(socket.as_raw_fd()) else {
+        wait_child(pid)?;
+        bail!("Failed to install the seccomp filter");
+    };
+
+    loop {
+        let mut poll_fd = libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll_fd` is a single valid entry
+        if unsafe { libc::poll(&mut poll_fd, 1, -1) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            bail!("Failed to poll the seccomp listener: {error}");
+        }
+        if poll_fd.revents & libc::POLLIN == 0 {
+            // Every process under the filter has exited
+            break;
+        }
+
+        // SAFETY: `seccomp_notif` is plain old data, which the kernel requires zeroed
+        let mut notif: libc::seccomp_notif = unsafe { std::mem::zeroed() };
+        // SAFETY: `notif` has the size encoded in the request
+        let received =
+            unsafe { libc::ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_RECV, &mut notif) };
+        if received != 0 {
+            // The notifying process died before the notification was received
+            continue;
+        }
+
+        if let Some(report) = report {
+            writeln!(report, "syscall {}", syscall_name(notif.data.nr.into()))?;
+        }
+        // SAFETY: plain signal delivery, the offender may be a descendant of the child
+        unsafe {
+            libc::kill(notif.pid as libc::pid_t, libc::SIGKILL);
+            libc::kill(pid, libc::SIGKILL);
+        }
+        wait_child(pid)?;
+        return Ok(VIOLATION_EXIT_CODE);
+    }
+
+    let status = wait_child(pid)?;
+    if libc::WIFSIGNALED(status) {
+        // Die from the same signal, so that the sandbox reports it like without the wrapper
+        let signal = libc::WTERMSIG(status);
+        // SAFETY: restoring the default disposition and raising a signal on this process
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+        return Ok(128 + signal);
+    }
+    Ok(libc::WEXITSTATUS(status))
+}
+
+/// Installs the filter in the forked child, hands over its listener and executes the program
+///
+/// # Safety
+///
+/// Must only be called in a freshly forked child of a single-threaded process, with
+/// `argv` a null-terminated array of valid C strings.
+unsafe fn exec_filtered(
+    filter: &[libc::sock_filter],
+    socket: RawFd,
+    program: &CString,
+    argv: &[*const libc::c_char],
+) -> ! {
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+    // SAFETY: only raw syscalls without allocation follow, as required after fork
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            libc::_exit(127);
+        }
+        let listener = libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            &prog,
+        );
+        if listener < 0 || !send_fd(socket, listener as RawFd) {
+            libc::_exit(127);
+        }
+        libc::execvp(program.as_ptr(), argv.as_ptr());
+        libc::_exit(127)
+    }
+}
+
+/// Sends a file descriptor over a Unix socket without allocating
+fn send_fd(socket: RawFd, fd: RawFd) -> bool {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&raw mut byte).cast(),
+        iov_len: 1,
+    };
+    // Aligned buffer with room for one control message carrying a descriptor
+    let mut control = [0u64; 4];
+    // SAFETY: `msghdr` is plain old data, for which all zeros is valid
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    // SAFETY: computing the size of a control message has no preconditions
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<RawFd>() as u32) } as usize;
+
+    // SAFETY: the control buffer is large enough for the single message written
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as u32) as usize;
+        libc::CMSG_DATA(cmsg).cast::<RawFd>().write_unaligned(fd);
+        libc::sendmsg(socket, &msg, 0) == 1
+    }
+}
+
+/// Receives a file descriptor sent with [`send_fd`], or `None` if the peer sent none
+fn receive_fd(socket: RawFd) -> Option<OwnedFd> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&raw mut byte).cast(),
+        iov_len: 1,
+    };
+    let mut control = [0u64; 4];
+    // SAFETY: `msghdr` is plain old data, for which all zeros is valid
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = size_of_val(&control);
+
+    // SAFETY: the buffers referenced by `msg` outlive the call
+    unsafe {
+        if libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) <= 0 {
+            return None;
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return None;
+        }
+        let fd = libc::CMSG_DATA(cmsg).cast::<RawFd>().read_unaligned();
+        Some(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Waits for the child to exit and returns its raw wait status
+fn wait_child(pid: libc::pid_t) -> anyhow::Result<libc::c_int> {
+    let mut status = 0;
+    loop {
+        // SAFETY: `pid` is a child of this process that has not been reaped yet
+        if unsafe { libc::waitpid(pid, &mut status, 0) } == pid {
+            return Ok(status);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            bail!("Failed to wait for process {pid}: {error}");
+        }
+    }
+}
+
+/// Builds the BPF program of a policy
+///
+/// Syscalls of other architectures kill the program outright, allowed syscalls
+/// pass, and everything else is sent to the wrapper as a user notification.
+fn build_filter(policy: SeccompPolicy) -> anyhow::Result<Vec<libc::sock_filter>> {
+    let extra: &[&str] = match policy {
+        SeccompPolicy::Compiled => &[],
+        SeccompPolicy::Interpreter => INTERPRETER_SYSCALLS,
+    };
+    let mut allowed = COMPILED_SYSCALLS
+        .iter()
+        .chain(extra)
+        .map(|name| syscall_number(name).ok_or_else(|| anyhow!("Unknown syscall {name}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    allowed.sort_unstable();
+    allowed.dedup();
+
+    let statement = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let mut filter = vec![
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4), // seccomp_data.arch
+        libc::sock_filter {
+            jt: 1,
+            ..statement(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH_X86_64,
+            )
+        },
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0), // seccomp_data.nr
+    ];
+    for (idx, &nr) in allowed.iter().enumerate() {
+        // Jump over the remaining checks and the notification to the final allow
+        let jt = u8::try_from(allowed.len() - idx)
+            .map_err(|_| anyhow!("Too many syscalls in seccomp policy"))?;
+        filter.push(libc::sock_filter {
+            jt,
+            ..statement(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr as u32)
+        });
+    }
+    filter.push(statement(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_USER_NOTIF,
+    ));
+    filter.push(statement(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ALLOW,
+    ));
+    Ok(filter)
+}
+
+/// Names of the syscalls in [`SYSCALLS`], without the `SYS_` prefix
+fn syscalls() -> impl Iterator<Item = (libc::c_long, &'static str)> {
+    SYSCALLS
+        .iter()
+        .map(|&(nr, constant)| (nr, constant.trim_start_matches("SYS_")))
+}
+
+fn syscall_number(name: &str) -> Option<libc::c_long> {
+    syscalls()
+        .find(|&(_, syscall)| syscall == name)
+        .map(|(nr, _)| nr)
+}
+
+/// Returns the name of a syscall, falling back to its number for unknown ones
+fn syscall_name(nr: libc::c_long) -> String {
+    syscalls()
+        .find(|&(syscall_nr, _)| syscall_nr == nr)
+        .map_or_else(|| format!("syscall {nr}"), |(_, name)| name.to_string())
+}
+
+/// Builds a table of syscall numbers and the names of their `libc` constants
+macro_rules! syscall_table {
+    ($($constant:ident),* $(,)?) => {
+        &[$((libc::$constant, stringify!($constant))),*]
+    };
+}
+
+/// Syscalls of x86_64 known to `libc`, used to resolve names in both directions
+#[cfg(target_arch = "x86_64")]
+#[allow(deprecated)] // Syscalls removed from the kernel still have names
+#[rustfmt::skip]
+const SYSCALLS: &[(libc::c_long, &str)] = syscall_table! {
+    SYS_read, SYS_write, SYS_open, SYS_close, SYS_stat, SYS_fstat, SYS_lstat, SYS_poll,
+    SYS_lseek, SYS_mmap, SYS_mprotect, SYS_munmap, SYS_brk, SYS_rt_sigaction,
+    SYS_rt_sigprocmask, SYS_rt_sigreturn, SYS_ioctl, SYS_pread64, SYS_pwrite64,
+    SYS_readv, SYS_writev, SYS_access, SYS_pipe, SYS_select, SYS_sched_yield,
+    SYS_mremap, SYS_msync, SYS_mincore, SYS_madvise, SYS_shmget, SYS_shmat, SYS_shmctl,
+    SYS_dup, SYS_dup2, SYS_pause, SYS_nanosleep, SYS_getitimer, SYS_alarm,
+    SYS_setitimer, SYS_getpid, SYS_sendfile, SYS_socket, SYS_connect, SYS_accept,
+    SYS_sendto, SYS_recvfrom, SYS_sendmsg, SYS_recvmsg, SYS_shutdown, SYS_bind,
+    SYS_listen, SYS_getsockname, SYS_getpeername, SYS_socketpair, SYS_setsockopt,
+    SYS_getsockopt, SYS_clone, SYS_fork, SYS_vfork, SYS_execve, SYS_exit, SYS_wait4,
+    SYS_kill, SYS_uname, SYS_semget, SYS_semop, SYS_semctl, SYS_shmdt, SYS_msgget,
+    SYS_msgsnd, SYS_msgrcv, SYS_msgctl, SYS_fcntl, SYS_flock, SYS_fsync, SYS_fdatasync,
+    SYS_truncate, SYS_ftruncate, SYS_getdents, SYS_getcwd, SYS_chdir, SYS_fchdir,
+    SYS_rename, SYS_mkdir, SYS_rmdir, SYS_creat, SYS_link, SYS_unlink, SYS_symlink,
+    SYS_readlink, SYS_chmod, SYS_fchmod, SYS_chown, SYS_fchown, SYS_lchown, SYS_umask,
+    SYS_gettimeofday, SYS_getrlimit, SYS_getrusage, SYS_sysinfo, SYS_times, SYS_ptrace,
+    SYS_getuid, SYS_syslog, SYS_getgid, SYS_setuid, SYS_setgid, SYS_geteuid,
+    SYS_getegid, SYS_setpgid, SYS_getppid, SYS_getpgrp, SYS_setsid, SYS_setreuid,
+    SYS_setregid, SYS_getgroups, SYS_setgroups, SYS_setresuid, SYS_getresuid,
+    SYS_setresgid, SYS_getresgid, SYS_getpgid, SYS_setfsuid, SYS_setfsgid, SYS_getsid,
+    SYS_capget, SYS_capset, SYS_rt_sigpending, SYS_rt_sigtimedwait, SYS_rt_sigqueueinfo,
+    SYS_rt_sigsuspend, SYS_sigaltstack, SYS_utime, SYS_mknod, SYS_uselib,
+    SYS_personality, SYS_ustat, SYS_statfs, SYS_fstatfs, SYS_sysfs, SYS_getpriority,
+    SYS_setpriority, SYS_sched_setparam, SYS_sched_getparam, SYS_sched_setscheduler,
+    SYS_sched_getscheduler, SYS_sched_get_priority_max, SYS_sched_get_priority_min,
+    SYS_sched_rr_get_interval, SYS_mlock, SYS_munlock, SYS_mlockall, SYS_munlockall,
+    SYS_vhangup, SYS_modify_ldt, SYS_pivot_root, SYS__sysctl, SYS_prctl, SYS_arch_prctl,
+    SYS_adjtimex, SYS_setrlimit, SYS_chroot, SYS_sync, SYS_acct, SYS_settimeofday,
+    SYS_mount, SYS_umount2, SYS_swapon, SYS_swapoff, SYS_reboot, SYS_sethostname,
+    SYS_setdomainname, SYS_iopl, SYS_ioperm, SYS_create_module, SYS_init_module,
+    SYS_delete_module, SYS_get_kernel_syms, SYS_query_module, SYS_quotactl,
+    SYS_nfsservctl, SYS_getpmsg, SYS_putpmsg, SYS_afs_syscall, SYS_tuxcall,
+    SYS_security, SYS_gettid, SYS_readahead, SYS_setxattr, SYS_lsetxattr, SYS_fsetxattr,
+    SYS_getxattr, SYS_lgetxattr, SYS_fgetxattr, SYS_listxattr, SYS_llistxattr,
+    SYS_flistxattr, SYS_removexattr, SYS_lremovexattr, SYS_fremovexattr, SYS_tkill,
+    SYS_time, SYS_futex, SYS_sched_setaffinity, SYS_sched_getaffinity,
+    SYS_set_thread_area, SYS_io_setup, SYS_io_destroy, SYS_io_getevents, SYS_io_submit,
+    SYS_io_cancel, SYS_get_thread_area, SYS_lookup_dcookie, SYS_epoll_create,
+    SYS_epoll_ctl_old, SYS_epoll_wait_old, SYS_remap_file_pages, SYS_getdents64,
+    SYS_set_tid_address, SYS_restart_syscall, SYS_semtimedop, SYS_fadvise64,
+    SYS_timer_create, SYS_timer_settime, SYS_timer_gettime, SYS_timer_getoverrun,
+    SYS_timer_delete, SYS_clock_settime, SYS_clock_gettime, SYS_clock_getres,
+    SYS_clock_nanosleep, SYS_exit_group, SYS_epoll_wait, SYS_epoll_ctl, SYS_tgkill,
+    SYS_utimes, SYS_vserver, SYS_mbind, SYS_set_mempolicy, SYS_get_mempolicy,
+    SYS_mq_open, SYS_mq_unlink, SYS_mq_timedsend, SYS_mq_timedreceive, SYS_mq_notify,
+    SYS_mq_getsetattr, SYS_kexec_load, SYS_waitid, SYS_add_key, SYS_request_key,
+    SYS_keyctl, SYS_ioprio_set, SYS_ioprio_get, SYS_inotify_init, SYS_inotify_add_watch,
+    SYS_inotify_rm_watch, SYS_migrate_pages, SYS_openat, SYS_mkdirat, SYS_mknodat,
+    SYS_fchownat, SYS_futimesat, SYS_newfstatat, SYS_unlinkat, SYS_renameat, SYS_linkat,
+    SYS_symlinkat, SYS_readlinkat, SYS_fchmodat, SYS_faccessat, SYS_pselect6, SYS_ppoll,
+    SYS_unshare, SYS_set_robust_list, SYS_get_robust_list, SYS_splice, SYS_tee,
+    SYS_sync_file_range, SYS_vmsplice, SYS_move_pages, SYS_utimensat, SYS_epoll_pwait,
+    SYS_signalfd, SYS_timerfd_create, SYS_eventfd, SYS_fallocate, SYS_timerfd_settime,
+    SYS_timerfd_gettime, SYS_accept4, SYS_signalfd4, SYS_eventfd2, SYS_epoll_create1,
+    SYS_dup3, SYS_pipe2, SYS_inotify_init1, SYS_preadv, SYS_pwritev,
+    SYS_rt_tgsigqueueinfo, SYS_perf_event_open, SYS_recvmmsg, SYS_fanotify_init,
+    SYS_fanotify_mark, SYS_prlimit64, SYS_name_to_handle_at, SYS_open_by_handle_at,
+    SYS_clock_adjtime, SYS_syncfs, SYS_sendmmsg, SYS_setns, SYS_getcpu,
+    SYS_process_vm_readv, SYS_process_vm_writev, SYS_kcmp, SYS_finit_module,
+    SYS_sched_setattr, SYS_sched_getattr, SYS_renameat2, SYS_seccomp, SYS_getrandom,
+    SYS_memfd_create, SYS_kexec_file_load, SYS_bpf, SYS_execveat, SYS_userfaultfd,
+    SYS_membarrier, SYS_mlock2, SYS_copy_file_range, SYS_preadv2, SYS_pwritev2,
+    SYS_pkey_mprotect, SYS_pkey_alloc, SYS_pkey_free, SYS_statx, SYS_rseq,
+    SYS_pidfd_send_signal, SYS_io_uring_setup, SYS_io_uring_enter,
+    SYS_io_uring_register, SYS_open_tree, SYS_move_mount, SYS_fsopen, SYS_fsconfig,
+    SYS_fsmount, SYS_fspick, SYS_pidfd_open, SYS_clone3, SYS_close_range, SYS_openat2,
+    SYS_pidfd_getfd, SYS_faccessat2, SYS_process_madvise, SYS_epoll_pwait2,
+    SYS_mount_setattr, SYS_quotactl_fd, SYS_landlock_create_ruleset,
+    SYS_landlock_add_rule, SYS_landlock_restrict_self, SYS_memfd_secret,
+    SYS_process_mrelease, SYS_futex_waitv, SYS_set_mempolicy_home_node, SYS_fchmodat2,
+    SYS_mseal,
+};
+
+#[cfg(not(target_arch = "x86_64"))]
+const SYSCALLS: &[(libc::c_long, &str)] = &[];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seccomp_filter() {
+        for policy in [SeccompPolicy::Compiled, SeccompPolicy::Interpreter] {
+            let filter = build_filter(policy).unwrap();
+            // The first syscall check jumps exactly to the final allow
+            assert_eq!(4 + filter[4].jt as usize + 1, filter.len() - 1);
+        }
+        assert_eq!(syscall_name(libc::SYS_ptrace), "ptrace");
+        assert_eq!(syscall_number("execve"), Some(libc::SYS_execve));
+
+        assert_eq!(
+            SeccompReport::parse("syscall socket\n"),
+            SeccompReport::Violation("socket".to_string())
+        );
+        assert_eq!(
+            SeccompReport::parse("error Failed to fork\n"),
+            SeccompReport::Failure("Failed to fork".to_string())
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt::Write as _;
+
+use super::*;
+use crate::config::{KiloByte, LanguageConfig};
+use crate::routes::JobSubmission;
+
+/// Limits of the test cases run by the self-test
+const SELF_TEST_TIME_LIMIT: MicroSecond = MicroSecond(2_000_000);
+const SELF_TEST_MEMORY_LIMIT: KiloByte = KiloByte(262144);
+const LIMIT_CHECK_TIME_LIMIT: MicroSecond = MicroSecond(500_000);
+const LIMIT_CHECK_MEMORY_LIMIT: KiloByte = KiloByte(65536);
+
+/// Tiny programs for the languages the self-test knows, by source file extension
+///
+/// Each language has a program printing `OK`, one spinning forever and one filling
+/// 256 MiB of memory.
+const SAMPLE_PROGRAMS: &[(&[&str], [&str; 3])] = &[
+    (
+        &["c"],
+        [
+            "#include <stdio.h>\nint main(void) { puts(\"OK\"); return 0; }",
+            "int main(void) { volatile unsigned long i = 0; for (;;) i++; }",
+            "#include <stdlib.h>\nint main(void) { volatile char *p = malloc(256u << 20); for (size_t i = 0; i < 256u << 20; i += 4096) p[i] = 1; return p[0] == 2; }",
+        ],
+    ),
+    (
+        &["cpp", "cc", "cxx"],
+        [
+            "#include <cstdio>\nint main() { std::puts(\"OK\"); }",
+            "int main() { volatile unsigned long i = 0; for (;;) i++; }",
+            "#include <cstdlib>\nint main() { volatile char *p = (char *)std::malloc(256u << 20); for (unsigned i = 0; i < 256u << 20; i += 4096) p[i] = 1; return p[0] == 2; }",
+        ],
+    ),
+    (
+        &["rs"],
+        [
+            "fn main() { println!(\"OK\"); }",
+            "fn main() { let mut i = 0u64; loop { i = std::hint::black_box(i + 1); } }",
+            "fn main() { let v = std::hint::black_box(vec![1u8; 256 << 20]); std::process::exit((v[v.len() - 1] == 2) as i32); }",
+        ],
+    ),
+    (
+        &["py"],
+        [
+            "print(\"OK\")",
+            "while True:\n    pass",
+            "data = b\"x\" * (256 << 20)\nprint(len(data))",
+        ],
+    ),
+];
+
+/// Outcome of one self-test check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestStatus {
+    Pass,
+    Fail,
+    /// The check could not run, e.g. for a language without a sample program
+    Skip,
+}
+
+/// A check of the judging environment and its outcome
+#[derive(Debug)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub status: SelfTestStatus,
+    pub detail: String,
+}
+
+impl SelfTestCheck {
+    fn new(name: String, status: SelfTestStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Checks the judging environment in sandbox `sandbox_id`, which no worker may use yet
+///
+/// Compiles and runs a tiny program for every language whose source files the
+/// self-test has a sample for. With the first language that works, it also checks
+/// that exceeding the time and memory limits is judged as such. Compilation always
+/// runs for real, bypassing the compile cache.
+pub fn self_test(
+    backend: SandboxBackendKind,
+    sandbox_id: u8,
+    cpus: Vec<usize>,
+    languages: &LanguageConfig,
+) -> anyhow::Result<Vec<SelfTestCheck>> {
+    let work_dir = std::env::temp_dir().join(format!("oj-self-test-{}", std::process::id()));
+    fs::create_dir_all(&work_dir)?;
+    // Not the shared store, whose pruning follows the configured retention
+    let sandbox = Sandbox::build(
+        sandbox_id,
+        backend,
+        cpus,
+        Arc::new(CompileCache::build(0)?),
+        Arc::new(ArtifactStore::scratch(work_dir.join("artifacts"))?),
+    )?;
+    let input_file = work_dir.join("input.txt");
+    fs::write(&input_file, "")?;
+    let case_config = |time_limit, memory_limit| OneCaseConfig {
+        score: 100.0,
+        input_file: input_file.to_string_lossy().to_string(),
+        answer_file: String::new(),
+        time_limit,
+        memory_limit,
+    };
+    let run_case = case_config(SELF_TEST_TIME_LIMIT, SELF_TEST_MEMORY_LIMIT);
+    let limit_case = case_config(LIMIT_CHECK_TIME_LIMIT, LIMIT_CHECK_MEMORY_LIMIT);
+
+    let mut checks = Vec::new();
+    let mut limit_language = None;
+    for language in languages {
+        let name = format!("Run {}", language.name);
+        let Some(programs) = sample_programs(language) else {
+            checks.push(SelfTestCheck::new(
+                name,
+                SelfTestStatus::Skip,
+                "No sample program for this file type",
+            ));
+            continue;
+        };
+
+        let check = match sandbox.self_test_program(language, programs[0], &run_case, &work_dir) {
+            Ok(result) if result.error.is_none() && result.stdout_content.trim() == "OK" => {
+                limit_language.get_or_insert((language, programs));
+                SelfTestCheck::new(name, SelfTestStatus::Pass, "")
+            }
+            Ok(result) => SelfTestCheck::new(name, SelfTestStatus::Fail, describe(&result)),
+            Err(e) => SelfTestCheck::new(name, SelfTestStatus::Fail, e.to_string()),
+        };
+        checks.push(check);
+    }
+
+    let limit_checks = [
+        ("Time limit", 1, "Time Limit Exceeded"),
+        ("Memory limit", 2, "Memory Limit Exceeded"),
+    ];
+    for (limit, program, expected) in limit_checks {
+        let Some((language, programs)) = limit_language else {
+            checks.push(SelfTestCheck::new(
+                limit.to_string(),
+                SelfTestStatus::Skip,
+                "No language passed the run check",
+            ));
+            continue;
+        };
+
+        let name = format!("{limit} ({})", language.name);
+        let check =
+            match sandbox.self_test_program(language, programs[program], &limit_case, &work_dir) {
+                Ok(result) if result.error == Some(expected) => {
+                    SelfTestCheck::new(name, SelfTestStatus::Pass, "")
+                }
+                Ok(result) => SelfTestCheck::new(
+                    name,
+                    SelfTestStatus::Fail,
+                    format!("Expected {expected}, got {}", describe(&result)),
+                ),
+                Err(e) => SelfTestCheck::new(name, SelfTestStatus::Fail, e.to_string()),
+            };
+        checks.push(check);
+    }
+
+    let _ = fs::remove_dir_all(&work_dir);
+    Ok(checks)
+}
+
+/// Formats self-test checks as a table, one check per line
+pub fn format_self_test_table(checks: &[SelfTestCheck]) -> String {
+    let width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .chain(["CHECK".len()])
+        .max()
+        .unwrap_or_default();
+
+    let mut table = format!("{:<width$}  RESULT  DETAIL\n", "CHECK");
+    for check in checks {
+        let status = match check.status {
+            SelfTestStatus::Pass => "PASS",
+            SelfTestStatus::Fail => "FAIL",
+            SelfTestStatus::Skip => "SKIP",
+        };
+        let detail = check.detail.lines().next().unwrap_or_default();
+        let line = format!("{:<width$}  {status:<6}  {detail}", check.name);
+        let _ = writeln!(table, "{}", line.trim_end());
+    }
+    table
+}
+
+impl Sandbox {
+    /// Compiles a program and runs it on a single case
+    fn self_test_program(
+        &self,
+        language: &OneLanguageConfig,
+        source_code: &str,
+        case_config: &OneCaseConfig,
+        work_dir: &Path,
+    ) -> anyhow::Result<TestCaseResult> {
+        self.reinit()?;
+        let mut job = JobRecord {
+            id: 0,
+            created_time: String::new(),
+            updated_time: String::new(),
+            submission: JobSubmission {
+                user_id: 0,
+                contest_id: 0,
+                problem_id: 0,
+                source_code: source_code.to_string(),
+                language: language.name.clone(),
+            },
+            state: String::new(),
+            result: String::new(),
+            score: 0.0,
+            cases: (0..2)
+                .map(|id| CaseResult {
+                    id,
+                    result: String::new(),
+                    time: 0,
+                    memory: 0,
+                    score: 0.0,
+                    info: String::new(),
+                })
+                .collect(),
+        };
+        if !self.compile_source_code(&mut job, language, work_dir)? {
+            bail!("Compilation Error: {}", job.cases[0].info.trim());
+        }
+
+        self.run_single_test_case(1, case_config, language.seccomp, work_dir)
+    }
+}
+
+/// Returns the sample programs for a language, based on its source file extension
+fn sample_programs(language: &OneLanguageConfig) -> Option<[&'static str; 3]> {
+    let extension = Path::new(&language.file_name).extension()?.to_str()?;
+    SAMPLE_PROGRAMS
+        .iter()
+        .find(|(extensions, _)| extensions.contains(&extension))
+        .map(|&(_, programs)| programs)
+}
+
+/// Describes the result of a case that didn't go as expected
+fn describe(result: &TestCaseResult) -> String {
+    match (result.error, result.info.is_empty()) {
+        (Some(error), true) => error.to_string(),
+        (Some(error), false) => format!("{error}: {}", result.info),
+        (None, _) => format!("Accepted with output {:?}", result.stdout_content.trim()),
+    }
+}
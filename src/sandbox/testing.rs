@@ -1,3 +1,7 @@
+use std::io::PipeReader;
+
+use super::checker::run_special_judge;
+use super::seccomp::{apply_seccomp_report, program_command};
 use super::*;
 
 impl Sandbox {
@@ -6,7 +10,9 @@ impl Sandbox {
         &self,
         job: &mut JobRecord,
         problem: &OneProblemConfig,
-        cache_dir: PathBuf,
+        seccomp: Option<SeccompPolicy>,
+        cache_dir: &Path,
+        on_case: &mut dyn FnMut(&CaseResult),
     ) -> anyhow::Result<()> {
         let mut total_score = 0.0;
         let mut first_error: Option<&str> = None;
@@ -15,7 +21,14 @@ impl Sandbox {
             let case_idx = idx + 1; // Add 1 because case 0 is compilation
             job.cases[case_idx].result = "Running".to_string();
 
-            let test_result = self.run_single_test_case(case_idx, case_config, &cache_dir)?;
+            let (test_result, interactor_check) = if problem.judge_type == JudgeType::Interactive {
+                self.run_interactive_test_case(case_idx, case_config, problem, seccomp, cache_dir)?
+            } else {
+                (
+                    self.run_single_test_case(case_idx, case_config, seccomp, cache_dir)?,
+                    None,
+                )
+            };
 
             job.cases[case_idx].time = test_result.time;
             job.cases[case_idx].memory = test_result.memory;
@@ -25,21 +38,34 @@ impl Sandbox {
                 job.cases[case_idx].info = test_result.info;
                 first_error = first_error.or(Some(error));
             } else {
-                // Check program output
-                let is_correct = self.check_output_correctness(
-                    &test_result.stdout_content,
-                    case_config,
-                    problem,
-                )?;
-
-                if is_correct {
-                    job.cases[case_idx].result = "Accepted".to_string();
-                    total_score += case_config.score;
-                } else {
-                    job.cases[case_idx].result = "Wrong Answer".to_string();
-                    first_error = first_error.or(Some("Wrong Answer"));
+                // Check program output, unless the interactor has already judged it
+                let check = match interactor_check {
+                    Some(check) => check,
+                    None => self.check_output_correctness(
+                        &test_result.stdout_content,
+                        &cache_dir.join(format!("{case_idx}.out")),
+                        case_config,
+                        problem,
+                    )?,
+                };
+
+                let result = match check.error {
+                    Some(error) => error,
+                    None if check.fraction >= 1.0 => "Accepted",
+                    None if check.fraction > 0.0 => "Partially Correct",
+                    None => "Wrong Answer",
+                };
+                let case_score = case_config.score * check.fraction;
+
+                job.cases[case_idx].result = result.to_string();
+                job.cases[case_idx].score = case_score;
+                job.cases[case_idx].info = check.info;
+                total_score += case_score;
+                if result != "Accepted" {
+                    first_error = first_error.or(Some(result));
                 }
             }
+            on_case(&job.cases[case_idx]);
         }
 
         job.score = total_score;
@@ -50,20 +76,18 @@ impl Sandbox {
     }
 
     /// Runs a single test case and returns the result
-    fn run_single_test_case(
+    pub(super) fn run_single_test_case(
         &self,
         case_idx: usize,
         case_config: &OneCaseConfig,
+        seccomp: Option<SeccompPolicy>,
         cache_dir: &Path,
     ) -> anyhow::Result<TestCaseResult> {
-        let paths = self.setup_test_case_paths(case_idx, cache_dir)?;
-
-        // Prepare input file
-        fs::copy(&case_config.input_file, &paths.stdin)?;
+        let paths = self.setup_test_case_paths(case_idx, case_config, cache_dir);
 
         // Execute the program
         let start_time = Instant::now();
-        self.execute_test_case(case_config, &paths)?;
+        let seccomp_report = self.execute_test_case(case_config, seccomp, &paths)?;
         let elapsed_time = start_time.elapsed();
 
         // Set result template
@@ -88,6 +112,7 @@ impl Sandbox {
             result.time = elapsed_time.as_micros() as u32;
             result.error = Some("Time Limit Exceeded");
         }
+        apply_seccomp_report(seccomp_report, &mut result);
 
         // Read program output if no error occurred
         if result.error.is_none() {
@@ -101,81 +126,55 @@ impl Sandbox {
                 .unwrap_or_default();
         }
 
-        // Move the stdout file to the cache directory  TODO: optional
-        fs::rename(
-            &paths.stdout,
-            cache_dir.join(paths.stdout.file_name().unwrap()),
-        )?;
-
         Ok(result)
     }
 
     /// Sets up file paths for a test case
+    ///
+    /// The program reads the case input file directly, and its output, stderr and meta
+    /// file are kept with the job artifacts in `cache_dir`.
     fn setup_test_case_paths(
         &self,
         case_idx: usize,
+        case_config: &OneCaseConfig,
         cache_dir: &Path,
-    ) -> anyhow::Result<TestCasePaths> {
-        let stdin_name = format!("{case_idx}.in");
-        let stdout_name = format!("{case_idx}.out");
-        let meta_name = format!("{case_idx}.meta");
-
-        Ok(TestCasePaths {
-            stdin: self.box_dir.join(stdin_name),
-            stdout: self.box_dir.join(stdout_name),
-            meta: cache_dir.join(meta_name),
-        })
+    ) -> TestCasePaths {
+        TestCasePaths {
+            stdin: PathBuf::from(&case_config.input_file),
+            stdout: cache_dir.join(format!("{case_idx}.out")),
+            stderr: cache_dir.join(format!("{case_idx}.err")),
+            meta: cache_dir.join(format!("{case_idx}.meta")),
+        }
     }
 
-    /// Executes a test case in the sandbox
+    /// Executes a test case in the sandbox, recording stderr apart from the judged stdout
+    ///
+    /// With a seccomp policy, the program runs under the syscall filter, and the
+    /// wrapper's report is returned.
     fn execute_test_case(
         &self,
         case_config: &OneCaseConfig,
+        seccomp: Option<SeccompPolicy>,
         paths: &TestCasePaths,
-    ) -> anyhow::Result<()> {
-        let wall_time_limit = Second::from(case_config.time_limit);
-        let memory_limit = case_config.memory_limit;
-
-        let sandbox_id = self.id.to_string();
-        let wall_time_arg = format!("{:.4}", wall_time_limit.0 + 0.5);
-        let memory_arg = format!("--cg-mem={}", memory_limit.0);
-        let stack_arg = format!("--stack={}", memory_limit.0 / 2);
-        let processes_arg = format!("--processes={RUNTIME_PROCESSES}");
-        let open_files_arg = format!("--open-files={RUNTIME_OPEN_FILES}");
-        let fsize_arg = format!("--fsize={RUNTIME_FILE_SIZE}");
-        let meta_path = paths.meta.to_string_lossy();
-        let stdin_name = paths.stdin.file_name().unwrap().to_string_lossy();
-        let stdout_name = paths.stdout.file_name().unwrap().to_string_lossy();
-
-        let _ = Command::new("isolate")
-            .args([
-                "-b",
-                &sandbox_id,
-                "--cg",
-                "--run",
-                "-w",
-                &wall_time_arg,
-                &memory_arg,
-                &stack_arg,
-                &processes_arg,
-                &open_files_arg,
-                &fsize_arg,
-                "-E",
-                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
-                "-M",
-                &meta_path,
-                "-i",
-                &stdin_name,
-                "-o",
-                &stdout_name,
-                "--stderr-to-stdout",
-                "--silent",
-                "--",
-                "./main",
-            ])
-            .output()?;
+    ) -> anyhow::Result<Option<PipeReader>> {
+        let output = fs::File::create(&paths.stdout)?;
+        let error_output = fs::File::create(&paths.stderr)?;
+        let program = program_command(seccomp)?;
+        let command: Vec<&str> = program.command.iter().map(String::as_str).collect();
 
-        Ok(())
+        self.backend.run(RunSpec {
+            command: &command,
+            env: RUNTIME_ENV,
+            limits: runtime_limits(case_config),
+            toolchains: false,
+            extra_dirs: &program.extra_dirs,
+            meta_path: &paths.meta,
+            stdin: fs::File::open(&paths.stdin)?.into(),
+            stdout: output.into(),
+            stderr: error_output.into(),
+            pass_fd: program.report_writer,
+        })?;
+        Ok(program.report_reader)
     }
 
     /// Processes the meta file content and updates the test result
@@ -191,10 +190,15 @@ impl Sandbox {
                         // cg-oom-killed:1
                         result.error = Some("Memory Limit Exceeded");
                     }
-                    "exitcode" => {
-                        if value != "0" && result.error.is_none() {
-                            result.error = Some("Runtime Error");
-                        }
+                    "exitcode" if value != "0" && result.error.is_none() => {
+                        result.error = Some("Runtime Error");
+                    }
+                    "exitsig" if result.error.is_none() => {
+                        // exitsig:11, a program killed by a signal has no exitcode
+                        result.error = Some("Runtime Error");
+                    }
+                    "status" if matches!(value, "RE" | "SG") && result.error.is_none() => {
+                        result.error = Some("Runtime Error");
                     }
                     "cg-mem" => {
                         if let Ok(memory) = value.parse::<u32>() {
@@ -216,27 +220,80 @@ impl Sandbox {
     }
 
     /// Checks if the program output matches the expected output
+    ///
+    /// `output_file` holds the same program output, for checkers that read it from disk.
     fn check_output_correctness(
         &self,
         program_output: &str,
+        output_file: &Path,
         case_config: &OneCaseConfig,
         problem: &OneProblemConfig,
-    ) -> anyhow::Result<bool> {
+    ) -> anyhow::Result<OutputCheck> {
+        if problem.judge_type == JudgeType::Spj {
+            return run_special_judge(output_file, case_config, problem);
+        }
+
         let expected_output = fs::read_to_string(&case_config.answer_file).map_err(|e| {
             log::error!("Failed to read answer file: {e}");
             anyhow!("Failed to read answer file: {}", e)
         })?;
 
-        let is_correct = match problem.judge_type {
-            JudgeType::Standard => compare_output_standard(program_output, &expected_output),
-            JudgeType::Strict => compare_output_strict(program_output, &expected_output),
+        let check = match problem.judge_type {
+            JudgeType::Standard => {
+                if compare_output_standard(program_output, &expected_output) {
+                    true.into()
+                } else {
+                    let program = normalize_output_standard(program_output);
+                    let expected = normalize_output_standard(&expected_output);
+                    OutputCheck::wrong(describe_line_mismatch(
+                        program.split('\n'),
+                        expected.split('\n'),
+                        problem.misc.wrong_answer_info,
+                    ))
+                }
+            }
+            JudgeType::Strict => {
+                if compare_output_strict(program_output, &expected_output) {
+                    true.into()
+                } else {
+                    OutputCheck::wrong(describe_line_mismatch(
+                        program_output.split_inclusive('\n'),
+                        expected_output.split_inclusive('\n'),
+                        problem.misc.wrong_answer_info,
+                    ))
+                }
+            }
+            JudgeType::Float => compare_output_float(
+                program_output,
+                &expected_output,
+                problem.misc.epsilon.unwrap_or(DEFAULT_FLOAT_EPSILON),
+            ),
+            JudgeType::Token => compare_output_token(
+                program_output,
+                &expected_output,
+                problem.misc.case_insensitive,
+            ),
             _ => {
                 log::warn!("Unsupported judge type: {:?}", problem.judge_type);
-                false
+                false.into()
             }
         };
 
-        Ok(is_correct)
+        Ok(check)
+    }
+}
+
+/// Returns the limits that apply to a program run on a test case
+pub(super) fn runtime_limits(case_config: &OneCaseConfig) -> RunLimits {
+    let memory_limit = case_config.memory_limit.0;
+
+    RunLimits {
+        wall_time: Second::from(case_config.time_limit).0 + 0.5,
+        memory: memory_limit,
+        stack: Some(memory_limit / 2),
+        processes: RUNTIME_PROCESSES,
+        open_files: RUNTIME_OPEN_FILES,
+        file_size: RUNTIME_FILE_SIZE,
     }
 }
 
@@ -246,19 +303,18 @@ impl Sandbox {
 /// This is more lenient than strict comparison and is suitable for most
 /// programming contests.
 fn compare_output_standard(program_output: &str, expected_output: &str) -> bool {
-    let normalize = |s: &str| -> String {
-        s.lines()
-            .map(|line| line.trim_end())
-            .collect::<Vec<_>>()
-            .join("\n")
-            .trim_end()
-            .to_string()
-    };
-
-    let normalized_program = normalize(program_output);
-    let normalized_expected = normalize(expected_output);
-
-    normalized_program == normalized_expected
+    normalize_output_standard(program_output) == normalize_output_standard(expected_output)
+}
+
+/// Strips trailing spaces on each line and trailing empty lines, as standard mode does
+fn normalize_output_standard(output: &str) -> String {
+    output
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
 }
 
 /// Compares program output with expected output using strict mode
@@ -270,3 +326,237 @@ fn compare_output_standard(program_output: &str, expected_output: &str) -> bool
 fn compare_output_strict(program_output: &str, expected_output: &str) -> bool {
     program_output == expected_output
 }
+
+/// Describes where two differing outputs first diverge, at the requested detail level
+///
+/// Both iterators yield the lines already prepared by the comparison mode. Returns
+/// an empty string for `WrongAnswerInfo::None`.
+fn describe_line_mismatch<'a>(
+    program_lines: impl Iterator<Item = &'a str>,
+    expected_lines: impl Iterator<Item = &'a str>,
+    level: WrongAnswerInfo,
+) -> String {
+    if level == WrongAnswerInfo::None {
+        return String::new();
+    }
+
+    let mut program_lines = program_lines.fuse();
+    let mut expected_lines = expected_lines.fuse();
+    let mut line_no = 0;
+
+    loop {
+        line_no += 1;
+        match (program_lines.next(), expected_lines.next()) {
+            (None, None) => return String::new(),
+            (None, Some(_)) => return "Output too short".to_string(),
+            (Some(_), None) => return "Output too long".to_string(),
+            (Some(found), Some(expected)) if found != expected => {
+                return match level {
+                    WrongAnswerInfo::Snippet => format!(
+                        "First mismatch at line {line_no}: expected {:?}, found {:?}",
+                        truncate_snippet(expected),
+                        truncate_snippet(found)
+                    ),
+                    _ => format!("First mismatch at line {line_no}"),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Truncates a line to at most `WRONG_ANSWER_SNIPPET_LEN` characters
+fn truncate_snippet(line: &str) -> String {
+    match line.char_indices().nth(WRONG_ANSWER_SNIPPET_LEN) {
+        Some((offset, _)) => format!("{}...", &line[..offset]),
+        None => line.to_string(),
+    }
+}
+
+/// Compares program output with expected output token by token
+///
+/// Tokens are separated by any whitespace. When the expected token is a finite
+/// number, the program token must also be a number within `epsilon` of it, either
+/// absolutely or relatively. All other tokens must match exactly. On mismatch,
+/// the first differing token of the program output and its position are reported.
+fn compare_output_float(program_output: &str, expected_output: &str, epsilon: f64) -> OutputCheck {
+    let mut program_tokens = program_output.split_whitespace();
+    let mut expected_tokens = expected_output.split_whitespace();
+    let mut position = 0;
+
+    loop {
+        position += 1;
+        let (program_token, expected_token) = match (program_tokens.next(), expected_tokens.next())
+        {
+            (None, None) => return true.into(),
+            (None, Some(_)) => {
+                return OutputCheck::wrong(format!("Output too short: token {position} missing"));
+            }
+            (Some(found), None) => {
+                return OutputCheck::wrong(format!(
+                    "Output too long: unexpected token {position} `{found}`"
+                ));
+            }
+            (Some(found), Some(expected)) => (found, expected),
+        };
+
+        let matched = match expected_token.parse::<f64>() {
+            Ok(expected) if expected.is_finite() => program_token
+                .parse::<f64>()
+                .is_ok_and(|found| float_within_epsilon(found, expected, epsilon)),
+            _ => program_token == expected_token,
+        };
+
+        if !matched {
+            return OutputCheck::wrong(format!(
+                "Token {position} differs: found `{program_token}`"
+            ));
+        }
+    }
+}
+
+/// Returns whether `found` is within `epsilon` of `expected`, absolutely or relatively
+fn float_within_epsilon(found: f64, expected: f64, epsilon: f64) -> bool {
+    let diff = (found - expected).abs();
+    diff <= epsilon || diff <= epsilon * expected.abs()
+}
+
+/// Compares program output with expected output as whitespace-separated tokens
+///
+/// Line structure and the amount of whitespace are ignored entirely, so an answer
+/// printed on one line matches the same answer spread across many. On mismatch,
+/// only the line and column in the program output are reported, so that the
+/// expected answer is not revealed.
+fn compare_output_token(
+    program_output: &str,
+    expected_output: &str,
+    case_insensitive: bool,
+) -> OutputCheck {
+    let mut program_tokens = tokens_with_position(program_output);
+    let mut expected_tokens = expected_output.split_whitespace();
+    let mut last_line = 1;
+
+    loop {
+        match (program_tokens.next(), expected_tokens.next()) {
+            (None, None) => return true.into(),
+            (None, Some(_)) => {
+                return OutputCheck::wrong(format!(
+                    "Output too short: ended early after line {last_line}"
+                ));
+            }
+            (Some((line, column, _)), None) => {
+                return OutputCheck::wrong(format!(
+                    "Output too long: unexpected token at line {line}, column {column}"
+                ));
+            }
+            (Some((line, column, found)), Some(expected)) => {
+                let matched = if case_insensitive {
+                    found.eq_ignore_ascii_case(expected)
+                } else {
+                    found == expected
+                };
+                if !matched {
+                    return OutputCheck::wrong(format!("Mismatch at line {line}, column {column}"));
+                }
+                last_line = line;
+            }
+        }
+    }
+}
+
+/// Splits text on whitespace, yielding each token with its 1-based line and column
+fn tokens_with_position(text: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    text.lines().enumerate().flat_map(|(line_idx, line)| {
+        let mut tokens = Vec::new();
+        let mut start: Option<(usize, usize)> = None; // (byte offset, column)
+
+        for (column, (offset, ch)) in line.char_indices().enumerate() {
+            match (ch.is_whitespace(), start) {
+                (false, None) => start = Some((offset, column + 1)),
+                (true, Some((begin, begin_column))) => {
+                    tokens.push((line_idx + 1, begin_column, &line[begin..offset]));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some((begin, begin_column)) = start {
+            tokens.push((line_idx + 1, begin_column, &line[begin..]));
+        }
+
+        tokens
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_output_float() {
+        assert!(compare_output_float("1.0000001 2\nyes", "1 2.0 yes\n", 1e-6).is_correct());
+        assert!(compare_output_float("1000000.5", "1000000", 1e-6).is_correct());
+
+        let check = compare_output_float("1.1 2", "1 2", 1e-6);
+        assert!(!check.is_correct());
+        assert_eq!(check.info, "Token 1 differs: found `1.1`");
+
+        let check = compare_output_float("1 YES", "1 yes", 1e-6);
+        assert_eq!(check.info, "Token 2 differs: found `YES`");
+
+        let check = compare_output_float("1", "1 2", 1e-6);
+        assert_eq!(check.info, "Output too short: token 2 missing");
+        assert!(!compare_output_float("1 2 3", "1 2", 1e-6).is_correct());
+        assert!(!compare_output_float("nan", "1", 1e-6).is_correct());
+    }
+
+    #[test]
+    fn test_compare_output_token() {
+        assert!(compare_output_token("1 2\n3\n\n", "1\n2 3", false).is_correct());
+        assert!(compare_output_token("Yes", "YES", true).is_correct());
+        assert!(!compare_output_token("Yes", "YES", false).is_correct());
+
+        let check = compare_output_token("1 2\n  4 5", "1 2 3 5", false);
+        assert_eq!(check.info, "Mismatch at line 2, column 3");
+
+        let check = compare_output_token("1 2\n3 4", "1 2 3", false);
+        assert_eq!(
+            check.info,
+            "Output too long: unexpected token at line 2, column 3"
+        );
+
+        let check = compare_output_token("1\n2", "1 2 3", false);
+        assert_eq!(check.info, "Output too short: ended early after line 2");
+    }
+
+    #[test]
+    fn test_describe_line_mismatch() {
+        let program = normalize_output_standard("1\n2 \n4\n");
+        let expected = normalize_output_standard("1\n2\n3\n\n");
+        let describe =
+            |level| describe_line_mismatch(program.split('\n'), expected.split('\n'), level);
+        assert_eq!(describe(WrongAnswerInfo::None), "");
+        assert_eq!(describe(WrongAnswerInfo::Line), "First mismatch at line 3");
+        assert_eq!(
+            describe(WrongAnswerInfo::Snippet),
+            "First mismatch at line 3: expected \"3\", found \"4\""
+        );
+
+        let check = |program: &str, expected: &str| {
+            describe_line_mismatch(
+                program.split_inclusive('\n'),
+                expected.split_inclusive('\n'),
+                WrongAnswerInfo::Line,
+            )
+        };
+        assert_eq!(check("1\n", "1\n2\n"), "Output too short");
+        assert_eq!(check("1\n2\n", "1\n"), "Output too long");
+        assert_eq!(check("1", "1\n"), "First mismatch at line 1");
+
+        let long_line = "x".repeat(100);
+        assert_eq!(
+            truncate_snippet(&long_line),
+            format!("{}...", "x".repeat(40))
+        );
+    }
+}
@@ -2,36 +2,62 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use actix_web::{App, HttpServer, dev::Server, middleware, web};
-use sqlx::sqlite::SqlitePool;
 
 use crate::config::{LanguageConfig, ProblemConfig, ServerConfig};
+use crate::database::Storage;
 use crate::queue::JobQueue;
+use crate::rate_limit::RateLimiter;
 use crate::routes::{
-    delete_job_handler, exit, get_job_by_id_handler, get_jobs_handler, get_ranklist_handler,
-    get_users_handler, json_error_handler, post_job_handler, post_users_handler, put_job_handler,
-    query_error_handler,
+    AdminToken, JudgeSecret, ProblemFiles, ReadPool, delete_compile_cache_handler,
+    delete_job_handler, exit, get_case_artifacts_handler, get_export_handler,
+    get_job_artifacts_handler, get_job_by_id_handler, get_jobs_handler, get_judge_file_handler,
+    get_queue_handler, get_ranklist_handler, get_users_handler, get_workers_handler,
+    json_error_handler, post_drain_queue_handler, post_import_handler, post_job_handler,
+    post_judge_case_handler, post_judge_claim_handler, post_judge_heartbeat_handler,
+    post_judge_result_handler, post_pause_workers_handler, post_resume_workers_handler,
+    post_users_handler, put_job_handler, query_error_handler,
 };
+use crate::sandbox::{ArtifactStore, CompileCache};
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_server(
     server_config: ServerConfig,
     problems: Arc<ProblemConfig>,
     languages: Arc<LanguageConfig>,
-    db_pool: Arc<SqlitePool>,
+    db_pool: Arc<dyn Storage>,
+    read_pool: Arc<dyn Storage>,
     job_queue: Arc<JobQueue>,
+    compile_cache: Arc<CompileCache>,
+    artifacts: Arc<ArtifactStore>,
+    problem_files: Arc<ProblemFiles>,
 ) -> std::io::Result<Server> {
     let db_pool = web::Data::from(db_pool);
+    let read_pool = web::Data::new(ReadPool(read_pool));
     let problems = web::Data::from(problems);
     let languages = web::Data::from(languages);
     let job_queue = web::Data::from(job_queue); // Construct directly from Arc
     let blocking = web::Data::new(server_config.blocking.unwrap_or(false));
+    let compile_cache = web::Data::from(compile_cache);
+    let artifacts = web::Data::from(artifacts);
+    let admin_token = web::Data::new(AdminToken(server_config.admin_token.clone()));
+    let judge_secret = web::Data::new(JudgeSecret(server_config.judge_secret.clone()));
+    let problem_files = web::Data::from(problem_files);
+    let rate_limiter = web::Data::new(RateLimiter::new(&server_config.rate_limit));
 
     let server = HttpServer::new(move || {
         App::new()
             .app_data(db_pool.clone())
+            .app_data(read_pool.clone())
             .app_data(problems.clone())
             .app_data(languages.clone())
             .app_data(job_queue.clone())
             .app_data(blocking.clone())
+            .app_data(compile_cache.clone())
+            .app_data(artifacts.clone())
+            .app_data(admin_token.clone())
+            .app_data(judge_secret.clone())
+            .app_data(problem_files.clone())
+            .app_data(rate_limiter.clone())
             .app_data(
                 web::JsonConfig::default()
                     .limit(1024 * 1024) // 1MB limit to prevent excessive memory usage
@@ -42,11 +68,26 @@ pub fn build_server(
             .service(post_job_handler)
             .service(get_job_by_id_handler)
             .service(get_jobs_handler)
+            .service(get_job_artifacts_handler)
+            .service(get_case_artifacts_handler)
             .service(put_job_handler)
             .service(delete_job_handler)
             .service(get_users_handler)
             .service(post_users_handler)
             .service(get_ranklist_handler)
+            .service(delete_compile_cache_handler)
+            .service(get_queue_handler)
+            .service(get_workers_handler)
+            .service(post_pause_workers_handler)
+            .service(post_resume_workers_handler)
+            .service(post_drain_queue_handler)
+            .service(get_export_handler)
+            .service(post_import_handler)
+            .service(post_judge_claim_handler)
+            .service(post_judge_heartbeat_handler)
+            .service(post_judge_case_handler)
+            .service(post_judge_result_handler)
+            .service(get_judge_file_handler)
             .service(exit)
     })
     .bind((
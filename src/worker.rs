@@ -1,24 +1,95 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use sqlx::SqlitePool;
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
 use crate::config::{LanguageConfig, ProblemConfig};
-use crate::database as db;
-use crate::queue::JobQueue;
-use crate::routes::JobMessage;
-use crate::sandbox::Sandbox;
+use crate::queue::{ClaimedJob, JobQueue, lease_owner};
+use crate::routes::{CaseResult, JobRecord};
+use crate::sandbox::{CheckerCache, Sandbox};
 
-pub async fn worker(
+/// Where workers claim jobs from and report their results to
+///
+/// `owner` identifies the worker holding the lease of a job, see `lease_owner`.
+pub trait JobSource: Send + Sync + 'static {
+    /// Waits until a job can be claimed and leases it to `owner`
+    fn claim(&self, owner: &str) -> impl Future<Output = ClaimedJob> + Send;
+
+    /// Interval at which the lease of a running job must be renewed
+    fn heartbeat_interval(&self) -> Duration;
+
+    /// Renews the lease of a job, returning whether `owner` still holds it
+    fn heartbeat(
+        &self,
+        job_id: u32,
+        owner: &str,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send;
+
+    /// Reports the result of a case as soon as it is judged
+    fn report_case(
+        &self,
+        job_id: u32,
+        owner: &str,
+        case: &CaseResult,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Submits the final result of a job, or discards the job without one
+    ///
+    /// Returns whether `owner` still held the lease, the result is dropped otherwise.
+    fn finish(
+        &self,
+        job_id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send;
+}
+
+/// Jobs from the queue in the database shared with the server
+pub struct LocalJobs {
+    pub queue: Arc<JobQueue>,
+    pub problems: Arc<ProblemConfig>,
+    pub languages: Arc<LanguageConfig>,
+}
+
+impl JobSource for LocalJobs {
+    async fn claim(&self, owner: &str) -> ClaimedJob {
+        self.queue.pop(owner, &self.problems, &self.languages).await
+    }
+
+    fn heartbeat_interval(&self) -> Duration {
+        self.queue.heartbeat_interval()
+    }
+
+    async fn heartbeat(&self, job_id: u32, owner: &str) -> anyhow::Result<bool> {
+        Ok(self.queue.heartbeat(job_id, owner).await?)
+    }
+
+    async fn report_case(&self, job_id: u32, owner: &str, case: &CaseResult) -> anyhow::Result<()> {
+        self.queue.report_case(job_id, owner, case).await?;
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        job_id: u32,
+        owner: &str,
+        result: Option<&JobRecord>,
+    ) -> anyhow::Result<bool> {
+        Ok(self.queue.finish(job_id, owner, result).await?)
+    }
+}
+
+pub async fn worker<S: JobSource>(
     id: u8,
-    problems: Arc<ProblemConfig>,
-    languages: Arc<LanguageConfig>,
-    db_pool: Arc<SqlitePool>,
-    queue: Arc<JobQueue>,
+    source: Arc<S>,
+    checkers: Arc<CheckerCache>,
+    sandbox: Sandbox,
     token: CancellationToken,
 ) -> anyhow::Result<()> {
-    let sandbox = Arc::new(Sandbox::build(id)?);
-    log::info!("Worker {id} initialized");
+    let sandbox = Arc::new(sandbox);
+    let owner = lease_owner(id);
+    log::info!("Worker {id} initialized as {owner}");
 
     loop {
         tokio::select! {
@@ -27,71 +98,71 @@ pub async fn worker(
                 break;
             }
 
-            job_message = queue.pop() => {
-                let job_id = job_message.id();
-
-                // 1. Get full job from database
-                let job = db::fetch_job(job_id, db_pool.clone()).await;
-                let job = match job {
-                    Ok(job) => job,
-                    Err(e) => {
-                        log::error!("Failed to fetch job {job_id} from database, job discarded: {e}");
-                        continue; // Skip to the next iteration
-                    }
-                };
+            claimed = source.claim(&owner) => {
+                // 1. Got a job already marked Running, along with its configs
+                let checker_languages = claimed.checker_languages();
+                let ClaimedJob { job, problem, language, .. } = claimed;
+                let job_id = job.id;
                 log::info!("Worker {id} got job {job_id} from queue");
 
-                // 2. Update job status to Running
-                if let Err(e) = db::update_job_to_running(job_id, db_pool.clone()).await {
-                    log::error!("Failed to update job {job_id} status to Running: {e}");
-                    continue; // Skip to the next iteration
-                }
-
-                let problem_config = problems
-                    .iter()
-                    .find(|p| p.id == job.submission.problem_id)
-                    .cloned();
-                let language_config = languages
-                    .iter()
-                    .find(|l| l.name == job.submission.language)
-                    .cloned();
-
-                if problem_config.is_none() || language_config.is_none() {
-                    log::error!(
-                        "Missing config for problem {} or language {}, job {job_id} discarded",
-                        job.submission.problem_id,
-                        job.submission.language
-                    );
-                    continue; // Skip to the next iteration
-                }
-
-                // 3. Spawn blocking judger and get its handle
+                // 2. Spawn blocking judger and get its handle
                 let sandbox_ref = Arc::clone(&sandbox);
-                let result_handle = tokio::task::spawn_blocking(move || {
-                    let result = sandbox_ref.run(job, problem_config.unwrap(), language_config.unwrap());
+                let checkers_ref = Arc::clone(&checkers);
+                let (progress_tx, mut progress_rx) = mpsc::unbounded_channel::<CaseResult>();
+                let mut result_handle = tokio::task::spawn_blocking(move || {
+                    // Compile the problem's checker first if it has one
+                    let result = match checkers_ref.prepare(problem, &checker_languages) {
+                        Ok(problem) => sandbox_ref.run(job, problem, language, &mut |case| {
+                            let _ = progress_tx.send(case.clone());
+                        }),
+                        Err(compile_log) => {
+                            let mut job = job;
+                            job.cases[0].result = "System Error".to_string();
+                            job.cases[0].info = format!("Checker compilation failed:\n{compile_log}");
+                            job.result = "System Error".to_string();
+                            job.state = "Finished".to_string();
+                            Ok(job)
+                        }
+                    };
                     log::info!("Job {job_id} finished on worker {id}");
 
                     result
                 });
 
-                // 4. Give back control to the runtime until job is done
-                match result_handle.await {
-                    Ok(Ok(job_result)) => {
-                        db::save_result(job_id, db_pool.clone(), &job_result)
-                            .await
-                            .unwrap_or_else(|e| log::error!("Failed to save job {job_id} result: {e}"));
-
-                        if let JobMessage::Blocking { responder, .. } = job_message {
-                            if responder.send(job_result).is_err() {
-                                log::warn!("Failed to send blocking job {job_id} result back to server");
-                            } else {
-                                log::debug!("Blocking job {job_id} result sent back from worker {id}");
+                // 3. Give back control to the runtime until job is done, keeping the lease
+                //    alive and reporting the progress of each case
+                let mut heartbeat = tokio::time::interval(source.heartbeat_interval());
+                heartbeat.tick().await; // The first tick completes immediately
+                let result = loop {
+                    tokio::select! {
+                        result = &mut result_handle => break result,
+                        Some(case) = progress_rx.recv() => {
+                            if let Err(e) = source.report_case(job_id, &owner, &case).await {
+                                log::warn!("Failed to report case {} of job {job_id}: {e}", case.id);
                             }
                         }
+                        _ = heartbeat.tick() => match source.heartbeat(job_id, &owner).await {
+                            Ok(true) => {}
+                            Ok(false) => log::warn!("Worker {id} lost the lease of job {job_id}"),
+                            Err(e) => log::error!("Failed to renew the lease of job {job_id}: {e}"),
+                        },
                     }
+                };
+
+                // 4. Submit the result, unless the lease expired in the meantime
+                let job_result = match result {
+                    Ok(Ok(job_result)) => Some(job_result),
                     err => {
                         log::error!("Spawning job {job_id} failed on worker {id}: {err:?}");
+                        None
+                    }
+                };
+                match source.finish(job_id, &owner, job_result.as_ref()).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::warn!("Lease of job {job_id} expired on worker {id}, result discarded");
                     }
+                    Err(e) => log::error!("Failed to save job {job_id} result: {e}"),
                 }
             }
         };
@@ -1,3 +1,6 @@
+#[path = "common/postgres.rs"]
+pub mod postgres;
+
 use assert_json_diff::{CompareMode, Config, assert_json_matches_no_panic};
 use lazy_static::lazy_static;
 use reqwest::blocking::{Client, RequestBuilder};
@@ -137,13 +140,21 @@ impl TestCase {
             config["server"]["bind_port"]
         );
 
+        let mut arguments = vec![
+            "--config".to_string(),
+            config_file.to_str().unwrap().to_string(),
+            "--flush-data".to_string(),
+            // Every case restarts the server, which a self-test would slow down
+            "--skip-self-test".to_string(),
+        ];
+        // Allows running the cases without isolate, e.g. with the `process` backend
+        if let Ok(backend) = std::env::var("OJ_TEST_SANDBOX_BACKEND") {
+            arguments.extend(["--sandbox-backend".to_string(), backend]);
+        }
+
         Self {
             name: name.to_string(),
-            arguments: vec![
-                "--config".to_string(),
-                config_file.to_str().unwrap().to_string(),
-                "--flush-data".to_string(),
-            ],
+            arguments,
             data: serde_json::from_reader(File::open(data_file).unwrap()).unwrap(),
             prefix,
             running_process: None,
@@ -378,7 +389,19 @@ impl TestCase {
         body
     }
 
+    /// Runs the case on SQLite, then on PostgreSQL unless it's unavailable, returning
+    /// the responses of the SQLite run
     pub fn run(&mut self) -> Vec<Value> {
+        let responses = self.run_once();
+        if postgres::available() {
+            let url = postgres::create_database(&format!("oj_{}", self.name));
+            self.arguments.extend(["--database".to_string(), url]);
+            self.run_once();
+        }
+        responses
+    }
+
+    fn run_once(&mut self) -> Vec<Value> {
         self.start_server(false);
         // send requests sequentially
         let res = self
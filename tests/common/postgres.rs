@@ -0,0 +1,119 @@
+//! Local PostgreSQL cluster that the tests run against besides SQLite
+//!
+//! The tests run on PostgreSQL too whenever it is installed, unless
+//! `OJ_TEST_DATABASE=sqlite`. With `OJ_TEST_DATABASE=postgres`, a missing PostgreSQL
+//! fails the tests instead of skipping their PostgreSQL runs. The cluster is created in a temporary directory on first use, listens only on a
+//! socket there, and is stopped when the test process exits. Its tools are looked up
+//! in the directory printed by `pg_config --bindir`. Running as root, they run as the
+//! `postgres` user, as `initdb` refuses to run as root.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+const PORT: u16 = 5432;
+const USER: &str = "oj";
+
+struct Cluster {
+    dir: PathBuf,
+    bin_dir: PathBuf,
+}
+
+static CLUSTER: OnceLock<Cluster> = OnceLock::new();
+
+/// Whether the tests should run against PostgreSQL as well as SQLite
+pub fn available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| match std::env::var("OJ_TEST_DATABASE").as_deref() {
+        Ok("sqlite") => false,
+        Ok("postgres") => true,
+        _ => {
+            let installed = bin_dir()
+                .is_some_and(|dir| ["initdb", "pg_ctl"].iter().all(|t| dir.join(t).is_file()));
+            if !installed {
+                // Not through `eprintln!`, which the test harness captures
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "PostgreSQL not found, skipping the PostgreSQL runs of the tests"
+                );
+            }
+            installed
+        }
+    })
+}
+
+/// Directory of the PostgreSQL tools, as printed by `pg_config --bindir`
+fn bin_dir() -> Option<PathBuf> {
+    let output = Command::new("pg_config").arg("--bindir").output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Creates an empty database named `name`, returning its URL
+pub fn create_database(name: &str) -> String {
+    let cluster = CLUSTER.get_or_init(Cluster::start);
+    let port = PORT.to_string();
+    let dir = cluster.dir.to_str().unwrap();
+    cluster.run("createdb", &["-h", dir, "-p", &port, "-U", USER, name]);
+    format!("postgres://{USER}@localhost:{PORT}/{name}?host={dir}")
+}
+
+impl Cluster {
+    fn start() -> Self {
+        let bin_dir = bin_dir().expect("pg_config not found, is PostgreSQL installed?");
+        let dir = std::env::temp_dir().join(format!("oj-test-postgres-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let cluster = Self { dir, bin_dir };
+        let data = cluster.dir.to_str().unwrap();
+        cluster.run("initdb", &["-D", data, "-A", "trust", "-U", USER]);
+        let options = format!("-k {data} -p {PORT} -c listen_addresses=''");
+        let log = cluster.dir.join("postgres.log");
+        let log = log.to_str().unwrap();
+        cluster.run(
+            "pg_ctl",
+            &["-D", data, "-o", &options, "-l", log, "-w", "start"],
+        );
+
+        // SAFETY: `stop` only touches the cluster, which is initialized by now
+        unsafe { libc::atexit(stop) };
+        cluster
+    }
+
+    fn command(&self, tool: &str, args: &[&str]) -> Command {
+        let program = self.bin_dir.join(tool);
+        let mut command = if unsafe { libc::geteuid() } == 0 {
+            let mut command = Command::new("runuser");
+            command.args(["-u", "postgres", "--"]).arg(program);
+            command
+        } else {
+            Command::new(program)
+        };
+        command.args(args).current_dir(std::env::temp_dir());
+        command
+    }
+
+    /// Runs a PostgreSQL tool, panicking if it fails
+    fn run(&self, tool: &str, args: &[&str]) {
+        let status = self
+            .command(tool, args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap_or_else(|e| panic!("Failed to run {tool}: {e}"));
+        assert!(status.success(), "{tool} failed with {status}");
+    }
+}
+
+extern "C" fn stop() {
+    if let Some(cluster) = CLUSTER.get() {
+        let data = cluster.dir.to_str().unwrap();
+        // Panicking here would abort the process
+        let _ = cluster
+            .command("pg_ctl", &["-D", data, "-m", "immediate", "-w", "stop"])
+            .status();
+        let _ = std::fs::remove_dir_all(&cluster.dir);
+    }
+}
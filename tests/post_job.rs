@@ -10,49 +10,120 @@ use actix_web::{
 };
 use chrono::{SecondsFormat, Utc};
 use serde_json::json;
-use sqlx::sqlite::SqlitePool;
+
+#[path = "common/postgres.rs"]
+mod postgres;
 
 use oj::config::{
-    JudgeType, KiloByte, LanguageConfig, MicroSecond, OneCaseConfig, OneLanguageConfig,
-    OneProblemConfig, ProblemConfig,
+    DatabaseConfig, JudgeType, KiloByte, LanguageConfig, MicroSecond, OneCaseConfig,
+    OneLanguageConfig, OneProblemConfig, ProblemConfig, ProblemMisc, QueueConfig, RateLimitConfig,
+    TokenBucketConfig,
+};
+use oj::database::{self as db, ExportFilter, ImportMode, Storage};
+use oj::queue::{JobPriority, JobQueue};
+use oj::rate_limit::RateLimiter;
+use oj::routes::{
+    AdminToken, CaseResult, JobRecord, JobSubmission, JobsQueryParams, JudgeSecret, ProblemFiles,
+    get_judge_file_handler, hash_content, post_job_handler, post_judge_case_handler,
+    post_judge_claim_handler, post_judge_heartbeat_handler, post_judge_result_handler,
+};
+
+// Queue settings of the tests, leases last 30s by default
+const QUEUE_CONFIG: QueueConfig = QueueConfig {
+    lease_secs: None,
+    max_length: None,
+    blocking_timeout_secs: None,
 };
-use oj::database as db;
-use oj::queue::JobQueue;
-use oj::routes::{CaseResult, JobMessage, JobRecord, JobSubmission, post_job_handler};
+
+/// Database a test runs against
+#[derive(Clone, Copy)]
+enum Database {
+    Sqlite,
+    Postgres,
+}
+
+/// Defines each test, taking the database to run against, as `<test>::sqlite` and
+/// `<test>::postgres`, the latter passing without running if PostgreSQL is unavailable
+macro_rules! database_tests {
+    ($($test:ident),* $(,)?) => {
+        $(
+            mod $test {
+                #[actix_web::test]
+                async fn sqlite() {
+                    super::$test(super::Database::Sqlite).await;
+                }
+
+                #[actix_web::test]
+                async fn postgres() {
+                    if super::postgres::available() {
+                        super::$test(super::Database::Postgres).await;
+                    }
+                }
+            }
+        )*
+    };
+}
+
+database_tests!(
+    test_post_jobs_nonblocking_success,
+    test_post_jobs_blocking_success,
+    test_post_jobs_invalid_language,
+    test_post_jobs_invalid_problem_id,
+    test_post_jobs_unknown_contest,
+    test_post_jobs_invalid_json,
+    test_post_jobs_missing_fields,
+    test_blocking_job_delayed_response,
+    test_multiple_languages_support,
+    test_database_persistence,
+    test_concurrent_requests,
+    test_queue_priority_and_round_robin,
+    test_queue_pause_and_drain,
+    test_post_jobs_rate_limited,
+    test_post_jobs_queue_full_and_blocking_timeout,
+    test_export_and_import_archive,
+    test_judge_node_protocol,
+);
 
 // Global counter to ensure unique test database names
 static TEST_DB_COUNTER: AtomicU32 = AtomicU32::new(0);
 
 // Helper function to create isolated test database
-async fn create_test_db() -> (SqlitePool, String) {
-    // Create a unique database file for each test
+async fn create_test_db(database: Database) -> (Arc<dyn Storage>, String) {
+    // Create a unique database for each test
     let test_id = TEST_DB_COUNTER.fetch_add(1, Ordering::SeqCst);
-    let db_name = format!("test_oj_{test_id}.db");
-    let db_path = format!("data/{db_name}");
-
-    // Remove existing test database if it exists
-    let _ = fs::remove_file(&db_path);
+    let db_path = match database {
+        Database::Postgres => postgres::create_database(&format!("test_oj_{test_id}")),
+        Database::Sqlite => {
+            let db_path = format!("data/test_oj_{test_id}.db");
+            // Remove existing test database if it exists
+            let _ = fs::remove_file(&db_path);
+            db_path
+        }
+    };
 
-    let db_pool = db::init_db(&db_path).await.unwrap();
+    let database = DatabaseConfig {
+        path: Some(db_path.clone()),
+        ..Default::default()
+    };
+    let storage = oj::database::init_db(&database).await.unwrap();
 
-    // Add test users for integration tests
+    // Add test users for integration tests, with ids 1 to 9
     for i in 1..10 {
-        let user_name = format!("test_user_{i}");
-        sqlx::query!(
-            "INSERT OR IGNORE INTO users (id, name) VALUES (?, ?)",
-            i,
-            user_name
-        )
-        .execute(&db_pool)
-        .await
-        .unwrap();
+        storage
+            .create_user(&format!("test_user_{i}"))
+            .await
+            .unwrap();
     }
 
-    (db_pool, db_path)
+    (storage, db_path)
 }
 
 // Helper function to cleanup test database
 fn cleanup_test_db(db_path: &str) {
+    // Databases in the PostgreSQL cluster go away with it
+    if db_path.starts_with("postgres:") {
+        return;
+    }
     if let Err(e) = fs::remove_file(db_path) {
         eprintln!("Warning: Failed to remove test database {db_path}: {e}");
     } else {
@@ -95,6 +166,7 @@ fn create_test_config() -> (Arc<ProblemConfig>, Arc<LanguageConfig>) {
             id: 0,
             name: "test_problem_1".to_string(),
             judge_type: JudgeType::Standard,
+            misc: ProblemMisc::default(),
             cases: vec![
                 OneCaseConfig {
                     score: 50.0,
@@ -116,6 +188,7 @@ fn create_test_config() -> (Arc<ProblemConfig>, Arc<LanguageConfig>) {
             id: 1,
             name: "test_problem_2".to_string(),
             judge_type: JudgeType::Standard,
+            misc: ProblemMisc::default(),
             cases: vec![OneCaseConfig {
                 score: 100.0,
                 input_file: "test1.in".to_string(),
@@ -136,11 +209,13 @@ fn create_test_config() -> (Arc<ProblemConfig>, Arc<LanguageConfig>) {
                 "%OUTPUT%".to_string(),
                 "%INPUT%".to_string(),
             ],
+            seccomp: None,
         },
         OneLanguageConfig {
             name: "Python".to_string(),
             file_name: "main.py".to_string(),
             command: vec!["python3".to_string(), "%INPUT%".to_string()],
+            seccomp: None,
         },
     ];
 
@@ -148,74 +223,72 @@ fn create_test_config() -> (Arc<ProblemConfig>, Arc<LanguageConfig>) {
 }
 
 // Mock judger that simulates evaluation results
-async fn mock_judger(job_queue: Arc<JobQueue>) {
+async fn mock_judger(
+    job_queue: Arc<JobQueue>,
+    problems: Arc<ProblemConfig>,
+    languages: Arc<LanguageConfig>,
+) {
     loop {
-        let message = job_queue.pop().await;
-        match message {
-            JobMessage::FireAndForget { job_id } => {
-                // For non-blocking jobs, we just consume the message
-                println!("Mock judger received fire-and-forget job: {job_id}");
-            }
-            JobMessage::Blocking { job_id, responder } => {
-                // For blocking jobs, we send back a mock response
-                println!("Mock judger received blocking job: {job_id}");
-
-                let mock_response = JobRecord {
-                    id: job_id,
-                    created_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
-                    updated_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
-                    submission: JobSubmission {
-                        source_code: "fn main() { println!(\"Hello World!\"); }".to_string(),
-                        language: "Rust".to_string(),
-                        user_id: 0,
-                        contest_id: 0,
-                        problem_id: 0,
-                    },
-                    state: "Finished".to_string(),
+        let job_id = job_queue.pop("mock", &problems, &languages).await.job.id;
+        println!("Mock judger received job: {job_id}");
+
+        let mock_response = JobRecord {
+            id: job_id,
+            created_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            updated_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            submission: JobSubmission {
+                source_code: "fn main() { println!(\"Hello World!\"); }".to_string(),
+                language: "Rust".to_string(),
+                user_id: 0,
+                contest_id: 0,
+                problem_id: 0,
+            },
+            state: "Finished".to_string(),
+            result: "Accepted".to_string(),
+            score: 100.0,
+            cases: vec![
+                CaseResult {
+                    id: 0,
                     result: "Accepted".to_string(),
-                    score: 100.0,
-                    cases: vec![
-                        CaseResult {
-                            id: 0,
-                            result: "Accepted".to_string(),
-                            time: 100,
-                            memory: 1024,
-                            info: "".to_string(),
-                        },
-                        CaseResult {
-                            id: 1,
-                            result: "Accepted".to_string(),
-                            time: 150,
-                            memory: 1024,
-                            info: "".to_string(),
-                        },
-                    ],
-                };
-
-                let _ = responder.send(mock_response);
-            }
-        }
+                    time: 100,
+                    memory: 1024,
+                    score: 0.0,
+                    info: "".to_string(),
+                },
+                CaseResult {
+                    id: 1,
+                    result: "Accepted".to_string(),
+                    time: 150,
+                    memory: 1024,
+                    score: 0.0,
+                    info: "".to_string(),
+                },
+            ],
+        };
+
+        let finished = job_queue.finish(job_id, "mock", Some(&mock_response));
+        assert!(finished.await.unwrap());
     }
 }
 
-#[actix_web::test]
-async fn test_post_jobs_nonblocking_success() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_post_jobs_nonblocking_success(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
-    // Start mock judger
-    tokio::spawn(mock_judger(job_queue.clone()));
+    // No judger runs, so that the job stays in the queue
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -250,37 +323,49 @@ async fn test_post_jobs_nonblocking_success() {
 
     // Verify job was stored in database
     let job_id = response_body["id"].as_u64().unwrap() as u32;
-    let stored_job = sqlx::query!("SELECT * FROM jobs WHERE id = ?", job_id)
-        .fetch_one(&db_pool)
+    let stored_job = storage
+        .fetch_job(job_id)
         .await
         .expect("Failed to fetch job from database");
 
-    assert_eq!(stored_job.user_id, 0);
-    assert_eq!(stored_job.contest_id, 0);
-    assert_eq!(stored_job.problem_id, 1);
-    assert_eq!(stored_job.language, "Rust");
+    assert_eq!(stored_job.submission.user_id, 0);
+    assert_eq!(stored_job.submission.contest_id, 0);
+    assert_eq!(stored_job.submission.problem_id, 1);
+    assert_eq!(stored_job.submission.language, "Rust");
     assert_eq!(stored_job.state, "Queueing");
     assert_eq!(stored_job.result, "Waiting");
+
+    // Verify job was pushed to the durable queue
+    let queued = storage
+        .fetch_queued_jobs()
+        .await
+        .expect("Failed to fetch jobs from queue");
+    assert!(queued.iter().any(|job| job.id == job_id));
 }
 
-#[actix_web::test]
-async fn test_post_jobs_blocking_success() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_post_jobs_blocking_success(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(true);
 
     // Start mock judger
-    tokio::spawn(mock_judger(job_queue.clone()));
+    tokio::spawn(mock_judger(
+        job_queue.clone(),
+        problems.clone(),
+        languages.clone(),
+    ));
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -315,33 +400,34 @@ async fn test_post_jobs_blocking_success() {
 
     // Verify job was stored in database
     let job_id = response_body["id"].as_u64().unwrap() as u32;
-    let stored_job = sqlx::query!("SELECT * FROM jobs WHERE id = ?", job_id)
-        .fetch_one(&db_pool)
+    let stored_job = storage
+        .fetch_job(job_id)
         .await
         .expect("Failed to fetch job from database");
 
-    assert_eq!(stored_job.user_id, 0);
-    assert_eq!(stored_job.contest_id, 0);
-    assert_eq!(stored_job.problem_id, 0);
-    assert_eq!(stored_job.language, "Rust");
-    assert_eq!(stored_job.state, "Queueing"); // Note: database state isn't updated in mock
+    assert_eq!(stored_job.submission.user_id, 0);
+    assert_eq!(stored_job.submission.contest_id, 0);
+    assert_eq!(stored_job.submission.problem_id, 0);
+    assert_eq!(stored_job.submission.language, "Rust");
+    assert_eq!(stored_job.state, "Finished");
 }
 
-#[actix_web::test]
-async fn test_post_jobs_invalid_language() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_post_jobs_invalid_language(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool))
+            .app_data(web::Data::from(storage))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -368,21 +454,22 @@ async fn test_post_jobs_invalid_language() {
     assert_eq!(response_body["code"], 3);
 }
 
-#[actix_web::test]
-async fn test_post_jobs_invalid_problem_id() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_post_jobs_invalid_problem_id(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool))
+            .app_data(web::Data::from(storage))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -409,21 +496,63 @@ async fn test_post_jobs_invalid_problem_id() {
     assert_eq!(response_body["code"], 3);
 }
 
-#[actix_web::test]
-async fn test_post_jobs_invalid_json() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_post_jobs_unknown_contest(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(storage.clone()))
+            .app_data(web::Data::from(problems))
+            .app_data(web::Data::from(languages))
+            .app_data(web::Data::from(job_queue.clone()))
+            .app_data(web::Data::new(false))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .service(post_job_handler),
+    )
+    .await;
+
+    // A made-up contest must not give the job contest priority
+    let request_body = json!({
+        "source_code": "fn main() {}",
+        "language": "Rust",
+        "user_id": 1,
+        "contest_id": 1,
+        "problem_id": 0
+    });
+    let req = test::TestRequest::post()
+        .uri("/jobs")
+        .set_json(&request_body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+
+    let response_body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(response_body["reason"], "ERR_NOT_FOUND");
+    assert_eq!(response_body["message"], "Contest 1 not found.");
+    assert!(job_queue.queued_jobs().await.unwrap().is_empty());
+}
+
+async fn test_post_jobs_invalid_json(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
+    let _guard = TestDbGuard::new(db_path);
+    let (problems, languages) = create_test_config();
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool))
+            .app_data(web::Data::from(storage))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -442,21 +571,22 @@ async fn test_post_jobs_invalid_json() {
     // The error body might not be valid JSON
 }
 
-#[actix_web::test]
-async fn test_post_jobs_missing_fields() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_post_jobs_missing_fields(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool))
+            .app_data(web::Data::from(storage))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -480,60 +610,65 @@ async fn test_post_jobs_missing_fields() {
     // The error body might not be valid JSON
 }
 
-#[actix_web::test]
-async fn test_blocking_job_delayed_response() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_blocking_job_delayed_response(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(true);
 
     // Mock judger that responds after a delay
     let delayed_queue = job_queue.clone();
+    let (delayed_problems, delayed_languages) = (problems.clone(), languages.clone());
     tokio::spawn(async move {
         loop {
-            let message = delayed_queue.pop().await;
-            if let JobMessage::Blocking { job_id, responder } = message {
-                println!("Mock judger received blocking job, will respond after delay: {job_id}");
-
-                // Simulate some processing time
-                tokio::time::sleep(Duration::from_millis(100)).await;
-
-                let response = JobRecord {
-                    id: job_id,
-                    created_time: "2024-08-09T01:00:00.000Z".to_string(),
-                    updated_time: "2024-08-09T01:00:01.000Z".to_string(),
-                    submission: JobSubmission {
-                        source_code: "fn main() { println!(\"Hello World!\"); }".to_string(),
-                        language: "Rust".to_string(),
-                        user_id: 0,
-                        contest_id: 0,
-                        problem_id: 0,
-                    },
-                    state: "Finished".to_string(),
+            let claimed = delayed_queue
+                .pop("mock", &delayed_problems, &delayed_languages)
+                .await;
+            let job_id = claimed.job.id;
+            println!("Mock judger received blocking job, will respond after delay: {job_id}");
+
+            // Simulate some processing time
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let response = JobRecord {
+                id: job_id,
+                created_time: "2024-08-09T01:00:00.000Z".to_string(),
+                updated_time: "2024-08-09T01:00:01.000Z".to_string(),
+                submission: JobSubmission {
+                    source_code: "fn main() { println!(\"Hello World!\"); }".to_string(),
+                    language: "Rust".to_string(),
+                    user_id: 0,
+                    contest_id: 0,
+                    problem_id: 0,
+                },
+                state: "Finished".to_string(),
+                result: "Accepted".to_string(),
+                score: 100.0,
+                cases: vec![CaseResult {
+                    id: 1,
                     result: "Accepted".to_string(),
-                    score: 100.0,
-                    cases: vec![CaseResult {
-                        id: 1,
-                        result: "Accepted".to_string(),
-                        time: 1000,
-                        memory: 1024,
-                        info: "".to_string(),
-                    }],
-                };
-
-                let _ = responder.send(response);
-            }
+                    time: 1000,
+                    memory: 1024,
+                    score: 0.0,
+                    info: "".to_string(),
+                }],
+            };
+
+            let finished = delayed_queue.finish(job_id, "mock", Some(&response));
+            finished.await.unwrap();
         }
     });
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool))
+            .app_data(web::Data::from(storage))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -561,23 +696,28 @@ async fn test_blocking_job_delayed_response() {
     assert_eq!(body.result, "Accepted");
 }
 
-#[actix_web::test]
-async fn test_multiple_languages_support() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_multiple_languages_support(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
-    tokio::spawn(mock_judger(job_queue.clone()));
+    tokio::spawn(mock_judger(
+        job_queue.clone(),
+        problems.clone(),
+        languages.clone(),
+    ));
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -618,31 +758,36 @@ async fn test_multiple_languages_support() {
     assert_eq!(resp.status(), 200);
 
     // Verify both jobs were stored
-    let job_count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM jobs")
-        .fetch_one(&db_pool)
+    let jobs = storage
+        .fetch_jobs_by_query(&JobsQueryParams::default())
         .await
-        .expect("Failed to count jobs");
+        .expect("Failed to fetch jobs");
 
-    assert_eq!(job_count, 2);
+    assert_eq!(jobs.len(), 2);
 }
 
-#[actix_web::test]
-async fn test_database_persistence() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_database_persistence(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
-    tokio::spawn(mock_judger(job_queue.clone()));
+    tokio::spawn(mock_judger(
+        job_queue.clone(),
+        problems.clone(),
+        languages.clone(),
+    ));
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -670,56 +815,54 @@ async fn test_database_persistence() {
     }
 
     // Verify that jobs were inserted into the database
-    let job_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM jobs")
-        .fetch_one(&db_pool)
+    let mut jobs = storage
+        .fetch_jobs_by_query(&JobsQueryParams::default())
         .await
-        .expect("Failed to count jobs");
+        .expect("Failed to fetch jobs");
 
     assert_eq!(
-        job_count.0, 3,
+        jobs.len(),
+        3,
         "Expected 3 jobs to be inserted into database"
     );
 
     // Verify job details
-    let jobs: Vec<(i64, String, String, i64, i64, i64)> = sqlx::query_as(
-        "SELECT id, source_code, language, user_id, contest_id, problem_id FROM jobs ORDER BY id",
-    )
-    .fetch_all(&db_pool)
-    .await
-    .expect("Failed to fetch jobs");
-
-    assert_eq!(jobs.len(), 3);
-
-    for (i, (_, source_code, language, user_id, contest_id, problem_id)) in jobs.iter().enumerate()
-    {
+    jobs.sort_by_key(|job| job.id);
+    for (i, job) in jobs.iter().enumerate() {
+        let submission = &job.submission;
         assert_eq!(
-            source_code,
-            &format!("fn main() {{ println!(\"Test {i}\"); }}")
+            submission.source_code,
+            format!("fn main() {{ println!(\"Test {i}\"); }}")
         );
-        assert_eq!(language, "Rust");
-        assert_eq!(*user_id, i as i64);
-        assert_eq!(*contest_id, 0);
-        assert_eq!(*problem_id, 1);
+        assert_eq!(submission.language, "Rust");
+        assert_eq!(submission.user_id, i as u32);
+        assert_eq!(submission.contest_id, 0);
+        assert_eq!(submission.problem_id, 1);
     }
 }
 
-#[actix_web::test]
-async fn test_concurrent_requests() {
-    let (db_pool, db_path) = create_test_db().await;
+async fn test_concurrent_requests(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
     let _guard = TestDbGuard::new(db_path);
     let (problems, languages) = create_test_config();
-    let job_queue = Arc::new(JobQueue::new());
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
     let blocking = Arc::new(false);
 
-    tokio::spawn(mock_judger(job_queue.clone()));
+    tokio::spawn(mock_judger(
+        job_queue.clone(),
+        problems.clone(),
+        languages.clone(),
+    ));
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
             .app_data(web::Data::from(problems))
             .app_data(web::Data::from(languages))
             .app_data(web::Data::from(job_queue))
             .app_data(web::Data::from(blocking))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
             .service(post_job_handler),
     )
@@ -757,13 +900,518 @@ async fn test_concurrent_requests() {
     }
 
     // Verify that all jobs were inserted
-    let job_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM jobs")
-        .fetch_one(&db_pool)
+    let jobs = storage
+        .fetch_jobs_by_query(&JobsQueryParams::default())
         .await
-        .expect("Failed to count jobs");
+        .expect("Failed to fetch jobs");
 
     assert_eq!(
-        job_count.0, 5,
+        jobs.len(),
+        5,
         "Expected 5 jobs to be inserted into database"
     );
 }
+
+async fn test_queue_priority_and_round_robin(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
+    let _guard = TestDbGuard::new(db_path);
+    let (problems, languages) = create_test_config();
+    let job_queue = JobQueue::new(storage.clone(), &QUEUE_CONFIG);
+
+    // (user, contest, rejudge) of each job, pushed in this order
+    let jobs = [
+        (1, 0, false),
+        (1, 0, false),
+        (1, 0, false),
+        (2, 0, false),
+        (3, 0, true),
+        (4, 1, false),
+    ];
+    for (user_id, contest_id, rejudge) in jobs {
+        let submission = JobSubmission {
+            source_code: "fn main() {}".to_string(),
+            language: "Rust".to_string(),
+            user_id,
+            contest_id,
+            problem_id: 0,
+        };
+        let body = web::Json(submission.clone());
+        let job_id = storage.create_job(&body, 2).await.unwrap();
+        let priority = JobPriority::of(&submission, rejudge);
+        job_queue.push(job_id, &submission, priority).await.unwrap();
+    }
+
+    // The contest job first, then user 2 gets a turn between the jobs of user 1, and
+    // the rejudge last
+    let expected = [5, 0, 3, 1, 2, 4];
+    for (i, job_id) in expected.into_iter().enumerate() {
+        let position = job_queue.position(job_id).await.unwrap();
+        assert_eq!(position, Some(i as u32 + 1), "position of job {job_id}");
+    }
+    for job_id in expected {
+        let claimed = job_queue
+            .try_claim("test", &problems, &languages)
+            .await
+            .unwrap()
+            .expect("Queue should not be empty");
+        assert_eq!(claimed.job.id, job_id);
+        assert_eq!(job_queue.position(job_id).await.unwrap(), None);
+    }
+}
+
+async fn test_queue_pause_and_drain(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
+    let _guard = TestDbGuard::new(db_path);
+    let (problems, languages) = create_test_config();
+    let job_queue = JobQueue::new(storage.clone(), &QUEUE_CONFIG);
+
+    let submission = JobSubmission {
+        source_code: "fn main() {}".to_string(),
+        language: "Rust".to_string(),
+        user_id: 1,
+        contest_id: 0,
+        problem_id: 0,
+    };
+    for _ in 0..3 {
+        let body = web::Json(submission.clone());
+        let job_id = storage.create_job(&body, 2).await.unwrap();
+        let priority = JobPriority::of(&submission, false);
+        job_queue.push(job_id, &submission, priority).await.unwrap();
+    }
+
+    // Nothing is claimed while paused
+    job_queue.set_paused(true).await.unwrap();
+    assert!(job_queue.is_paused().await.unwrap());
+    let claimed = job_queue.try_claim("test", &problems, &languages).await;
+    assert!(claimed.unwrap().is_none());
+    assert_eq!(job_queue.queued_jobs().await.unwrap().len(), 3);
+
+    job_queue.set_paused(false).await.unwrap();
+    let claimed = job_queue.try_claim("test", &problems, &languages).await;
+    assert_eq!(claimed.unwrap().unwrap().job.id, 0);
+    let workers = job_queue.workers().await.unwrap();
+    assert_eq!(workers.len(), 1);
+    assert_eq!(
+        (workers[0].state, workers[0].job_id),
+        ("Compiling", Some(0))
+    );
+
+    // Draining cancels the waiting jobs only
+    assert_eq!(job_queue.drain().await.unwrap(), vec![1, 2]);
+    assert!(job_queue.queued_jobs().await.unwrap().is_empty());
+    let record = storage.fetch_job(1).await.unwrap();
+    assert_eq!(record.state, "Canceled");
+    let record = storage.fetch_job(0).await.unwrap();
+    assert_eq!(record.state, "Running");
+
+    // Finishing without a result fails the job, once only
+    assert!(!job_queue.finish(0, "other", None).await.unwrap());
+    assert!(job_queue.finish(0, "test", None).await.unwrap());
+    assert!(!job_queue.finish(0, "test", None).await.unwrap());
+    let record = storage.fetch_job(0).await.unwrap();
+    assert_eq!(
+        (record.state.as_str(), record.result.as_str()),
+        ("Finished", "System Error")
+    );
+    assert!(record.cases.iter().all(|case| case.result == "Skipped"));
+}
+
+async fn test_post_jobs_rate_limited(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
+    let _guard = TestDbGuard::new(db_path);
+    let (problems, languages) = create_test_config();
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
+    let rate_limit = RateLimitConfig {
+        per_user: Some(TokenBucketConfig {
+            submissions: 1,
+            window_secs: 60,
+            burst: 0,
+        }),
+        per_ip: None,
+        client_ip_header: None,
+    };
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(storage.clone()))
+            .app_data(web::Data::from(problems))
+            .app_data(web::Data::from(languages))
+            .app_data(web::Data::from(job_queue))
+            .app_data(web::Data::new(false))
+            .app_data(web::Data::new(AdminToken(Some("admin".to_string()))))
+            .app_data(web::Data::new(RateLimiter::new(&rate_limit)))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .service(post_job_handler),
+    )
+    .await;
+
+    let request_body = json!({
+        "source_code": "fn main() {}",
+        "language": "Rust",
+        "user_id": 1,
+        "contest_id": 0,
+        "problem_id": 0
+    });
+    let submit = |admin: bool| {
+        let req = test::TestRequest::post()
+            .uri("/jobs")
+            .set_json(&request_body);
+        match admin {
+            true => req.insert_header(("Authorization", "Bearer admin")),
+            false => req,
+        }
+        .to_request()
+    };
+
+    let resp = test::call_service(&app, submit(false)).await;
+    assert_eq!(resp.status(), 200);
+
+    let resp = test::call_service(&app, submit(false)).await;
+    assert_eq!(resp.status(), 429);
+    let retry_after = resp.headers().get("Retry-After").unwrap().to_str().unwrap();
+    assert_eq!(retry_after, "60");
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["reason"], "ERR_RATE_LIMIT");
+    assert_eq!(body["code"], 4);
+
+    // Admins are exempt
+    let resp = test::call_service(&app, submit(true)).await;
+    assert_eq!(resp.status(), 200);
+
+    // Buckets are kept in the database, so a restarted server still limits the user
+    let restarted = RateLimiter::new(&rate_limit);
+    let checked = restarted.check(storage.as_ref(), 1, None).await.unwrap();
+    assert!(checked.is_err());
+}
+
+async fn test_post_jobs_queue_full_and_blocking_timeout(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
+    let _guard = TestDbGuard::new(db_path);
+    let (problems, languages) = create_test_config();
+    let queue_config = QueueConfig {
+        max_length: Some(1),
+        blocking_timeout_secs: Some(1),
+        ..QUEUE_CONFIG
+    };
+    // No judger runs, so jobs stay queued
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &queue_config));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(storage.clone()))
+            .app_data(web::Data::from(problems))
+            .app_data(web::Data::from(languages))
+            .app_data(web::Data::from(job_queue))
+            .app_data(web::Data::new(true))
+            .app_data(web::Data::new(AdminToken(None)))
+            .app_data(web::Data::new(RateLimiter::default()))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .service(post_job_handler),
+    )
+    .await;
+
+    let request_body = json!({
+        "source_code": "fn main() {}",
+        "language": "Rust",
+        "user_id": 1,
+        "contest_id": 0,
+        "problem_id": 0
+    });
+
+    // The blocking wait gives up and returns the job as it is
+    let req = test::TestRequest::post()
+        .uri("/jobs")
+        .set_json(&request_body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["state"], "Queueing");
+
+    let req = test::TestRequest::post()
+        .uri("/jobs")
+        .set_json(&request_body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["reason"], "ERR_QUEUE_FULL");
+    assert_eq!(body["code"], 8);
+
+    let jobs = storage
+        .fetch_jobs_by_query(&JobsQueryParams::default())
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1, "Rejected jobs should not be created");
+
+    // Concurrent submissions can't overfill the queue either
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &queue_config));
+    job_queue.drain().await.unwrap();
+    let submission: JobSubmission = serde_json::from_value(request_body).unwrap();
+    let mut submits = tokio::task::JoinSet::new();
+    for _ in 0..8 {
+        let (job_queue, submission) = (job_queue.clone(), submission.clone());
+        submits.spawn(async move { job_queue.submit(&submission, 3).await.unwrap() });
+    }
+    let created = submits.join_all().await.into_iter().flatten().count();
+    assert_eq!(created, 1);
+    assert_eq!(job_queue.queued_jobs().await.unwrap().len(), 1);
+}
+
+async fn test_export_and_import_archive(database: Database) {
+    let (source, source_path) = create_test_db(database).await;
+    let _source_guard = TestDbGuard::new(source_path);
+    let (target, target_path) = create_test_db(database).await;
+    let _target_guard = TestDbGuard::new(target_path);
+    let (problems, _) = create_test_config();
+
+    // Job 0 in contest 0 finishes, job 1 in contest 1 is still queueing
+    for (user_id, contest_id) in [(1, 0), (2, 1)] {
+        let submission = JobSubmission {
+            source_code: "fn main() {}".to_string(),
+            language: "Rust".to_string(),
+            user_id,
+            contest_id,
+            problem_id: 0,
+        };
+        source.create_job(&submission, 2).await.unwrap();
+    }
+    let mut finished = source.fetch_job(0).await.unwrap();
+    finished.state = "Finished".to_string();
+    finished.result = "Accepted".to_string();
+    finished.score = 100.0;
+    source.save_result(0, &finished).await.unwrap();
+
+    let filter = ExportFilter {
+        contest_id: Some(1),
+        ..Default::default()
+    };
+    let archive = db::export_archive(source.as_ref(), &problems, &filter)
+        .await
+        .unwrap();
+    assert_eq!(archive.jobs.len(), 1);
+    assert_eq!(archive.contests.len(), 1);
+    assert_eq!(archive.contests[0].user_ids, vec![2]);
+
+    // Both databases have the same user ids, so only the jobs are merged, except those
+    // of user 2, which is somebody else in the target
+    target.update_user(2, "somebody_else").await.unwrap();
+    let archive = db::export_archive(source.as_ref(), &problems, &ExportFilter::default())
+        .await
+        .unwrap();
+    let archive = serde_json::from_value(serde_json::to_value(&archive).unwrap()).unwrap();
+    let summary = db::import_archive(target.as_ref(), archive, ImportMode::Merge)
+        .await
+        .unwrap();
+    assert_eq!((summary.users_imported, summary.users_skipped), (0, 10));
+    assert_eq!((summary.jobs_imported, summary.jobs_skipped), (1, 1));
+
+    let imported = target.fetch_job(0).await.unwrap();
+    assert_eq!(imported.created_time, finished.created_time);
+    assert_eq!(imported.result, "Accepted");
+    assert_eq!(imported.cases.len(), 2);
+    assert!(target.fetch_job(1).await.is_err());
+
+    // Merging again skips everything, a fresh import replaces everything
+    let archive = db::export_archive(source.as_ref(), &problems, &ExportFilter::default())
+        .await
+        .unwrap();
+    let summary = db::import_archive(target.as_ref(), archive, ImportMode::Merge)
+        .await
+        .unwrap();
+    assert_eq!((summary.jobs_imported, summary.jobs_skipped), (0, 2));
+    assert!(target.fetch_job(1).await.is_err());
+
+    target.create_user("extra_user").await.unwrap();
+    let archive = db::export_archive(source.as_ref(), &problems, &ExportFilter::default())
+        .await
+        .unwrap();
+    let summary = db::import_archive(target.as_ref(), archive, ImportMode::Fresh)
+        .await
+        .unwrap();
+    assert_eq!((summary.users_imported, summary.jobs_imported), (10, 2));
+    assert_eq!(target.get_users().await.unwrap().len(), 10);
+    let imported = target.fetch_job(1).await.unwrap();
+    assert_eq!(
+        (imported.state.as_str(), imported.submission.user_id),
+        ("Canceled", 2)
+    );
+    assert!(target.fetch_queued_jobs().await.unwrap().is_empty());
+
+    // New jobs get ids after the imported ones
+    let submission = JobSubmission {
+        source_code: "fn main() {}".to_string(),
+        language: "Rust".to_string(),
+        user_id: 3,
+        contest_id: 0,
+        problem_id: 0,
+    };
+    assert_eq!(target.create_job(&submission, 2).await.unwrap(), 2);
+}
+
+async fn test_judge_node_protocol(database: Database) {
+    let (storage, db_path) = create_test_db(database).await;
+    let _guard = TestDbGuard::new(db_path);
+    let (_, languages) = create_test_config();
+    let job_queue = Arc::new(JobQueue::new(storage.clone(), &QUEUE_CONFIG));
+
+    // A special judge problem whose data and checker program are shipped to nodes
+    let test_id = TEST_DB_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let data_dir = format!("data/test_oj_files_{test_id}");
+    fs::create_dir_all(&data_dir).unwrap();
+    let input_file = format!("{data_dir}/1.in");
+    let answer_file = format!("{data_dir}/1.ans");
+    let checker_file = format!("{data_dir}/checker.sh");
+    fs::write(&input_file, "1 2\n").unwrap();
+    fs::write(&answer_file, "3\n").unwrap();
+    fs::write(&checker_file, "#!/bin/sh\necho Accepted\n").unwrap();
+    let problems = Arc::new(vec![OneProblemConfig {
+        id: 0,
+        name: "aplusb".to_string(),
+        judge_type: JudgeType::Spj,
+        misc: ProblemMisc {
+            special_judge: Some(vec![
+                checker_file.clone(),
+                "%OUTPUT%".to_string(),
+                "%ANSWER%".to_string(),
+            ]),
+            ..Default::default()
+        },
+        cases: vec![OneCaseConfig {
+            score: 100.0,
+            input_file: input_file.clone(),
+            answer_file: answer_file.clone(),
+            time_limit: MicroSecond(1000000),
+            memory_limit: KiloByte(1048576),
+        }],
+    }]);
+    let problem_files = Arc::new(ProblemFiles::build(&problems));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(problems.clone()))
+            .app_data(web::Data::from(languages))
+            .app_data(web::Data::from(job_queue.clone()))
+            .app_data(web::Data::new(JudgeSecret(Some("secret".to_string()))))
+            .app_data(web::Data::from(problem_files.clone()))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .service(post_judge_claim_handler)
+            .service(post_judge_heartbeat_handler)
+            .service(post_judge_case_handler)
+            .service(post_judge_result_handler)
+            .service(get_judge_file_handler),
+    )
+    .await;
+    let node = |node: &str| json!({ "node": node });
+    let post = |uri: &str, body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(body)
+            .to_request()
+    };
+    let get_file = |hash: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/judge/files/{hash}"))
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_request()
+    };
+
+    // Requests without the secret are refused
+    for authorization in [None, Some("Bearer wrong")] {
+        let mut req = test::TestRequest::post()
+            .uri("/judge/claim")
+            .set_json(node("node-1"));
+        if let Some(authorization) = authorization {
+            req = req.insert_header(("Authorization", authorization));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), 403);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            (body["reason"].as_str(), body["code"].as_u64()),
+            (Some("ERR_FORBIDDEN"), Some(7))
+        );
+    }
+
+    let resp = test::call_service(&app, post("/judge/claim", node("node-1"))).await;
+    assert_eq!(resp.status(), 204);
+
+    let submission = JobSubmission {
+        source_code: "fn main() {}".to_string(),
+        language: "Rust".to_string(),
+        user_id: 1,
+        contest_id: 0,
+        problem_id: 0,
+    };
+    let job_id = storage.create_job(&submission, 2).await.unwrap();
+    let priority = JobPriority::of(&submission, false);
+    job_queue.push(job_id, &submission, priority).await.unwrap();
+
+    // The claim comes with the hashes of all files the problem needs
+    let resp = test::call_service(&app, post("/judge/claim", node("node-1"))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["job"]["id"], job_id);
+    let files = body["files"].as_object().unwrap();
+    assert_eq!(files.len(), 3);
+    let input_hash = files[&input_file].as_str().unwrap().to_string();
+    assert_eq!(input_hash, hash_content(b"1 2\n"));
+    assert!(files.contains_key(&checker_file));
+
+    let resp = test::call_service(&app, get_file(&input_hash)).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "1 2\n");
+    let resp = test::call_service(&app, get_file(&hash_content(b"unknown"))).await;
+    assert_eq!(resp.status(), 404);
+
+    // Swapped data is served under its new hash only
+    fs::write(&input_file, "10 20\n").unwrap();
+    let resp = test::call_service(&app, get_file(&input_hash)).await;
+    assert_eq!(resp.status(), 404);
+    let new_hash = problem_files.hashes(&problems[0])[&input_file].clone();
+    assert_eq!(new_hash, hash_content(b"10 20\n"));
+    let resp = test::call_service(&app, get_file(&new_hash)).await;
+    assert_eq!(test::read_body(resp).await, "10 20\n");
+
+    // Only the node holding the lease may report on the job
+    let heartbeat = format!("/judge/jobs/{job_id}/heartbeat");
+    let resp = test::call_service(&app, post(&heartbeat, node("node-1"))).await;
+    assert_eq!(resp.status(), 200);
+    let resp = test::call_service(&app, post(&heartbeat, node("node-2"))).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["reason"], "ERR_INVALID_STATE");
+
+    let case = json!({
+        "node": "node-1",
+        "case": { "id": 1, "result": "Accepted", "time": 1000, "memory": 1024, "score": 100.0, "info": "" }
+    });
+    let resp = test::call_service(&app, post(&format!("/judge/jobs/{job_id}/cases"), case)).await;
+    assert_eq!(resp.status(), 200);
+    let record = storage.fetch_job(job_id).await.unwrap();
+    assert_eq!(
+        (record.state.as_str(), record.cases[1].result.as_str()),
+        ("Running", "Accepted")
+    );
+
+    let mut finished = record;
+    finished.state = "Finished".to_string();
+    finished.result = "Accepted".to_string();
+    finished.score = 100.0;
+    let result = format!("/judge/jobs/{job_id}/result");
+    let body = |node: &str| json!({ "node": node, "result": finished });
+    let resp = test::call_service(&app, post(&result, body("node-2"))).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, post(&result, body("node-1"))).await;
+    assert_eq!(resp.status(), 200);
+    let resp = test::call_service(&app, post(&result, body("node-1"))).await;
+    assert_eq!(resp.status(), 400);
+    let record = storage.fetch_job(job_id).await.unwrap();
+    assert_eq!(
+        (record.state.as_str(), record.result.as_str()),
+        ("Finished", "Accepted")
+    );
+
+    let _ = fs::remove_dir_all(&data_dir);
+}